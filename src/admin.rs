@@ -0,0 +1,184 @@
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::{AppArgs, AppCommand, JwksCommand, SessionBackend, SessionsCommand, StateBackend, StatesCommand};
+use crate::db::{self, RedisPool};
+use crate::handlers::auth::cli_logout::revoke_refresh_token;
+use crate::handlers::auth::utils::CLI_SESSION_TTL;
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::store::{self, SessionStore, StateStore};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Runs an administration command against the configured stores and returns its output.
+///
+/// The stores are opened exactly as the server opens them, so the commands see the same
+/// Redis keys and database rows.
+pub async fn run(command: &AppCommand, args: &AppArgs) -> Result<String, String> {
+    let redis_pool = db::create_pool(&args.redis)
+        .await
+        .map_err(|e| format!("Could not connect to Redis: {}", e))?;
+
+    match command {
+        AppCommand::Sessions(command) => {
+            if args.storage.session_backend == SessionBackend::Memory {
+                return Err(memory_backend_error("session"));
+            }
+            let (_, sessions) = store::create_stores(&args.storage, &redis_pool).await?;
+            match command {
+                SessionsCommand::List { user } => list_sessions(&*sessions, user).await,
+                SessionsCommand::Revoke { user } => {
+                    let audit = crate::build_audit_log(args, &redis_pool);
+                    revoke_session(&*sessions, &audit, args, user).await
+                }
+            }
+        }
+        AppCommand::States(StatesCommand::Purge) => {
+            if args.storage.state_backend == StateBackend::Memory {
+                return Err(memory_backend_error("state"));
+            }
+            let (states, _) = store::create_stores(&args.storage, &redis_pool).await?;
+            purge_states(&*states).await
+        }
+        AppCommand::Jwks(JwksCommand::Refresh) => refresh_jwks(args, redis_pool).await,
+        AppCommand::Stats => {
+            if args.storage.state_backend == StateBackend::Memory {
+                return Err(memory_backend_error("state"));
+            }
+            if args.storage.session_backend == SessionBackend::Memory {
+                return Err(memory_backend_error("session"));
+            }
+            let (states, sessions) = store::create_stores(&args.storage, &redis_pool).await?;
+            stats(&*states, &*sessions).await
+        }
+        AppCommand::Serve | AppCommand::Check => Err("Not an administration command".to_string()),
+    }
+}
+
+/// Describes the current session of a user and, when kept by the backend, its history.
+pub async fn list_sessions<T: SessionStore + ?Sized>(sessions: &T, user: &str) -> Result<String, String> {
+    let mut out = String::new();
+
+    match sessions.get_session(user).await.map_err(|e| e.to_string())? {
+        Some(session) => {
+            let _ = writeln!(out, "User:    {}", session.user_sub);
+            let _ = writeln!(out, "Email:   {}", session.email.as_deref().unwrap_or("-"));
+            let _ = writeln!(out, "Device:  {}", session.device_name.as_deref().unwrap_or("-"));
+            let _ = writeln!(out, "Status:  {}", if session.active { "active" } else { "inactive" });
+        }
+        None => {
+            let _ = writeln!(out, "No session for user {}", user);
+        }
+    }
+
+    let history = sessions.session_history(user).await.map_err(|e| e.to_string())?;
+    if !history.is_empty() {
+        let _ = writeln!(out, "\nHistory ({}):", sessions.backend());
+        for entry in history {
+            let _ = writeln!(
+                out,
+                "  {}  {:<8}  {}  {}",
+                entry.recorded_at,
+                if entry.active { "active" } else { "inactive" },
+                entry.device_name.as_deref().unwrap_or("-"),
+                entry.email.as_deref().unwrap_or("-"),
+            );
+        }
+    }
+
+    Ok(out)
+}
+
+/// Deactivates the session of a user, like a logout from its CLI, and records the revocation.
+///
+/// The refresh token is revoked in Cognito first; a failure there is reported but does not
+/// keep the session active.
+pub async fn revoke_session<T: SessionStore + ?Sized>(
+    sessions: &T,
+    audit: &AuditLog,
+    config: &AppArgs,
+    user: &str,
+) -> Result<String, String> {
+    let Some(mut session) = sessions.get_session(user).await.map_err(|e| e.to_string())? else {
+        return Err(format!("No session for user {}", user));
+    };
+
+    let mut out = String::new();
+    if let Some(refresh_token) = &session.refresh_token
+        && let Err(e) = revoke_refresh_token(refresh_token, config).await
+    {
+        let _ = writeln!(out, "Warning: could not revoke the refresh token in Cognito: {}", e);
+    }
+
+    session.active = false;
+    session.refresh_token = None;
+
+    let event = AuditEvent::success(AuditAction::Revoke)
+        .user(&session.user_sub, session.email.as_deref())
+        .device(session.device_name.as_deref());
+
+    if let Err(e) = sessions.put_session(&session, CLI_SESSION_TTL).await {
+        audit.record(event.failed("Failed to deactivate session")).await;
+        return Err(e.to_string());
+    }
+    audit.record(event).await;
+
+    let _ = writeln!(
+        out,
+        "Revoked the session of {} ({})",
+        user,
+        session.device_name.as_deref().unwrap_or("unknown device")
+    );
+    Ok(out)
+}
+
+/// Removes every login flow in progress.
+pub async fn purge_states<S: StateStore + ?Sized>(states: &S) -> Result<String, String> {
+    let purged = states.purge_states().await.map_err(|e| e.to_string())?;
+    Ok(format!("Purged {} login flow(s) from {}\n", purged, states.backend()))
+}
+
+/// Downloads the JWKS and replaces the copy cached in Redis.
+///
+/// Running servers keep their in-memory copy until it expires, or until a token signed with
+/// a key they do not know makes them load the new one.
+async fn refresh_jwks(args: &AppArgs, redis_pool: RedisPool) -> Result<String, String> {
+    let metrics = Arc::new(Metrics::new().map_err(|e| format!("Could not register metrics: {}", e))?);
+    let verifier = IdTokenVerifier::new(args, redis_pool, metrics);
+    let keys = verifier.refresh().await.map_err(|e| e.to_string())?;
+
+    let kids: Vec<&str> = keys.keys.iter().filter_map(|k| k.common.key_id.as_deref()).collect();
+    Ok(format!(
+        "Fetched {} key(s) from {}: {}\n",
+        kids.len(),
+        args.cognito.jwks_url(),
+        kids.join(", ")
+    ))
+}
+
+/// Counts the login flows in progress and the stored sessions.
+pub async fn stats<S, T>(states: &S, sessions: &T) -> Result<String, String>
+where
+    S: StateStore + ?Sized,
+    T: SessionStore + ?Sized,
+{
+    let flows = states.state_stats().await.map_err(|e| e.to_string())?;
+    let counts = sessions.session_stats().await.map_err(|e| e.to_string())?;
+
+    Ok(format!(
+        "Login flows ({}): {} pending, {} awaiting credentials pickup\nSessions ({}): {} active, {} inactive\n",
+        states.backend(),
+        flows.pending,
+        flows.completed,
+        sessions.backend(),
+        counts.active,
+        counts.inactive
+    ))
+}
+
+/// Error for commands that cannot reach a store kept in the memory of the server process.
+fn memory_backend_error(kind: &str) -> String {
+    format!(
+        "The {} store uses the memory backend, which only exists inside the server process",
+        kind
+    )
+}
@@ -0,0 +1,63 @@
+use crate::audit::AuditLog;
+use crate::config::SharedConfig;
+use crate::db::RedisPool;
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::middleware;
+use crate::pages::Pages;
+use crate::routes;
+use crate::shutdown::Shutdown;
+use crate::store::{SessionStore, StateStore};
+use crate::sts::CredentialCache;
+use crate::telemetry;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, Error};
+use tracing_actix_web::TracingLogger;
+
+/// Data shared by the workers of the application.
+#[derive(Clone)]
+pub struct AppData {
+    pub redis_pool: web::Data<RedisPool>,
+    pub state_store: web::Data<dyn StateStore>,
+    pub session_store: web::Data<dyn SessionStore>,
+    pub config: web::Data<SharedConfig>,
+    pub sts_client: web::Data<aws_sdk_sts::Client>,
+    pub credential_cache: web::Data<CredentialCache>,
+    pub pages: web::Data<Pages>,
+    pub audit_log: web::Data<AuditLog>,
+    pub metrics: web::Data<Metrics>,
+    pub id_token_verifier: web::Data<IdTokenVerifier>,
+    pub shutdown: web::Data<Shutdown>,
+}
+
+/// Builds the application run by each worker: its shared data, middleware and routes.
+pub fn app(
+    data: AppData,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(data.redis_pool)
+        .app_data(data.state_store)
+        .app_data(data.session_store)
+        .app_data(data.config)
+        .app_data(data.sts_client)
+        .app_data(data.credential_cache)
+        .app_data(data.pages)
+        .app_data(data.audit_log)
+        .app_data(data.metrics)
+        .app_data(data.id_token_verifier)
+        .app_data(data.shutdown)
+        .wrap(from_fn(middleware::shutdown::track_in_flight))
+        .wrap(from_fn(middleware::metrics::track_requests))
+        .wrap(TracingLogger::<telemetry::RequestSpan>::new())
+        .configure(routes::config::<dyn StateStore, dyn SessionStore>)
+}
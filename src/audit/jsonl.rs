@@ -0,0 +1,37 @@
+use crate::audit::{AuditEvent, AuditSink};
+use async_trait::async_trait;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Audit sink appending events as JSON lines to a local file.
+pub struct JsonLinesSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesSink {
+    /// Opens (or creates) the file at `path` in append mode.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for JsonLinesSink {
+    fn name(&self) -> &'static str {
+        "json lines file"
+    }
+
+    async fn write(&self, event: &AuditEvent) -> Result<(), String> {
+        let mut line = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+
+        // Events are small, a single blocking append keeps lines from interleaving
+        let mut file = self.file.lock().map_err(|e| e.to_string())?;
+        file.write_all(&line).map_err(|e| e.to_string())
+    }
+}
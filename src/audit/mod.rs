@@ -0,0 +1,170 @@
+pub mod jsonl;
+pub mod redis_stream;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use jsonl::JsonLinesSink;
+pub use redis_stream::RedisStreamSink;
+
+/// Authentication activity recorded in the audit log.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// A CLI started a new authentication request.
+    Start,
+    /// The identity provider redirected the user back to the callback.
+    Callback,
+    /// The user confirmed, or rejected, the device that started a login.
+    Confirm,
+    /// A polling CLI received AWS credentials.
+    StatusAuthorized,
+    /// A CLI renewed its credentials with a refresh token.
+    Renew,
+    /// A CLI was refused credentials because its session is no longer active.
+    Deny,
+    /// A CLI ended its session.
+    Logout,
+    /// A CLI obtained a token for the container credentials endpoint.
+    ContainerToken,
+    /// A local tool fetched credentials from the container credentials endpoint.
+    ContainerCredentials,
+    /// An operator revoked a session with the `sessions revoke` command.
+    Revoke,
+}
+
+/// Result of an audited activity.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// Typed audit event describing a single authentication activity.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    /// Unix timestamp (seconds) at which the event occurred.
+    pub timestamp: i64,
+    /// Cognito subject of the user, when known.
+    pub user_sub: Option<String>,
+    pub email: Option<String>,
+    pub device_name: Option<String>,
+    /// Client IP address as seen through the ingress.
+    pub ip: Option<String>,
+    /// IAM role assumed on behalf of the user.
+    pub role_arn: Option<String>,
+    /// Authentication state the event belongs to.
+    pub state: Option<String>,
+    /// Short description of why the activity failed.
+    pub reason: Option<String>,
+}
+
+impl AuditEvent {
+    /// Creates an event for the given action and outcome, timestamped now.
+    pub fn new(action: AuditAction, outcome: AuditOutcome) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_else(|_| 0);
+
+        Self {
+            action,
+            outcome,
+            timestamp,
+            user_sub: None,
+            email: None,
+            device_name: None,
+            ip: None,
+            role_arn: None,
+            state: None,
+            reason: None,
+        }
+    }
+
+    /// Creates a successful event for the given action.
+    pub fn success(action: AuditAction) -> Self {
+        Self::new(action, AuditOutcome::Success)
+    }
+
+    /// Creates a failed event for the given action with the reason of the failure.
+    pub fn failure(action: AuditAction, reason: impl Into<String>) -> Self {
+        Self::new(action, AuditOutcome::Success).failed(reason)
+    }
+
+    /// Turns the event into a failure with the given reason, keeping its context.
+    pub fn failed(mut self, reason: impl Into<String>) -> Self {
+        self.outcome = AuditOutcome::Failure;
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// Sets the user identity of the event.
+    pub fn user(mut self, user_sub: &str, email: Option<&str>) -> Self {
+        self.user_sub = Some(user_sub.to_string());
+        self.email = email.map(str::to_string);
+        self
+    }
+
+    /// Sets the device name of the event.
+    pub fn device(mut self, device_name: Option<&str>) -> Self {
+        self.device_name = device_name.map(str::to_string);
+        self
+    }
+
+    /// Sets the client IP address of the event.
+    pub fn ip(mut self, ip: Option<&str>) -> Self {
+        self.ip = ip.map(str::to_string);
+        self
+    }
+
+    /// Sets the IAM role of the event.
+    pub fn role_arn(mut self, role_arn: &str) -> Self {
+        self.role_arn = Some(role_arn.to_string());
+        self
+    }
+
+    /// Sets the authentication state of the event.
+    pub fn state(mut self, state: &str) -> Self {
+        self.state = Some(state.to_string());
+        self
+    }
+}
+
+/// Destination to which audit events are written.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Human-readable name of the sink, used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Persists a single audit event.
+    async fn write(&self, event: &AuditEvent) -> Result<(), String>;
+}
+
+/// Audit log dispatching events to every configured sink.
+///
+/// Failing to write an event is logged but never fails the request being audited.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    sinks: Vec<Arc<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    /// Creates an audit log writing to the given sinks.
+    pub fn new(sinks: Vec<Arc<dyn AuditSink>>) -> Self {
+        Self { sinks }
+    }
+
+    /// Writes an event to all sinks.
+    pub async fn record(&self, event: AuditEvent) {
+        for sink in &self.sinks {
+            if let Err(e) = sink.write(&event).await {
+                log::error!("Failed to write audit event to {}: {}", sink.name(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::audit::{AuditEvent, AuditSink};
+use crate::db::RedisPool;
+use async_trait::async_trait;
+
+/// Audit sink appending events to a Redis Stream.
+///
+/// Each entry carries the action and the full event serialized as JSON. The stream is
+/// trimmed approximately to `max_len` entries.
+pub struct RedisStreamSink {
+    pool: RedisPool,
+    stream: String,
+    max_len: usize,
+}
+
+impl RedisStreamSink {
+    /// Creates a sink writing to the given stream key.
+    pub fn new(pool: RedisPool, stream: String, max_len: usize) -> Self {
+        Self { pool, stream, max_len }
+    }
+}
+
+#[async_trait]
+impl AuditSink for RedisStreamSink {
+    fn name(&self) -> &'static str {
+        "redis stream"
+    }
+
+    async fn write(&self, event: &AuditEvent) -> Result<(), String> {
+        let payload = serde_json::to_string(event).map_err(|e| e.to_string())?;
+        let action = serde_json::to_value(event.action).map_err(|e| e.to_string())?;
+
+        let mut conn = self.pool.get().await.map_err(|e| e.to_string())?;
+
+        // XADD <stream> MAXLEN ~ <max_len> * action <action> event <json>
+        let _: String = redis::cmd("XADD")
+            .arg(&self.stream)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(self.max_len)
+            .arg("*")
+            .arg("action")
+            .arg(action.as_str().unwrap_or_default())
+            .arg("event")
+            .arg(payload)
+            .query_async(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Session with one authentication service, kept between runs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedSession {
+    pub refresh_token: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    /// Unix timestamp (seconds) at which the credentials expire.
+    pub expires_at: i64,
+}
+
+impl CachedSession {
+    /// Whether the credentials expire within `margin` seconds.
+    pub fn needs_renewal(&self, margin: i64) -> bool {
+        self.expires_at - margin <= unix_now()
+    }
+}
+
+/// JSON file holding the cached sessions, indexed by service URL.
+///
+/// It contains refresh tokens and credentials, so it is only readable by its owner.
+pub struct SessionCache {
+    path: PathBuf,
+}
+
+impl SessionCache {
+    /// Opens the cache at `path`, or at `mega-auth/sessions.json` in the OS config directory.
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path,
+            None => dirs::config_dir()
+                .ok_or("could not find the configuration directory, set --cache-file")?
+                .join("mega-auth")
+                .join("sessions.json"),
+        };
+        Ok(Self { path })
+    }
+
+    /// Loads the session with a service.
+    pub fn load(&self, url: &str) -> Result<Option<CachedSession>, String> {
+        Ok(self.read_all()?.remove(url))
+    }
+
+    /// Stores the session with a service, replacing the previous one.
+    pub fn save(&self, url: &str, session: &CachedSession) -> Result<(), String> {
+        let mut sessions = self.read_all()?;
+        sessions.insert(url.to_string(), session.clone());
+        self.write_all(&sessions)
+    }
+
+    /// Forgets the session with a service.
+    pub fn remove(&self, url: &str) -> Result<(), String> {
+        let mut sessions = self.read_all()?;
+        if sessions.remove(url).is_some() {
+            self.write_all(&sessions)?;
+        }
+        Ok(())
+    }
+
+    fn read_all(&self) -> Result<BTreeMap<String, CachedSession>, String> {
+        match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("could not read {}: {}", self.path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(format!("could not read {}: {}", self.path.display(), e)),
+        }
+    }
+
+    /// Writes the sessions to a temporary file renamed over the cache, so that readers never
+    /// see a partial file.
+    fn write_all(&self, sessions: &BTreeMap<String, CachedSession>) -> Result<(), String> {
+        let error = |e: std::io::Error| format!("could not write {}: {}", self.path.display(), e);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+
+        let tmp = self.path.with_extension("json.tmp");
+        let json = serde_json::to_vec_pretty(sessions).map_err(|e| e.to_string())?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&tmp).and_then(|mut f| f.write_all(&json)).map_err(error)?;
+        fs::rename(&tmp, &self.path).map_err(error)
+    }
+}
+
+/// Returns the current Unix timestamp in seconds.
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_else(|_| 0)
+}
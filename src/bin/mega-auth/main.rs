@@ -0,0 +1,216 @@
+//! `mega-auth`: command line client of the Mega Uploader authentication service.
+//!
+//! It runs the browser login, caches the session in the OS configuration directory and
+//! renews the credentials shortly before they expire. The `credentials` command prints them
+//! in the format of the AWS `credential_process` setting, so it can be plugged into any
+//! AWS profile.
+
+mod cache;
+
+use crate::cache::{CachedSession, SessionCache};
+use clap::{Parser, Subcommand, ValueEnum};
+use mega_uploader_auth::client::{AuthClient, ClientError, Credentials};
+use mega_uploader_auth::format::{env_exports, iso_8601};
+use mega_uploader_auth::schemas::auth::{CliAuthStartRequest, CredentialProcessOutput};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Command line client of the Mega Uploader authentication service.
+#[derive(Parser)]
+#[command(name = "mega-auth", version)]
+struct Cli {
+    /// Base URL of the authentication service.
+    #[arg(long, env = "MEGA_AUTH_URL")]
+    url: String,
+
+    /// File caching the sessions, `mega-auth/sessions.json` in the OS config directory by default.
+    #[arg(long, env = "MEGA_AUTH_CACHE_FILE")]
+    cache_file: Option<PathBuf>,
+
+    /// Seconds before expiry at which cached credentials are renewed.
+    #[arg(long, env = "MEGA_AUTH_RENEW_BEFORE", default_value_t = 300)]
+    renew_before: i64,
+
+    /// Device name shown to the user and recorded in the audit log, the host name by default.
+    #[arg(long, env = "MEGA_AUTH_DEVICE_NAME")]
+    device_name: Option<String>,
+
+    /// Print the login URL instead of opening it in a browser.
+    #[arg(long)]
+    no_browser: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Log in through the browser and cache the session.
+    Login,
+    /// Print AWS credentials, renewing them or logging in when needed.
+    Credentials {
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = OutputFormat::CredentialProcess)]
+        format: OutputFormat,
+    },
+    /// End the session and remove it from the cache.
+    Logout,
+}
+
+/// Formats of the `credentials` command.
+#[derive(ValueEnum, Clone, Copy)]
+enum OutputFormat {
+    /// JSON read by the AWS SDKs from a `credential_process` command.
+    CredentialProcess,
+    /// `export` statements for a POSIX shell.
+    Env,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mega-auth: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: &Cli) -> Result<(), String> {
+    let client = AuthClient::new(&cli.url).with_cli_version(env!("CARGO_PKG_VERSION"));
+    let cache = SessionCache::open(cli.cache_file.clone())?;
+
+    match cli.command {
+        Command::Login => {
+            let session = login(cli, &client).await?;
+            cache.save(&cli.url, &session)?;
+            eprintln!("Logged in, credentials valid until {}.", iso_8601(session.expires_at));
+        }
+        Command::Credentials { format } => {
+            let session = current_session(cli, &client, &cache).await?;
+            print_credentials(&session, format);
+        }
+        Command::Logout => {
+            if let Some(session) = cache.load(&cli.url)? {
+                match client.logout(&session.refresh_token).await {
+                    // The session already ended on the service side
+                    Ok(()) | Err(ClientError::Rejected(_)) => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+                cache.remove(&cli.url)?;
+            }
+            eprintln!("Logged out.");
+        }
+    }
+
+    if let Some(warning) = client.deprecation_warning() {
+        eprintln!("mega-auth: {}", warning);
+    }
+    Ok(())
+}
+
+/// Returns the cached session, renewed if its credentials are about to expire, or a new one
+/// from a browser login when there is no usable session.
+async fn current_session(cli: &Cli, client: &AuthClient, cache: &SessionCache) -> Result<CachedSession, String> {
+    let session = match cache.load(&cli.url)? {
+        Some(session) if !session.needs_renewal(cli.renew_before) => return Ok(session),
+        Some(session) => match client.renew(&session.refresh_token).await {
+            Ok(credentials) => session_from(credentials, Some(&session.refresh_token))?,
+            Err(e) if e.is_session_ended() => {
+                eprintln!("The session has ended, logging in again.");
+                login(cli, client).await?
+            }
+            Err(e) => return Err(e.to_string()),
+        },
+        None => login(cli, client).await?,
+    };
+    cache.save(&cli.url, &session)?;
+    Ok(session)
+}
+
+/// Runs the browser login: starts a request, opens the authorization URL and waits until
+/// the user has logged in.
+async fn login(cli: &Cli, client: &AuthClient) -> Result<CachedSession, String> {
+    let request = CliAuthStartRequest {
+        device_name: cli.device_name.clone().or_else(host_name),
+        os: Some(std::env::consts::OS.to_string()),
+        cli_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+    };
+    let login = client.start(&request).await.map_err(|e| e.to_string())?;
+
+    eprintln!("Log in to Mega Uploader at:\n\n    {}\n", login.auth_url);
+    eprintln!(
+        "Confirm the login in the browser only if it shows the code {}.\n",
+        login.verification_code
+    );
+    if !cli.no_browser && open::that(&login.auth_url).is_err() {
+        eprintln!("Could not open a browser, open the URL above manually.");
+    }
+
+    let credentials = client.wait_for_authorization(&login).await.map_err(|e| e.to_string())?;
+    session_from(credentials, None)
+}
+
+/// Builds the cached session from new credentials, keeping `previous_refresh_token` unless
+/// the service issued a new one.
+fn session_from(credentials: Credentials, previous_refresh_token: Option<&str>) -> Result<CachedSession, String> {
+    let refresh_token = credentials
+        .refresh_token
+        .or_else(|| previous_refresh_token.map(str::to_string))
+        .ok_or("no refresh token was issued")?;
+
+    Ok(CachedSession {
+        refresh_token,
+        access_key_id: credentials.access_key_id,
+        secret_access_key: credentials.secret_access_key,
+        session_token: credentials.session_token,
+        expires_at: credentials.expires_at,
+    })
+}
+
+/// Writes the credentials of a session to stdout.
+fn print_credentials(session: &CachedSession, format: OutputFormat) {
+    match format {
+        OutputFormat::CredentialProcess => {
+            let output = CredentialProcessOutput {
+                version: 1,
+                access_key_id: session.access_key_id.clone(),
+                secret_access_key: session.secret_access_key.clone(),
+                session_token: session.session_token.clone(),
+                expiration: iso_8601(session.expires_at),
+                // The session, with its refresh token, stays in the local cache
+                refresh_token: None,
+            };
+            println!("{}", serde_json::to_string(&output).unwrap_or_default());
+        }
+        OutputFormat::Env => print!(
+            "{}",
+            env_exports(
+                &session.access_key_id,
+                &session.secret_access_key,
+                &session.session_token,
+                session.expires_at
+            )
+        ),
+    }
+}
+
+/// Host name of the machine, as given by the operating system.
+#[cfg(unix)]
+fn host_name() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its whole length, which is passed along
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok().filter(|name| !name.is_empty())
+}
+
+/// Host name of the machine, which Windows always sets in the environment.
+#[cfg(not(unix))]
+fn host_name() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok().filter(|name| !name.is_empty())
+}
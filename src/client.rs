@@ -0,0 +1,286 @@
+use crate::schemas::auth::{
+    CliAuthResponse, CliAuthStartRequest, CliAuthStartResponse, CliLogoutRequest, CliRenewRequest,
+    CLI_DEPRECATION_HEADER, CLI_VERSION_HEADER,
+};
+use crate::schemas::error::ProblemDetails;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest wait between two status polls while the service is unavailable, in seconds.
+const MAX_BACKOFF_SECS: u64 = 30;
+
+/// Failure of a call to the authentication service.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The service refused the request.
+    #[error("{} ({})", .0.detail.as_deref().unwrap_or(&.0.title), .0.code)]
+    Rejected(Box<ProblemDetails>),
+
+    /// The service no longer supports this version of the CLI.
+    #[error(
+        "This CLI version is no longer supported, upgrade to {minimum_version} or later{}",
+        .download_url.as_deref().map(|url| format!(" from {}", url)).unwrap_or_default()
+    )]
+    UpgradeRequired {
+        minimum_version: String,
+        download_url: Option<String>,
+    },
+
+    /// The client exceeded a rate limit and must wait the given seconds.
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+
+    /// The service could not be reached or failed on its side.
+    #[error("Authentication service unavailable: {0}")]
+    Unavailable(String),
+
+    /// The login request or the session has expired.
+    #[error("The login request or session has expired")]
+    Expired,
+
+    /// The login was denied or the session is no longer active.
+    #[error("Access was denied")]
+    Denied,
+
+    /// The service answered something this client does not understand.
+    #[error("Unexpected response: {0}")]
+    UnexpectedResponse(String),
+}
+
+impl ClientError {
+    /// Whether the session behind a refresh token is over, so that a new login is needed.
+    pub fn is_session_ended(&self) -> bool {
+        match self {
+            ClientError::Expired | ClientError::Denied => true,
+            ClientError::Rejected(problem) => problem.status == StatusCode::UNAUTHORIZED.as_u16(),
+            _ => false,
+        }
+    }
+}
+
+/// Login request started with [`AuthClient::start`], waiting for the user to log in.
+#[derive(Debug, Clone)]
+pub struct LoginRequest {
+    /// State identifying the request.
+    pub state: String,
+    /// URL the user must open in their browser.
+    pub auth_url: String,
+    /// Seconds until the request expires.
+    pub expires_in: u64,
+    /// Minimum number of seconds between two status polls.
+    pub interval: u64,
+    /// Code to display, which the browser shows again before the user confirms the login.
+    pub verification_code: String,
+}
+
+/// AWS credentials handed out by the service.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    /// Unix timestamp (seconds) at which the credentials expire.
+    pub expires_at: i64,
+    /// Refresh token of the session, when the service issued a new one.
+    pub refresh_token: Option<String>,
+}
+
+/// Async client of the CLI authentication flow.
+#[derive(Clone)]
+pub struct AuthClient {
+    http: reqwest::Client,
+    base_url: String,
+    cli_version: Option<String>,
+    deprecation: Arc<Mutex<Option<String>>>,
+}
+
+impl AuthClient {
+    /// Creates a client of the service at `base_url`.
+    pub fn new(base_url: &str) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Creates a client sending its requests through `http`.
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cli_version: None,
+            deprecation: Arc::default(),
+        }
+    }
+
+    /// Reports `version` as the CLI version in every request, so that the service can turn
+    /// away or warn outdated clients.
+    pub fn with_cli_version(mut self, version: &str) -> Self {
+        self.cli_version = Some(version.to_string());
+        self
+    }
+
+    /// Deprecation warning sent by the service in its last answer, if the CLI version is deprecated.
+    pub fn deprecation_warning(&self) -> Option<String> {
+        self.deprecation.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Starts a login (`POST /auth/cli/start`).
+    pub async fn start(&self, request: &CliAuthStartRequest) -> Result<LoginRequest, ClientError> {
+        let res = self.request(self.http.post(self.url("/auth/cli/start")).json(request)).await;
+        let start: CliAuthStartResponse = self.read_json(res).await?;
+
+        let state = reqwest::Url::parse(&start.auth_url)
+            .ok()
+            .and_then(|url| url.query_pairs().find(|(k, _)| k == "state").map(|(_, v)| v.into_owned()))
+            .ok_or_else(|| ClientError::UnexpectedResponse("the authorization URL has no state".to_string()))?;
+
+        Ok(LoginRequest {
+            state,
+            auth_url: start.auth_url,
+            expires_in: start.expires_in,
+            interval: start.interval,
+            verification_code: start.verification_code,
+        })
+    }
+
+    /// Checks the status of a login once (`GET /auth/cli/status`).
+    pub async fn status(&self, state: &str) -> Result<CliAuthResponse, ClientError> {
+        let path = format!("/auth/cli/status?state={}", urlencoding::encode(state));
+        let res = self.request(self.http.get(self.url(&path))).await;
+        self.read_json(res).await
+    }
+
+    /// Polls the status of a login until the user has logged in, and returns the credentials.
+    ///
+    /// Polls are spaced by the interval of the request, slowed down on `SLOW_DOWN` and rate
+    /// limits, and backed off exponentially while the service is unavailable.
+    pub async fn wait_for_authorization(&self, login: &LoginRequest) -> Result<Credentials, ClientError> {
+        let deadline = Instant::now() + Duration::from_secs(login.expires_in);
+        let mut interval = login.interval.max(1);
+        let mut wait = interval;
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+            if Instant::now() >= deadline {
+                return Err(ClientError::Expired);
+            }
+            wait = interval;
+
+            match self.status(&login.state).await {
+                Ok(CliAuthResponse::PENDING) => {}
+                Ok(CliAuthResponse::SLOW_DOWN { interval: slower }) => {
+                    interval = slower.max(interval);
+                    wait = interval;
+                }
+                Ok(status) => return credentials(status),
+                Err(ClientError::RateLimited(retry_after)) => wait = retry_after.max(interval),
+                Err(ClientError::Unavailable(e)) => {
+                    log::warn!("Authentication service unavailable, retrying: {}", e);
+                    wait = (wait * 2).min(MAX_BACKOFF_SECS);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Renews the credentials of a session with its refresh token (`POST /auth/cli/renew`).
+    pub async fn renew(&self, refresh_token: &str) -> Result<Credentials, ClientError> {
+        let request = CliRenewRequest {
+            refresh_token: refresh_token.to_string(),
+        };
+        let res = self.request(self.http.post(self.url("/auth/cli/renew")).json(&request)).await;
+        credentials(self.read_json(res).await?)
+    }
+
+    /// Ends a session (`POST /auth/cli/logout`).
+    pub async fn logout(&self, refresh_token: &str) -> Result<(), ClientError> {
+        let request = CliLogoutRequest {
+            refresh_token: refresh_token.to_string(),
+        };
+        let res = self.request(self.http.post(self.url("/auth/cli/logout")).json(&request)).await;
+        self.check(res).await.map(|_| ())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Sends a request, reporting the CLI version.
+    async fn request(&self, builder: RequestBuilder) -> reqwest::Result<Response> {
+        match &self.cli_version {
+            Some(version) => builder.header(CLI_VERSION_HEADER, version).send().await,
+            None => builder.send().await,
+        }
+    }
+
+    /// Checks the status of a response and decodes its JSON body.
+    async fn read_json<T: DeserializeOwned>(&self, res: reqwest::Result<Response>) -> Result<T, ClientError> {
+        self.check(res)
+            .await?
+            .json()
+            .await
+            .map_err(|e| ClientError::UnexpectedResponse(e.to_string()))
+    }
+
+    /// Records the deprecation warning of a response, then checks its status.
+    async fn check(&self, res: reqwest::Result<Response>) -> Result<Response, ClientError> {
+        if let Ok(res) = &res {
+            let warning = res.headers().get(CLI_DEPRECATION_HEADER).and_then(|v| v.to_str().ok());
+            *self.deprecation.lock().unwrap_or_else(|e| e.into_inner()) = warning.map(str::to_string);
+        }
+        check(res).await
+    }
+}
+
+/// Extracts the credentials of an `AUTHORIZED` status, or the error matching any other one.
+fn credentials(status: CliAuthResponse) -> Result<Credentials, ClientError> {
+    match status {
+        CliAuthResponse::AUTHORIZED {
+            access_key_id,
+            secret_access_key,
+            session_token,
+            expires_at,
+            refresh_token,
+        } => Ok(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token,
+            expires_at,
+            refresh_token,
+        }),
+        CliAuthResponse::EXPIRED => Err(ClientError::Expired),
+        CliAuthResponse::DENIED => Err(ClientError::Denied),
+        other => Err(ClientError::UnexpectedResponse(format!("status {}", other.status_name()))),
+    }
+}
+
+/// Turns transport failures and error statuses into a [`ClientError`].
+async fn check(res: reqwest::Result<Response>) -> Result<Response, ClientError> {
+    let res = res.map_err(|e| ClientError::Unavailable(e.to_string()))?;
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+        return Err(ClientError::RateLimited(retry_after));
+    }
+
+    match res.json::<ProblemDetails>().await {
+        Ok(problem) if status.is_server_error() => Err(ClientError::Unavailable(problem.title)),
+        Ok(problem) if problem.code == "upgrade_required" => Err(ClientError::UpgradeRequired {
+            minimum_version: problem.minimum_version.unwrap_or_default(),
+            download_url: problem.download_url,
+        }),
+        Ok(problem) => Err(ClientError::Rejected(Box::new(problem))),
+        Err(_) if status.is_server_error() => Err(ClientError::Unavailable(status.to_string())),
+        Err(_) => Err(ClientError::UnexpectedResponse(status.to_string())),
+    }
+}
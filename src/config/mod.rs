@@ -0,0 +1,491 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::path::PathBuf;
+
+mod file;
+mod reload;
+mod validate;
+
+pub use reload::{spawn_reloader, SharedConfig};
+pub use validate::ConfigError;
+
+/// Main application configuration structure.
+///
+/// This structure holds all the configuration parameters required by the application,
+/// gathered from an optional config file, environment variables and command-line arguments,
+/// in increasing order of precedence. Use [`AppArgs::load`] to build it.
+#[derive(Parser, Debug, Clone, Serialize)]
+#[command(author, version, about, long_about = None)]
+pub struct AppArgs {
+    /// Optional TOML or YAML config file. Its sections match the flattened settings below.
+    #[arg(long, env = "CONFIG_FILE")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration, with secrets redacted, and exit.
+    #[arg(long)]
+    #[serde(skip)]
+    pub print_config: bool,
+
+    /// Command to run; the server is started when none is given.
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<AppCommand>,
+
+    /// Redis connection settings.
+    #[command(flatten)]
+    pub redis: RedisConfig,
+
+    /// Storage backends of login flows and sessions.
+    #[command(flatten)]
+    pub storage: StorageConfig,
+
+    /// HTTP server settings.
+    #[command(flatten)]
+    pub server: ServerConfig,
+
+    /// AWS Cognito authentication settings.
+    #[command(flatten)]
+    pub cognito: CognitoConfig,
+
+    /// AWS STS (Security Token Service) settings.
+    #[command(flatten)]
+    pub sts: StsConfig,
+
+    /// Rate limiting settings for the authentication endpoints.
+    #[command(flatten)]
+    pub rate_limit: RateLimitConfig,
+
+    /// Audit log settings.
+    #[command(flatten)]
+    pub audit: AuditConfig,
+
+    /// Tracing and OpenTelemetry export settings.
+    #[command(flatten)]
+    pub telemetry: TelemetryConfig,
+
+    /// CLI versions accepted by the service.
+    #[command(flatten)]
+    pub cli: CliVersionConfig,
+
+    /// Browser pages shown at the end of a login.
+    #[command(flatten)]
+    pub pages: PagesConfig,
+
+    /// Graceful shutdown settings.
+    #[command(flatten)]
+    pub shutdown: ShutdownConfig,
+}
+
+/// Commands supported by the binary.
+#[derive(Subcommand, Debug, Clone)]
+pub enum AppCommand {
+    /// Run the HTTP server (the default).
+    Serve,
+    /// Validate the configuration, including JWKS reachability, and exit.
+    Check,
+    /// Inspect and revoke CLI sessions.
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+    /// Manage the login flows in progress.
+    #[command(subcommand)]
+    States(StatesCommand),
+    /// Manage the cached Cognito JWKS.
+    #[command(subcommand)]
+    Jwks(JwksCommand),
+    /// Print the number of login flows in progress and of stored sessions.
+    Stats,
+}
+
+/// `sessions` administration commands.
+#[derive(Subcommand, Debug, Clone)]
+pub enum SessionsCommand {
+    /// Show the session of a user, with its history when the backend keeps one.
+    List {
+        /// Cognito subject (`sub`) of the user.
+        #[arg(long)]
+        user: String,
+    },
+    /// Deactivate the session of a user and revoke its refresh token in Cognito.
+    Revoke {
+        /// Cognito subject (`sub`) of the user.
+        #[arg(long)]
+        user: String,
+    },
+}
+
+/// `states` administration commands.
+#[derive(Subcommand, Debug, Clone)]
+pub enum StatesCommand {
+    /// Remove every login flow in progress. Waiting CLIs see their login as expired.
+    Purge,
+}
+
+/// `jwks` administration commands.
+#[derive(Subcommand, Debug, Clone)]
+pub enum JwksCommand {
+    /// Download the JWKS from Cognito and replace the copy cached in Redis.
+    Refresh,
+}
+
+/// Redis deployment types supported by the connection pool.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisMode {
+    /// A single Redis server given by `url`.
+    Standalone,
+    /// A master discovered through the Sentinels given by `nodes`, followed across failovers.
+    Sentinel,
+    /// A Redis Cluster reached through the seed nodes given by `nodes`.
+    Cluster,
+}
+
+/// Redis configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "redis")]
+pub struct RedisConfig {
+    /// Redis deployment type.
+    #[arg(long = "redis-mode", env = "REDIS_MODE", value_enum, default_value = "standalone")]
+    pub mode: RedisMode,
+
+    /// Redis connection URL (e.g., redis://127.0.0.1:6379). Redis 7.0 or later is required.
+    /// In sentinel mode, only its credentials and database are used, for the connections to
+    /// the master.
+    #[arg(
+        short,
+        long,
+        env = "REDIS_URL",
+        default_value = "redis://127.0.0.1:6379"
+    )]
+    pub url: String,
+
+    /// Comma-separated Sentinel URLs (sentinel mode) or cluster seed node URLs (cluster mode).
+    #[arg(long = "redis-nodes", env = "REDIS_NODES", value_delimiter = ',')]
+    pub nodes: Vec<String>,
+
+    /// Name of the master monitored by the Sentinels.
+    #[arg(long = "redis-sentinel-master", env = "REDIS_SENTINEL_MASTER", default_value = "mymaster")]
+    pub sentinel_master: String,
+
+    /// Milliseconds to wait for a pooled connection before reporting the storage unavailable.
+    #[arg(long = "redis-connect-timeout", env = "REDIS_CONNECT_TIMEOUT_MS", default_value_t = 2000)]
+    pub connect_timeout_ms: u64,
+}
+
+/// Backends available for login flow states.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    /// Redis, shared by every replica.
+    Redis,
+    /// Process memory, for development and tests.
+    Memory,
+}
+
+/// Backends available for CLI sessions.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBackend {
+    /// Redis, shared by every replica.
+    Redis,
+    /// Process memory, for development and tests.
+    Memory,
+    /// SQLite or Postgres given by `database_url`, keeping a history of sessions.
+    Sql,
+}
+
+/// Storage backend configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "storage")]
+pub struct StorageConfig {
+    /// Backend of login flow states, status poll throttling and rate limiting counters.
+    #[arg(long = "state-backend", env = "STATE_BACKEND", value_enum, default_value = "redis")]
+    pub state_backend: StateBackend,
+
+    /// Backend of CLI sessions.
+    #[arg(long = "session-backend", env = "SESSION_BACKEND", value_enum, default_value = "redis")]
+    pub session_backend: SessionBackend,
+
+    /// Database URL of the SQL session backend (e.g., sqlite://sessions.db?mode=rwc or postgres://host/db).
+    #[arg(long = "database-url", env = "DATABASE_URL")]
+    pub database_url: Option<String>,
+}
+
+/// HTTP server configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "server")]
+pub struct ServerConfig {
+    /// HTTP server address and port (e.g., 127.0.0.1:8080).
+    #[arg(short, long, env = "SERVER_ADDR", default_value = "127.0.0.1:8080")]
+    pub addr: String,
+
+    /// PEM certificate chain file. Serves HTTPS on `addr` when set together with `tls_key`.
+    #[arg(long = "tls-cert", env = "TLS_CERT_FILE")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key file of the TLS certificate.
+    #[arg(long = "tls-key", env = "TLS_KEY_FILE")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Minimum TLS protocol version accepted by the HTTPS listener.
+    #[arg(long = "tls-min-version", env = "TLS_MIN_VERSION", value_enum, default_value = "1.2")]
+    pub tls_min_version: TlsVersion,
+
+    /// Optional plain HTTP address (e.g., 0.0.0.0:80) that only redirects to HTTPS. Requires TLS.
+    #[arg(long = "http-redirect-addr", env = "HTTP_REDIRECT_ADDR")]
+    pub http_redirect_addr: Option<String>,
+
+    /// Comma-separated CIDRs of the reverse proxies (e.g., 10.0.0.0/8) whose Forwarded and
+    /// X-Forwarded-For headers give the client IP. Without them, the peer address is used.
+    #[arg(long = "trusted-proxies", env = "TRUSTED_PROXIES", value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// TLS protocol versions supported by the HTTPS listener.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TlsVersion {
+    /// TLS 1.2 and later.
+    #[value(name = "1.2")]
+    #[serde(rename = "1.2")]
+    Tls12,
+    /// TLS 1.3 only.
+    #[value(name = "1.3")]
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// AWS Cognito configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "cognito")]
+pub struct CognitoConfig {
+    /// Cognito domain URL.
+    #[arg(long, env = "COGNITO_DOMAIN")]
+    pub domain: String,
+
+    /// Cognito client ID.
+    #[arg(long, env = "COGNITO_CLIENT_ID")]
+    pub client_id: String,
+
+    /// OAuth redirect URI.
+    #[arg(long, env = "COGNITO_REDIRECT_URI")]
+    pub redirect_uri: String,
+
+    /// Cognito User Pool ID (e.g., us-east-1_XXXXXXXXX).
+    #[arg(long, env = "COGNITO_USER_POOL_ID")]
+    pub user_pool_id: String,
+
+    /// Cognito Region (e.g., us-east-1).
+    #[arg(long, env = "COGNITO_REGION")]
+    pub region: String,
+
+    /// Issuer of the ID tokens, overriding the one derived from the region and user pool (e.g., for a
+    /// local Cognito emulator). The JWKS is downloaded from `<issuer>/.well-known/jwks.json`.
+    #[arg(long = "cognito-issuer-url", env = "COGNITO_ISSUER_URL")]
+    pub issuer_url: Option<String>,
+
+    /// Seconds the user pool JWKS is cached in memory and in Redis.
+    #[arg(long = "jwks-cache-ttl", env = "JWKS_CACHE_TTL_SECS", default_value_t = 24 * 3600)]
+    pub jwks_cache_ttl: u64,
+
+    /// Minimum seconds between two JWKS downloads triggered by tokens with an unknown key ID.
+    #[arg(long = "jwks-refetch-cooldown", env = "JWKS_REFETCH_COOLDOWN_SECS", default_value_t = 60)]
+    pub jwks_refetch_cooldown: u64,
+
+    /// Seconds before expiry at which the JWKS is refreshed in the background.
+    #[arg(long = "jwks-refresh-margin", env = "JWKS_REFRESH_MARGIN_SECS", default_value_t = 300)]
+    pub jwks_refresh_margin: u64,
+}
+
+impl CognitoConfig {
+    /// Issuer of the ID tokens of the configured user pool.
+    pub fn issuer(&self) -> String {
+        match &self.issuer_url {
+            Some(issuer) => issuer.trim_end_matches('/').to_string(),
+            None => format!(
+                "https://cognito-idp.{}.amazonaws.com/{}",
+                self.region, self.user_pool_id
+            ),
+        }
+    }
+
+    /// URL of the JSON Web Key Set of the configured user pool.
+    pub fn jwks_url(&self) -> String {
+        format!("{}/.well-known/jwks.json", self.issuer())
+    }
+}
+
+/// AWS STS configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "sts")]
+pub struct StsConfig {
+    /// AWS STS Role ARN that CLI clients will assume.
+    #[arg(long, env = "STS_ROLE_ARN")]
+    pub role_arn: String,
+
+    /// Optional external ID for the STS AssumeRole call.
+    #[arg(long, env = "STS_EXTERNAL_ID")]
+    pub external_id: Option<String>,
+
+    /// Minimum lifetime, in seconds, that cached credentials must have left to be handed out
+    /// again instead of calling AssumeRole. 0 disables the credential cache.
+    #[arg(long = "sts-cache-min-lifetime", env = "STS_CACHE_MIN_LIFETIME_SECS", default_value_t = 900)]
+    pub cache_min_lifetime_secs: u64,
+
+    /// Base64-encoded 256-bit key encrypting the cached credentials. Replicas sharing it share
+    /// the cache; when unset, each process uses a random key of its own.
+    #[arg(long = "sts-cache-key", env = "STS_CACHE_KEY")]
+    pub cache_key: Option<String>,
+}
+
+/// Rate limiting configuration settings.
+///
+/// Limits are counted in fixed windows of `window_secs` seconds. A limit of 0 disables it.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "rate_limit")]
+pub struct RateLimitConfig {
+    /// Length of the rate limiting window in seconds.
+    #[arg(long = "rate-limit-window", env = "RATE_LIMIT_WINDOW_SECS", default_value_t = 60)]
+    pub window_secs: u64,
+
+    /// Maximum `/auth/cli/start` requests per client IP within a window.
+    #[arg(long = "rate-limit-start-per-ip", env = "RATE_LIMIT_START_PER_IP", default_value_t = 10)]
+    pub start_per_ip: u64,
+
+    /// Maximum `/auth/cli/callback` requests per client IP within a window.
+    #[arg(long = "rate-limit-callback-per-ip", env = "RATE_LIMIT_CALLBACK_PER_IP", default_value_t = 20)]
+    pub callback_per_ip: u64,
+
+    /// Maximum `/auth/cli/confirm` requests per client IP within a window.
+    #[arg(long = "rate-limit-confirm-per-ip", env = "RATE_LIMIT_CONFIRM_PER_IP", default_value_t = 20)]
+    pub confirm_per_ip: u64,
+
+    /// Maximum `/auth/cli/status` requests per client IP within a window.
+    #[arg(long = "rate-limit-status-per-ip", env = "RATE_LIMIT_STATUS_PER_IP", default_value_t = 120)]
+    pub status_per_ip: u64,
+
+    /// Maximum `/auth/cli/status` requests per state within a window.
+    #[arg(long = "rate-limit-status-per-state", env = "RATE_LIMIT_STATUS_PER_STATE", default_value_t = 30)]
+    pub status_per_state: u64,
+
+    /// Maximum `/auth/cli/renew` requests per client IP within a window.
+    #[arg(long = "rate-limit-renew-per-ip", env = "RATE_LIMIT_RENEW_PER_IP", default_value_t = 30)]
+    pub renew_per_ip: u64,
+
+    /// Maximum `/auth/cli/logout` requests per client IP within a window.
+    #[arg(long = "rate-limit-logout-per-ip", env = "RATE_LIMIT_LOGOUT_PER_IP", default_value_t = 10)]
+    pub logout_per_ip: u64,
+
+    /// Maximum `/auth/cli/container-token` requests per client IP within a window.
+    #[arg(
+        long = "rate-limit-container-token-per-ip",
+        env = "RATE_LIMIT_CONTAINER_TOKEN_PER_IP",
+        default_value_t = 10
+    )]
+    pub container_token_per_ip: u64,
+
+    /// Maximum `/auth/cli/container-credentials` requests per client IP within a window.
+    #[arg(
+        long = "rate-limit-container-credentials-per-ip",
+        env = "RATE_LIMIT_CONTAINER_CREDENTIALS_PER_IP",
+        default_value_t = 60
+    )]
+    pub container_credentials_per_ip: u64,
+
+    /// Minimum number of seconds a CLI must wait between two status polls.
+    #[arg(long = "poll-interval", env = "POLL_INTERVAL_SECS", default_value_t = 5)]
+    pub poll_interval_secs: u64,
+}
+
+/// Destinations available for audit events.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSinkKind {
+    /// Append events to a Redis Stream.
+    Redis,
+    /// Append events as JSON lines to a local file.
+    Jsonl,
+}
+
+/// Audit log configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "audit")]
+pub struct AuditConfig {
+    /// Comma-separated list of audit sinks to write events to.
+    #[arg(
+        long = "audit-sinks",
+        env = "AUDIT_SINKS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "redis"
+    )]
+    pub sinks: Vec<AuditSinkKind>,
+
+    /// Redis Stream key receiving audit events.
+    #[arg(long = "audit-stream", env = "AUDIT_STREAM", default_value = "auth:audit")]
+    pub stream: String,
+
+    /// Approximate maximum number of entries kept in the audit stream.
+    #[arg(long = "audit-stream-max-len", env = "AUDIT_STREAM_MAX_LEN", default_value_t = 100_000)]
+    pub stream_max_len: usize,
+
+    /// Path of the JSON lines file receiving audit events.
+    #[arg(long = "audit-file", env = "AUDIT_FILE", default_value = "audit.jsonl")]
+    pub file: PathBuf,
+}
+
+/// Tracing and OpenTelemetry configuration settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "telemetry")]
+pub struct TelemetryConfig {
+    /// Optional OTLP/HTTP collector endpoint (e.g., http://otel-collector:4318). Spans are only exported when set.
+    #[arg(long = "otlp-endpoint", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Service name reported on exported spans.
+    #[arg(long = "service-name", env = "OTEL_SERVICE_NAME", default_value = "mega-uploader-auth")]
+    pub service_name: String,
+}
+
+/// CLI versions accepted by the service, compared as semver.
+///
+/// Clients report their version in the `cli_version` field of `/auth/cli/start` and in the
+/// `X-CLI-Version` header; clients reporting none are not checked.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "cli")]
+pub struct CliVersionConfig {
+    /// Oldest CLI version allowed to start logins, poll and renew; older clients get `upgrade_required`.
+    #[arg(long = "cli-min-version", env = "CLI_MIN_VERSION")]
+    pub min_version: Option<Version>,
+
+    /// Range of CLI versions still served but warned to upgrade (e.g. `<1.6.0`).
+    #[arg(long = "cli-deprecated-versions", env = "CLI_DEPRECATED_VERSIONS")]
+    pub deprecated_versions: Option<VersionReq>,
+
+    /// Download page of the CLI, given in upgrade errors and deprecation warnings.
+    #[arg(long = "cli-download-url", env = "CLI_DOWNLOAD_URL")]
+    pub download_url: Option<String>,
+}
+
+/// Settings of the browser pages shown at the end of a login.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "pages")]
+pub struct PagesConfig {
+    /// Directory with templates replacing the built-in `base.html`, `confirm.html`, `success.html`, `denied.html`, `expired.html` and `error.html`.
+    #[arg(long = "pages-dir", env = "PAGES_DIR")]
+    pub templates_dir: Option<PathBuf>,
+}
+
+/// Graceful shutdown settings.
+#[derive(Args, Debug, Clone, Serialize)]
+#[group(id = "shutdown")]
+pub struct ShutdownConfig {
+    /// Seconds `/readyz` reports failure before the server stops accepting connections, so load balancers stop routing to it.
+    #[arg(long = "shutdown-readiness-delay", env = "SHUTDOWN_READINESS_DELAY_SECS", default_value_t = 5)]
+    pub readiness_delay_secs: u64,
+
+    /// Maximum seconds to wait for in-flight requests to finish once the server stops accepting connections.
+    #[arg(long = "shutdown-drain-timeout", env = "SHUTDOWN_DRAIN_TIMEOUT_SECS", default_value_t = 30)]
+    pub drain_timeout_secs: u64,
+}
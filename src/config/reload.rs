@@ -0,0 +1,286 @@
+use super::AppArgs;
+use crate::utils::watch::watch_files;
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use arc_swap::ArcSwap;
+use notify::RecommendedWatcher;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Configuration shared with the handlers, swapped atomically on reload.
+pub type SharedConfig = ArcSwap<AppArgs>;
+
+/// Time to wait for a burst of file events to settle before reloading.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Settings that are read once at startup. Changes to them are ignored until a restart.
+const RESTART_ONLY_SETTINGS: &[&str] = &[
+    "redis.",
+    "storage.",
+    "server.",
+    "audit.",
+    "telemetry.",
+    "shutdown.",
+    "pages.",
+    "cognito.region",
+    "cognito.issuer_url",
+    "cognito.user_pool_id",
+    "cognito.client_id",
+    "cognito.jwks_",
+    "sts.cache_key",
+];
+
+/// Starts reloading the configuration on SIGHUP and, when a config file is used, whenever it changes.
+///
+/// The new configuration is re-read from the config file and the environment, validated like at
+/// startup and only then swapped in; an invalid configuration is logged and discarded.
+pub fn spawn_reloader(shared: Arc<SharedConfig>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<&'static str>();
+
+    let hangup_tx = tx.clone();
+    actix_web::rt::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                log::warn!("Could not listen for SIGHUP, configuration reload disabled: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if hangup_tx.send("SIGHUP").is_err() {
+                break;
+            }
+        }
+    });
+
+    let watcher = shared
+        .load()
+        .config
+        .as_deref()
+        .and_then(|path| watch_config_file(path, tx));
+
+    actix_web::rt::spawn(async move {
+        // Dropping the watcher stops it, so it lives as long as this task
+        let _watcher = watcher;
+        while let Some(trigger) = rx.recv().await {
+            actix_web::rt::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            reload(&shared, trigger).await;
+        }
+    });
+}
+
+/// Watches the config file, logging instead of failing when it cannot be watched.
+fn watch_config_file(path: &Path, tx: mpsc::UnboundedSender<&'static str>) -> Option<RecommendedWatcher> {
+    match watch_files(&[path], move || {
+        let _ = tx.send("config file change");
+    }) {
+        Ok(watcher) => {
+            log::info!("Watching {} for configuration changes", path.display());
+            Some(watcher)
+        }
+        Err(e) => {
+            log::warn!("Could not watch {}, reload on SIGHUP only: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Loads, validates and swaps in a new configuration, logging what changed.
+async fn reload(shared: &SharedConfig, trigger: &str) {
+    log::info!("Reloading configuration ({})", trigger);
+
+    let mut next = match AppArgs::try_load() {
+        Ok(next) => next,
+        Err(e) => {
+            log::error!(
+                "Configuration reload failed, keeping the current configuration: {}",
+                e.to_string().trim_end()
+            );
+            return;
+        }
+    };
+
+    let errors = next.check().await;
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        log::error!(
+            "Configuration reload rejected, keeping the current configuration: {}",
+            errors.join("; ")
+        );
+        return;
+    }
+
+    let current = shared.load_full();
+    for setting in next.keep_restart_only_settings(&current) {
+        log::warn!("{} changed, but only takes effect after a restart", setting);
+    }
+
+    let changes = diff(&current, &next);
+    if changes.is_empty() {
+        log::info!("Configuration reloaded, no changes");
+        return;
+    }
+    for change in &changes {
+        log::info!("Configuration changed: {}", change);
+    }
+    shared.store(Arc::new(next));
+}
+
+impl AppArgs {
+    /// Restores the settings that are only read at startup from `current`, returning the ones
+    /// that had changed.
+    fn keep_restart_only_settings(&mut self, current: &AppArgs) -> Vec<String> {
+        let ignored: Vec<String> = changed_settings(current, self)
+            .into_iter()
+            .filter(|setting| RESTART_ONLY_SETTINGS.iter().any(|p| setting.starts_with(p)))
+            .collect();
+
+        if !ignored.is_empty() {
+            self.redis = current.redis.clone();
+            self.storage = current.storage.clone();
+            self.server = current.server.clone();
+            self.audit = current.audit.clone();
+            self.telemetry = current.telemetry.clone();
+            self.shutdown = current.shutdown.clone();
+            self.pages = current.pages.clone();
+            self.cognito.region = current.cognito.region.clone();
+            self.cognito.issuer_url = current.cognito.issuer_url.clone();
+            self.cognito.user_pool_id = current.cognito.user_pool_id.clone();
+            self.cognito.client_id = current.cognito.client_id.clone();
+            self.cognito.jwks_cache_ttl = current.cognito.jwks_cache_ttl;
+            self.cognito.jwks_refetch_cooldown = current.cognito.jwks_refetch_cooldown;
+            self.cognito.jwks_refresh_margin = current.cognito.jwks_refresh_margin;
+            self.sts.cache_key = current.sts.cache_key.clone();
+        }
+        ignored
+    }
+}
+
+/// Describes the settings that differ between two configurations, with secrets redacted.
+fn diff(old: &AppArgs, new: &AppArgs) -> Vec<String> {
+    let old_redacted = flatten(&old.redacted());
+    let new_redacted = flatten(&new.redacted());
+
+    changed_settings(old, new)
+        .into_iter()
+        .map(|setting| {
+            let before = old_redacted.get(&setting).map_or("(unset)", String::as_str);
+            let after = new_redacted.get(&setting).map_or("(unset)", String::as_str);
+            if before == after {
+                format!("{} (secret changed)", setting)
+            } else {
+                format!("{}: {} -> {}", setting, before, after)
+            }
+        })
+        .collect()
+}
+
+/// Lists the settings, as `section.key`, whose values differ between two configurations.
+fn changed_settings(old: &AppArgs, new: &AppArgs) -> Vec<String> {
+    let old = flatten(old);
+    let new = flatten(new);
+
+    let mut settings: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|setting| old.get(*setting) != new.get(*setting))
+        .cloned()
+        .collect();
+    settings.sort();
+    settings.dedup();
+    settings
+}
+
+/// Flattens a configuration into `section.key` entries rendered as JSON values.
+fn flatten(args: &AppArgs) -> BTreeMap<String, String> {
+    let mut settings = BTreeMap::new();
+    if let Ok(Value::Object(sections)) = serde_json::to_value(args) {
+        for (section, values) in sections {
+            if let Value::Object(values) = values {
+                for (key, value) in values {
+                    settings.insert(format!("{}.{}", section, key), value.to_string());
+                }
+            }
+        }
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Parses a configuration from the settings without default plus `extra` arguments.
+    fn args(extra: &[&str]) -> AppArgs {
+        let required = [
+            "mega-uploader-auth",
+            "--domain",
+            "https://auth.example.com",
+            "--client-id",
+            "client",
+            "--redirect-uri",
+            "https://cli.example.com/auth/cli/callback",
+            "--user-pool-id",
+            "us-east-1_test",
+            "--region",
+            "us-east-1",
+            "--role-arn",
+            "arn:aws:iam::123456789012:role/cli",
+        ];
+        AppArgs::try_parse_from(required.iter().chain(extra)).unwrap()
+    }
+
+    #[test]
+    fn restart_only_settings_are_kept_and_reported() {
+        let current = args(&[]);
+        let mut next = current.clone();
+        next.redis.url = "redis://other:6379".to_string();
+        next.cognito.client_id = "other-client".to_string();
+        next.cognito.jwks_cache_ttl = 60;
+        next.sts.cache_key = Some("c2VjcmV0".to_string());
+        next.rate_limit.poll_interval_secs = 9;
+
+        let ignored = next.keep_restart_only_settings(&current);
+        assert_eq!(
+            ignored,
+            ["cognito.client_id", "cognito.jwks_cache_ttl", "redis.url", "sts.cache_key"]
+        );
+        assert_eq!(next.redis.url, current.redis.url);
+        assert_eq!(next.cognito.client_id, "client");
+        assert_eq!(next.cognito.jwks_cache_ttl, current.cognito.jwks_cache_ttl);
+        assert_eq!(next.sts.cache_key, None);
+
+        // Hot-reloadable settings changed alongside are still applied
+        assert_eq!(changed_settings(&current, &next), ["rate_limit.poll_interval_secs"]);
+    }
+
+    #[test]
+    fn hot_reloadable_settings_are_applied() {
+        let current = args(&[]);
+        let mut next = current.clone();
+        next.sts.role_arn = "arn:aws:iam::123456789012:role/other".to_string();
+        next.rate_limit.start_per_ip = 5;
+
+        assert!(next.keep_restart_only_settings(&current).is_empty());
+        assert_eq!(
+            changed_settings(&current, &next),
+            ["rate_limit.start_per_ip", "sts.role_arn"]
+        );
+    }
+
+    #[test]
+    fn changes_are_described_with_secrets_redacted() {
+        let current = args(&["--external-id", "first"]);
+        let next = args(&["--external-id", "second", "--poll-interval", "9"]);
+
+        assert_eq!(
+            diff(&current, &next),
+            ["rate_limit.poll_interval_secs: 5 -> 9", "sts.external_id (secret changed)"]
+        );
+    }
+}
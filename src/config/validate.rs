@@ -0,0 +1,404 @@
+use super::file::redact_url_password;
+use super::{AppArgs, AuditSinkKind, CognitoConfig, RedisMode, SessionBackend};
+use crate::tls;
+use jsonwebtoken::jwk::JwkSet;
+use redis::IntoConnectionInfo;
+use reqwest::Url;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Maximum time allowed for the JWKS reachability check.
+const JWKS_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A configuration problem, reported against the setting that caused it.
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    /// Setting name, as used in the config file (e.g. `cognito.domain`).
+    pub setting: &'static str,
+    /// What is wrong with the value.
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(setting: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            setting,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.setting, self.message)
+    }
+}
+
+impl AppArgs {
+    /// Runs every configuration check, including the JWKS reachability check.
+    ///
+    /// The JWKS is only fetched when the Cognito settings it is derived from are valid.
+    pub async fn check(&self) -> Vec<ConfigError> {
+        let mut errors = self.validate();
+        if !errors.iter().any(|e| e.setting.starts_with("cognito."))
+            && let Err(e) = check_jwks(&self.cognito).await
+        {
+            errors.push(e);
+        }
+        errors
+    }
+
+    /// Validates the format and consistency of every setting, without network access.
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        // Redis
+        if let Err(e) = self.redis.url.as_str().into_connection_info() {
+            errors.push(ConfigError::new("redis.url", format!("invalid Redis URL: {}", e)));
+        }
+        if self.redis.mode == RedisMode::Standalone {
+            if !self.redis.nodes.is_empty() {
+                errors.push(ConfigError::new("redis.nodes", "is only used in sentinel and cluster modes"));
+            }
+        } else if self.redis.nodes.is_empty() {
+            errors.push(ConfigError::new(
+                "redis.nodes",
+                "at least one node is required in sentinel and cluster modes",
+            ));
+        }
+        for node in &self.redis.nodes {
+            if let Err(e) = node.as_str().into_connection_info() {
+                errors.push(ConfigError::new(
+                    "redis.nodes",
+                    format!("invalid Redis node URL `{}`: {}", redact_url_password(node), e),
+                ));
+            }
+        }
+        if self.redis.mode == RedisMode::Sentinel && self.redis.sentinel_master.trim().is_empty() {
+            errors.push(ConfigError::new("redis.sentinel_master", "must not be empty"));
+        }
+        if self.redis.connect_timeout_ms == 0 {
+            errors.push(ConfigError::new("redis.connect_timeout_ms", "must be greater than 0"));
+        }
+
+        // Storage
+        match (&self.storage.session_backend, &self.storage.database_url) {
+            (SessionBackend::Sql, None) => errors.push(ConfigError::new(
+                "storage.database_url",
+                "is required by the sql session backend",
+            )),
+            (SessionBackend::Sql, Some(url))
+                if !["sqlite:", "postgres:", "postgresql:"].iter().any(|s| url.starts_with(s)) =>
+            {
+                errors.push(ConfigError::new(
+                    "storage.database_url",
+                    format!("`{}` is not a sqlite: or postgres: URL", redact_url_password(url)),
+                ))
+            }
+            _ => {}
+        }
+
+        // Server
+        if let Err(e) = validate_listen_addr(&self.server.addr) {
+            errors.push(ConfigError::new("server.addr", e));
+        }
+        match (&self.server.tls_cert, &self.server.tls_key) {
+            (Some(cert), Some(key)) => {
+                if let Err(e) = tls::check_certified_key(cert, key) {
+                    errors.push(ConfigError::new("server.tls_cert", e));
+                }
+            }
+            (Some(_), None) => errors.push(ConfigError::new("server.tls_key", "required with server.tls_cert")),
+            (None, Some(_)) => errors.push(ConfigError::new("server.tls_cert", "required with server.tls_key")),
+            (None, None) => {}
+        }
+        if let Some(redirect_addr) = &self.server.http_redirect_addr {
+            if self.server.tls_cert.is_none() {
+                errors.push(ConfigError::new(
+                    "server.http_redirect_addr",
+                    "requires server.tls_cert and server.tls_key",
+                ));
+            }
+            if let Err(e) = validate_listen_addr(redirect_addr) {
+                errors.push(ConfigError::new("server.http_redirect_addr", e));
+            }
+        }
+
+        // Cognito
+        let cognito = &self.cognito;
+        if let Err(e) = validate_http_url(&cognito.domain) {
+            errors.push(ConfigError::new("cognito.domain", e));
+        }
+        if let Err(e) = validate_http_url(&cognito.redirect_uri) {
+            errors.push(ConfigError::new("cognito.redirect_uri", e));
+        }
+        if let Some(issuer) = &cognito.issuer_url
+            && let Err(e) = validate_http_url(issuer)
+        {
+            errors.push(ConfigError::new("cognito.issuer_url", e));
+        }
+        if cognito.client_id.trim().is_empty() {
+            errors.push(ConfigError::new("cognito.client_id", "must not be empty"));
+        }
+        if !is_aws_region(&cognito.region) {
+            errors.push(ConfigError::new(
+                "cognito.region",
+                format!("`{}` is not an AWS region (e.g. us-east-1)", cognito.region),
+            ));
+        }
+        match cognito.user_pool_id.split_once('_') {
+            Some((region, id)) if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                if region != cognito.region {
+                    errors.push(ConfigError::new(
+                        "cognito.user_pool_id",
+                        format!(
+                            "pool `{}` belongs to region `{}`, but cognito.region is `{}`",
+                            cognito.user_pool_id, region, cognito.region
+                        ),
+                    ));
+                }
+            }
+            _ => errors.push(ConfigError::new(
+                "cognito.user_pool_id",
+                format!(
+                    "`{}` is not a user pool ID (e.g. us-east-1_XXXXXXXXX)",
+                    cognito.user_pool_id
+                ),
+            )),
+        }
+        if cognito.jwks_cache_ttl == 0 {
+            errors.push(ConfigError::new("cognito.jwks_cache_ttl", "must be greater than 0"));
+        } else if cognito.jwks_refresh_margin >= cognito.jwks_cache_ttl {
+            errors.push(ConfigError::new(
+                "cognito.jwks_refresh_margin",
+                "must be lower than cognito.jwks_cache_ttl",
+            ));
+        }
+
+        // STS
+        if let Err(e) = validate_role_arn(&self.sts.role_arn) {
+            errors.push(ConfigError::new("sts.role_arn", e));
+        }
+        if let Some(external_id) = &self.sts.external_id {
+            let valid_chars = external_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+=,.@:/-_".contains(c));
+            if !(2..=1224).contains(&external_id.len()) || !valid_chars {
+                errors.push(ConfigError::new(
+                    "sts.external_id",
+                    "must be 2 to 1224 characters from [A-Za-z0-9+=,.@:/_-]",
+                ));
+            }
+        }
+
+        if let Some(key) = &self.sts.cache_key
+            && let Err(e) = crate::sts::CredentialCache::decode_key(key)
+        {
+            errors.push(ConfigError::new("sts.cache_key", e));
+        }
+
+        // Rate limiting
+        if self.rate_limit.window_secs == 0 {
+            errors.push(ConfigError::new("rate_limit.window_secs", "must be greater than 0"));
+        }
+
+        // Audit
+        if self.audit.sinks.is_empty() {
+            errors.push(ConfigError::new("audit.sinks", "at least one sink is required"));
+        }
+        if self.audit.sinks.contains(&AuditSinkKind::Redis) && self.audit.stream.trim().is_empty() {
+            errors.push(ConfigError::new("audit.stream", "must not be empty"));
+        }
+
+        // CLI versions
+        match &self.cli.download_url {
+            Some(url) => {
+                if let Err(e) = validate_http_url(url) {
+                    errors.push(ConfigError::new("cli.download_url", e));
+                }
+            }
+            None if self.cli.min_version.is_some() => {
+                errors.push(ConfigError::new("cli.download_url", "is required with cli.min_version"));
+            }
+            None => {}
+        }
+
+        // Pages
+        if let Err(e) = crate::pages::Pages::load(self.pages.templates_dir.as_deref()) {
+            errors.push(ConfigError::new("pages.templates_dir", e));
+        }
+
+        // Telemetry
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint
+            && let Err(e) = validate_http_url(endpoint)
+        {
+            errors.push(ConfigError::new("telemetry.otlp_endpoint", e));
+        }
+
+        errors
+    }
+}
+
+/// Checks that the user pool JWKS can be downloaded and contains at least one key.
+async fn check_jwks(cognito: &CognitoConfig) -> Result<(), ConfigError> {
+    let url = cognito.jwks_url();
+    let error = |message: String| ConfigError::new("cognito.user_pool_id", message);
+
+    let jwks = reqwest::Client::new()
+        .get(&url)
+        .timeout(JWKS_CHECK_TIMEOUT)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| error(format!("JWKS at {} is not reachable: {}", url, e)))?
+        .json::<JwkSet>()
+        .await
+        .map_err(|e| error(format!("JWKS at {} is not valid: {}", url, e)))?;
+
+    if jwks.keys.is_empty() {
+        return Err(error(format!("JWKS at {} has no keys", url)));
+    }
+    Ok(())
+}
+
+/// Checks that a listen address is an IP address and port, or a host name and port.
+///
+/// Host names are not resolved, so that the check needs no network access.
+fn validate_listen_addr(value: &str) -> Result<(), String> {
+    if value.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+    let invalid = |reason: &str| format!("`{}` is not a valid listen address: {}", value, reason);
+    let (host, port) = value.rsplit_once(':').ok_or_else(|| invalid("expected host:port"))?;
+    port.parse::<u16>().map_err(|_| invalid("invalid port"))?;
+    let valid_host = !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if !valid_host {
+        return Err(invalid("invalid host"));
+    }
+    Ok(())
+}
+
+/// Checks that a value is an absolute `http` or `https` URL with a host.
+fn validate_http_url(value: &str) -> Result<(), String> {
+    let url = Url::parse(value).map_err(|e| format!("`{}` is not a valid URL: {}", value, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(format!("`{}` must be an http(s) URL with a host", value));
+    }
+    Ok(())
+}
+
+/// Checks that a value looks like an AWS region name (e.g. `us-east-1`).
+fn is_aws_region(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    parts.len() >= 3
+        && parts[..parts.len() - 1]
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_lowercase()))
+        && parts[parts.len() - 1].parse::<u8>().is_ok()
+}
+
+/// Checks that a value is an IAM role ARN (`arn:<partition>:iam::<account>:role/<name>`).
+fn validate_role_arn(value: &str) -> Result<(), String> {
+    let invalid = || {
+        format!(
+            "`{}` is not an IAM role ARN (e.g. arn:aws:iam::123456789012:role/CliRole)",
+            value
+        )
+    };
+
+    let parts: Vec<&str> = value.splitn(6, ':').collect();
+    let [prefix, partition, service, region, account, resource] = parts[..] else {
+        return Err(invalid());
+    };
+    let valid = prefix == "arn"
+        && partition.starts_with("aws")
+        && service == "iam"
+        && region.is_empty()
+        && account.len() == 12
+        && account.chars().all(|c| c.is_ascii_digit())
+        && resource.strip_prefix("role/").is_some_and(|name| !name.is_empty());
+
+    if valid { Ok(()) } else { Err(invalid()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn role_arns() {
+        for valid in [
+            "arn:aws:iam::123456789012:role/CliRole",
+            "arn:aws:iam::123456789012:role/path/to/CliRole",
+            "arn:aws-us-gov:iam::123456789012:role/CliRole",
+            "arn:aws-cn:iam::123456789012:role/CliRole",
+        ] {
+            assert_eq!(validate_role_arn(valid), Ok(()), "{}", valid);
+        }
+
+        for invalid in [
+            "",
+            "CliRole",
+            "arn:aws:iam::123456789012:user/alice",
+            "arn:aws:iam::123456789012:role/",
+            "arn:aws:iam::12345678901:role/CliRole",
+            "arn:aws:iam::12345678901x:role/CliRole",
+            "arn:aws:iam:us-east-1:123456789012:role/CliRole",
+            "arn:aws:sts::123456789012:role/CliRole",
+            "arn:gcp:iam::123456789012:role/CliRole",
+            "urn:aws:iam::123456789012:role/CliRole",
+        ] {
+            assert!(validate_role_arn(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn aws_regions() {
+        for valid in ["us-east-1", "eu-west-3", "ap-southeast-2", "us-gov-west-1", "cn-north-1"] {
+            assert!(is_aws_region(valid), "{}", valid);
+        }
+        for invalid in ["", "us-east", "useast1", "us-east-x", "US-EAST-1", "us--1", "us-east-1 ", "us-east-256"] {
+            assert!(!is_aws_region(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn listen_addresses() {
+        for valid in ["0.0.0.0:8080", "127.0.0.1:443", "[::]:8080", "[::1]:80", "localhost:8080", "auth.internal:443"] {
+            assert_eq!(validate_listen_addr(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "",
+            "8080",
+            ":8080",
+            "localhost",
+            "localhost:",
+            "localhost:65536",
+            "local_host:80",
+            "auth..internal:80",
+        ] {
+            assert!(validate_listen_addr(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn http_urls() {
+        for valid in ["http://localhost:4318", "https://auth.example.com/path?query=1", "https://10.0.0.1"] {
+            assert_eq!(validate_http_url(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "",
+            "auth.example.com",
+            "/auth/cli/callback",
+            "ftp://example.com",
+            "file:///etc/passwd",
+            "unix:/run/otel.sock",
+        ] {
+            assert!(validate_http_url(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
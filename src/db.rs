@@ -1,34 +1,156 @@
-use actix_web::{error, Error};
-use bb8::Pool;
-use bb8_redis::RedisConnectionManager;
-use redis::{AsyncCommands, RedisError};
+use crate::config::{RedisConfig, RedisMode};
+use crate::error::AppError;
+use bb8::{ManageConnection, Pool};
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::cluster::ClusterClient;
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType};
+use redis::{AsyncCommands, Cmd, ErrorKind, IntoConnectionInfo, Pipeline, RedisError, RedisFuture, Value};
 use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+use tokio::sync::Mutex;
 
 /// Type alias for the Redis connection pool.
 pub type RedisPool = Pool<RedisConnectionManager>;
 
-/// Creates a new Redis connection pool.
-pub async fn create_pool(connection_string: &str) -> Result<RedisPool, RedisError> {
-    let manager = RedisConnectionManager::new(connection_string)?;
-    let pool = Pool::builder().build(manager).await?;
+/// Connection manager for the pool, opening connections for the configured Redis mode.
+pub enum RedisConnectionManager {
+    /// A single server.
+    Standalone(redis::Client),
+    /// The current master of a Sentinel-monitored group. The client asks the Sentinels for
+    /// the master of every new connection, and pooled connections are checked to still be
+    /// on a master before use, which follows failovers.
+    Sentinel(Mutex<SentinelClient>),
+    /// A Redis Cluster, with commands routed to the node owning their key's slot.
+    Cluster(ClusterClient),
+}
+
+/// Pooled connection to Redis, whatever the mode.
+pub enum RedisConnection {
+    Single(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            RedisConnection::Single(conn) => conn.req_packed_command(cmd),
+            RedisConnection::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            RedisConnection::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            RedisConnection::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConnection::Single(conn) => conn.get_db(),
+            RedisConnection::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+impl ManageConnection for RedisConnectionManager {
+    type Connection = RedisConnection;
+    type Error = RedisError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        match self {
+            RedisConnectionManager::Standalone(client) => client
+                .get_multiplexed_async_connection()
+                .await
+                .map(RedisConnection::Single),
+            RedisConnectionManager::Sentinel(client) => client
+                .lock()
+                .await
+                .get_async_connection()
+                .await
+                .map(RedisConnection::Single),
+            RedisConnectionManager::Cluster(client) => {
+                client.get_async_connection().await.map(RedisConnection::Cluster)
+            }
+        }
+    }
+
+    async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        let RedisConnectionManager::Sentinel(_) = self else {
+            let _: String = Cmd::ping().query_async(conn).await?;
+            return Ok(());
+        };
+
+        // A master demoted by a failover still answers PING, but refuses writes
+        let role: Vec<Value> = redis::cmd("ROLE").query_async(conn).await?;
+        match role.first().map(redis::from_redis_value_ref::<String>) {
+            Some(Ok(role)) if role == "master" => Ok(()),
+            _ => Err((ErrorKind::Client, "connection is no longer to the master").into()),
+        }
+    }
+
+    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+impl RedisConnectionManager {
+    /// Builds the manager for the configured mode, without connecting yet.
+    pub fn new(config: &RedisConfig) -> Result<Self, RedisError> {
+        match config.mode {
+            RedisMode::Standalone => Ok(Self::Standalone(redis::Client::open(config.url.as_str())?)),
+            RedisMode::Sentinel => {
+                // Credentials and database of the master come from the URL
+                let master = config.url.as_str().into_connection_info()?;
+                let node_info =
+                    SentinelNodeConnectionInfo::default().set_redis_connection_info(master.redis_settings().clone());
+                let client = SentinelClient::build(
+                    config.nodes.iter().map(String::as_str).collect(),
+                    config.sentinel_master.clone(),
+                    Some(node_info),
+                    SentinelServerType::Master,
+                )?;
+                Ok(Self::Sentinel(Mutex::new(client)))
+            }
+            RedisMode::Cluster => Ok(Self::Cluster(ClusterClient::new(
+                config.nodes.iter().map(String::as_str).collect::<Vec<_>>(),
+            )?)),
+        }
+    }
+}
+
+/// Creates a new Redis connection pool for the configured mode.
+pub async fn create_pool(config: &RedisConfig) -> Result<RedisPool, RedisError> {
+    let manager = RedisConnectionManager::new(config)?;
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .build(manager)
+        .await?;
     Ok(pool)
 }
 
 /// Retrieves and deserializes a value from Redis.
-pub async fn redis_get<T>(pool: &RedisPool, key: &str) -> Result<Option<T>, Error>
+#[tracing::instrument(name = "redis.get", skip(pool), fields(db.system = "redis"))]
+pub async fn redis_get<T>(pool: &RedisPool, key: &str) -> Result<Option<T>, AppError>
 where
     T: DeserializeOwned,
 {
     // Get a connection from the pool
     let mut conn = pool.get().await.map_err(|e| {
         log::error!("Failed to get redis connection: {}", e);
-        error::ErrorInternalServerError("Database connection error")
+        AppError::StorageUnavailable(e.to_string())
     })?;
 
     // Fetch the value as a string
     let value: Option<String> = conn.get(key).await.map_err(|e| {
         log::error!("Redis get error: {}", e);
-        error::ErrorInternalServerError("Redis error")
+        AppError::StorageUnavailable(e.to_string())
     })?;
 
     // Deserialize the JSON string into the target type T
@@ -36,7 +158,40 @@ where
         Some(val) => {
             let decoded: T = serde_json::from_str(&val).map_err(|e| {
                 log::error!("Failed to parse JSON from Redis: {}", e);
-                error::ErrorInternalServerError("Data corruption error")
+                AppError::DataCorruption(e.to_string())
+            })?;
+            Ok(Some(decoded))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Retrieves, deletes and deserializes a value from Redis in a single command.
+///
+/// Concurrent callers cannot both get the value, so it can be used only once.
+#[tracing::instrument(name = "redis.get_del", skip(pool), fields(db.system = "redis"))]
+pub async fn redis_get_del<T>(pool: &RedisPool, key: &str) -> Result<Option<T>, AppError>
+where
+    T: DeserializeOwned,
+{
+    // Get a connection from the pool
+    let mut conn = pool.get().await.map_err(|e| {
+        log::error!("Failed to get redis connection: {}", e);
+        AppError::StorageUnavailable(e.to_string())
+    })?;
+
+    // Fetch and delete the value as a string
+    let value: Option<String> = conn.get_del(key).await.map_err(|e| {
+        log::error!("Redis getdel error: {}", e);
+        AppError::StorageUnavailable(e.to_string())
+    })?;
+
+    // Deserialize the JSON string into the target type T
+    match value {
+        Some(val) => {
+            let decoded: T = serde_json::from_str(&val).map_err(|e| {
+                log::error!("Failed to parse JSON from Redis: {}", e);
+                AppError::DataCorruption(e.to_string())
             })?;
             Ok(Some(decoded))
         }
@@ -45,20 +200,21 @@ where
 }
 
 /// Serializes and stores a value in Redis with an expiration time (TTL).
-pub async fn redis_set_ex<T>(pool: &RedisPool, key: &str, value: &T, ttl: u64) -> Result<(), Error>
+#[tracing::instrument(name = "redis.set_ex", skip(pool, value), fields(db.system = "redis"))]
+pub async fn redis_set_ex<T>(pool: &RedisPool, key: &str, value: &T, ttl: u64) -> Result<(), AppError>
 where
     T: Serialize,
 {
     // Get a connection from the pool
     let mut conn = pool.get().await.map_err(|e| {
         log::error!("Failed to get redis connection: {}", e);
-        error::ErrorInternalServerError("Database connection error")
+        AppError::StorageUnavailable(e.to_string())
     })?;
 
     // Serialize the value into a JSON string
     let serialized = serde_json::to_string(value).map_err(|e| {
         log::error!("Failed to serialize data for Redis: {}", e);
-        error::ErrorInternalServerError("Data serialization error")
+        AppError::Internal(e.to_string())
     })?;
 
     // Store the string in Redis with the specified TTL
@@ -66,25 +222,128 @@ where
         .await
         .map_err(|e| {
             log::error!("Redis set_ex error: {}", e);
-            error::ErrorInternalServerError("Failed to store data in Redis")
+            AppError::StorageUnavailable(e.to_string())
         })?;
 
     Ok(())
 }
 
 /// Deletes a key from Redis.
-pub async fn redis_del(pool: &RedisPool, key: &str) -> Result<(), Error> {
+#[tracing::instrument(name = "redis.del", skip(pool), fields(db.system = "redis"))]
+pub async fn redis_del(pool: &RedisPool, key: &str) -> Result<(), AppError> {
     // Get a connection from the pool
     let mut conn = pool.get().await.map_err(|e| {
         log::error!("Failed to get redis connection: {}", e);
-        error::ErrorInternalServerError("Database connection error")
+        AppError::StorageUnavailable(e.to_string())
     })?;
 
     // Execute the DEL command
     let _: () = conn.del(key).await.map_err(|e| {
         log::error!("Redis del error: {}", e);
-        error::ErrorInternalServerError("Redis error")
+        AppError::StorageUnavailable(e.to_string())
     })?;
 
     Ok(())
 }
+
+/// Stores a value only if the key does not exist yet, with an expiration time (TTL).
+///
+/// Returns `true` when the value was stored and `false` when the key already existed.
+#[tracing::instrument(name = "redis.set_nx_ex", skip(pool, value), fields(db.system = "redis"))]
+pub async fn redis_set_nx_ex<T>(pool: &RedisPool, key: &str, value: &T, ttl: u64) -> Result<bool, AppError>
+where
+    T: Serialize,
+{
+    // Get a connection from the pool
+    let mut conn = pool.get().await.map_err(|e| {
+        log::error!("Failed to get redis connection: {}", e);
+        AppError::StorageUnavailable(e.to_string())
+    })?;
+
+    // Serialize the value into a JSON string
+    let serialized = serde_json::to_string(value).map_err(|e| {
+        log::error!("Failed to serialize data for Redis: {}", e);
+        AppError::Internal(e.to_string())
+    })?;
+
+    // SET key value NX EX ttl replies OK when stored and nil otherwise
+    let stored: Option<String> = redis::cmd("SET")
+        .arg(key)
+        .arg(serialized)
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl)
+        .query_async(&mut *conn)
+        .await
+        .map_err(|e| {
+            log::error!("Redis set nx error: {}", e);
+            AppError::StorageUnavailable(e.to_string())
+        })?;
+
+    Ok(stored.is_some())
+}
+
+/// Increments a counter that expires `window` seconds after its first increment.
+///
+/// Returns the counter value after the increment and the seconds left in the window.
+#[tracing::instrument(name = "redis.incr_window", skip(pool), fields(db.system = "redis"))]
+pub async fn redis_incr_window(pool: &RedisPool, key: &str, window: u64) -> Result<(u64, u64), AppError> {
+    // Get a connection from the pool
+    let mut conn = pool.get().await.map_err(|e| {
+        log::error!("Failed to get redis connection: {}", e);
+        AppError::StorageUnavailable(e.to_string())
+    })?;
+
+    // Increment, start the window of a fresh counter and read the remaining TTL in one
+    // transaction, so that no counter is ever left without an expiry
+    let (count, ttl): (u64, i64) = redis::pipe()
+        .atomic()
+        .incr(key, 1)
+        .cmd("EXPIRE")
+        .arg(key)
+        .arg(window)
+        .arg("NX")
+        .ignore()
+        .ttl(key)
+        .query_async(&mut *conn)
+        .await
+        .map_err(|e| {
+            log::error!("Redis incr error: {}", e);
+            AppError::StorageUnavailable(e.to_string())
+        })?;
+
+    Ok((count, u64::try_from(ttl).unwrap_or(window)))
+}
+
+/// Lists the keys matching a glob pattern, for administration commands.
+///
+/// Standalone and Sentinel connections iterate with SCAN so that Redis is never blocked. In
+/// cluster mode, KEYS is sent to every primary and the results are merged by the client.
+#[tracing::instrument(name = "redis.keys", skip(pool), fields(db.system = "redis"))]
+pub async fn redis_keys(pool: &RedisPool, pattern: &str) -> Result<Vec<String>, AppError> {
+    // Get a connection from the pool
+    let mut conn = pool.get().await.map_err(|e| {
+        log::error!("Failed to get redis connection: {}", e);
+        AppError::StorageUnavailable(e.to_string())
+    })?;
+
+    let keys = match &mut *conn {
+        RedisConnection::Single(conn) => {
+            let mut iter = conn.scan_match::<&str, String>(pattern).await.map_err(|e| {
+                log::error!("Redis scan error: {}", e);
+                AppError::StorageUnavailable(e.to_string())
+            })?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key.map_err(|e| AppError::StorageUnavailable(e.to_string()))?);
+            }
+            keys
+        }
+        RedisConnection::Cluster(conn) => conn.keys(pattern).await.map_err(|e| {
+            log::error!("Redis keys error: {}", e);
+            AppError::StorageUnavailable(e.to_string())
+        })?,
+    };
+
+    Ok(keys)
+}
@@ -0,0 +1,179 @@
+use crate::schemas::error::ProblemDetails;
+use actix_web::http::header::{ContentType, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+
+/// Media type of RFC 7807 problem details.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Application-wide error type.
+///
+/// Every variant maps to an HTTP status and a stable error code, and is rendered as
+/// RFC 7807 `application/problem+json` so that clients can tell failures apart.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    /// The request body or query string is malformed.
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    /// The authentication state is unknown, already used or expired.
+    #[error("Invalid or expired state")]
+    InvalidState,
+
+    /// The identity provider rejected the authorization code.
+    #[error("Invalid authorization code")]
+    InvalidAuthorizationCode,
+
+    /// The identity provider rejected the refresh token (expired or revoked).
+    #[error("Invalid or expired refresh token")]
+    InvalidRefreshToken,
+
+    /// The ID token returned by the identity provider failed validation.
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(String),
+
+    /// The container credentials token is missing, unknown or belongs to an ended session.
+    #[error("Invalid or expired container credentials token")]
+    InvalidContainerToken,
+
+    /// The CLI version is older than the minimum supported one.
+    #[error("CLI version {version} is no longer supported, upgrade to {minimum_version} or later")]
+    UpgradeRequired {
+        version: String,
+        minimum_version: String,
+        download_url: Option<String>,
+    },
+
+    /// The client exceeded a rate limit.
+    #[error("Too many requests, retry in {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+
+    /// Cognito (token endpoint or JWKS) could not be reached or answered unexpectedly.
+    #[error("Identity provider unavailable: {0}")]
+    IdentityProviderUnavailable(String),
+
+    /// STS could not issue credentials.
+    #[error("AWS STS unavailable: {0}")]
+    StsUnavailable(String),
+
+    /// Redis could not be reached or failed to execute a command.
+    #[error("Storage unavailable: {0}")]
+    StorageUnavailable(String),
+
+    /// The server is shutting down and does not start new logins.
+    #[error("Service is shutting down")]
+    ShuttingDown,
+
+    /// A stored value could not be decoded.
+    #[error("Stored data is corrupted: {0}")]
+    DataCorruption(String),
+
+    /// Any other unexpected failure.
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl AppError {
+    /// Stable, machine-readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::InvalidRequest(_) => "invalid_request",
+            AppError::InvalidState => "invalid_state",
+            AppError::InvalidAuthorizationCode => "invalid_authorization_code",
+            AppError::InvalidRefreshToken => "invalid_refresh_token",
+            AppError::InvalidIdToken(_) => "invalid_id_token",
+            AppError::InvalidContainerToken => "invalid_container_token",
+            AppError::UpgradeRequired { .. } => "upgrade_required",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::IdentityProviderUnavailable(_) => "identity_provider_unavailable",
+            AppError::StsUnavailable(_) => "sts_unavailable",
+            AppError::StorageUnavailable(_) => "storage_unavailable",
+            AppError::ShuttingDown => "shutting_down",
+            AppError::DataCorruption(_) => "data_corruption",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Short summary of the error type, used as the problem title.
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::InvalidRequest(_) => "Invalid request",
+            AppError::InvalidState => "Invalid or expired state",
+            AppError::InvalidAuthorizationCode => "Invalid authorization code",
+            AppError::InvalidRefreshToken => "Invalid or expired refresh token",
+            AppError::InvalidIdToken(_) => "Invalid ID token",
+            AppError::InvalidContainerToken => "Invalid or expired container credentials token",
+            AppError::UpgradeRequired { .. } => "CLI upgrade required",
+            AppError::RateLimited { .. } => "Too many requests",
+            AppError::IdentityProviderUnavailable(_) => "Identity provider unavailable",
+            AppError::StsUnavailable(_) => "AWS STS unavailable",
+            AppError::StorageUnavailable(_) => "Storage unavailable",
+            AppError::ShuttingDown => "Service is shutting down",
+            AppError::DataCorruption(_) => "Stored data is corrupted",
+            AppError::Internal(_) => "Internal server error",
+        }
+    }
+
+    /// Detail exposed to clients.
+    ///
+    /// Messages of server-side failures are only logged, as they may leak internals.
+    fn detail(&self) -> Option<String> {
+        match self {
+            AppError::InvalidRequest(detail) | AppError::InvalidIdToken(detail) => Some(detail.clone()),
+            AppError::UpgradeRequired { .. } | AppError::RateLimited { .. } => Some(self.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Builds the problem details describing this error.
+    pub fn problem(&self) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: format!("urn:mega-uploader-auth:error:{}", self.code()),
+            title: self.title().to_string(),
+            status: self.status_code().as_u16(),
+            detail: self.detail(),
+            code: self.code().to_string(),
+            retry_after: match self {
+                AppError::RateLimited { retry_after } => Some(*retry_after),
+                _ => None,
+            },
+            minimum_version: match self {
+                AppError::UpgradeRequired { minimum_version, .. } => Some(minimum_version.clone()),
+                _ => None,
+            },
+            download_url: match self {
+                AppError::UpgradeRequired { download_url, .. } => download_url.clone(),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::InvalidRequest(_) | AppError::InvalidState | AppError::InvalidAuthorizationCode => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::InvalidRefreshToken | AppError::InvalidIdToken(_) | AppError::InvalidContainerToken => {
+                StatusCode::UNAUTHORIZED
+            }
+            AppError::UpgradeRequired { .. } => StatusCode::FORBIDDEN,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::IdentityProviderUnavailable(_) | AppError::StsUnavailable(_) => StatusCode::BAD_GATEWAY,
+            AppError::StorageUnavailable(_) | AppError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::DataCorruption(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.insert_header(ContentType(PROBLEM_JSON.parse().unwrap()));
+
+        if let AppError::RateLimited { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+
+        response.json(self.problem())
+    }
+}
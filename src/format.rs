@@ -0,0 +1,47 @@
+//! Text formats of AWS credentials, shared by the service and the CLI.
+
+/// Formats a Unix timestamp as an ISO-8601 UTC date-time, e.g. `2099-01-01T00:00:00Z`.
+///
+/// Timestamps outside years 0 to 9999 are written as the plain number of seconds.
+pub fn iso_8601(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let time = secs.rem_euclid(86_400);
+
+    // Civil date of a day count since 1970-01-01, in the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    if !(0..=9999).contains(&year) {
+        return secs.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Formats credentials as shell `export` lines of the variables read by the AWS SDKs.
+pub fn env_exports(access_key_id: &str, secret_access_key: &str, session_token: &str, expires_at: i64) -> String {
+    format!(
+        "export AWS_ACCESS_KEY_ID='{}'\n\
+         export AWS_SECRET_ACCESS_KEY='{}'\n\
+         export AWS_SESSION_TOKEN='{}'\n\
+         export AWS_CREDENTIAL_EXPIRATION='{}'\n",
+        access_key_id,
+        secret_access_key,
+        session_token,
+        iso_8601(expires_at)
+    )
+}
@@ -1,164 +1,178 @@
-use crate::config::AppArgs;
-use crate::db::{redis_del, redis_get, redis_set_ex, RedisPool};
-use crate::handlers::auth::utils::{get_cli_session_key, get_cli_state_key, get_jwks_cache_key};
-use crate::schemas::auth::{CliAuthState, CliSessionData, IdTokenClaims, TokenResponse};
-use actix_web::{get, web, HttpResponse, Result};
-use jsonwebtoken::{decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::{AppArgs, SharedConfig};
+use crate::error::AppError;
+use crate::handlers::auth::utils::{generate_token, get_client_ip, hash_secret, unix_now};
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::pages::{Page, PageContext, Pages};
+use crate::schemas::error::ProblemDetails;
+use crate::schemas::auth::{CliAuthState, IdTokenClaims, PendingConfirmation, TokenResponse};
+use crate::store::StateStore;
+use actix_web::{web, HttpRequest, HttpResponse};
 use serde::Deserialize;
+use utoipa::IntoParams;
+
+/// Seconds the user has to confirm the device once logged in.
+const CONFIRMATION_TTL: u64 = 300;
 
 /// Query parameters for the CLI authentication callback.
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 pub struct AuthCallbackQuery {
     /// The authorization code returned by the identity provider.
-    pub code: String,
+    pub code: Option<String>,
     /// The state parameter used for CSRF protection and session tracking.
     pub state: String,
+    /// Error code returned by the identity provider instead of a code (e.g. `access_denied`).
+    pub error: Option<String>,
 }
 
 /// Handler for the CLI authentication callback.
 ///
 /// This endpoint is called by the identity provider after the user completes the login process.
-/// It exchanges the authorization code for tokens, validates the ID token, and stores
-/// the session data in Redis.
-#[get("/auth/cli/callback")]
-pub async fn auth_cli_callback(
+/// It exchanges the authorization code for tokens and validates the ID token, then shows a
+/// page asking the user to confirm the device that started the login; the CLI is only
+/// authenticated once they do (see `/auth/cli/confirm`). Failures are reported as problem
+/// details to clients that do not accept HTML.
+#[utoipa::path(
+    get,
+    path = "/auth/cli/callback",
+    tag = "auth",
+    summary = "CLI auth callback",
+    description = "Callback endpoint for the identity provider. Handles token exchange and session creation.",
+    params(AuthCallbackQuery),
+    responses(
+        (status = 200, description = "Device confirmation page", content_type = "text/html"),
+        (status = 400, description = "Invalid state or authorization code, or login denied", content(
+            (ProblemDetails = "application/problem+json"),
+            (String = "text/html")
+        )),
+        (status = 401, description = "Invalid ID token", content(
+            (ProblemDetails = "application/problem+json"),
+            (String = "text/html")
+        )),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Identity provider unavailable", content(
+            (ProblemDetails = "application/problem+json"),
+            (String = "text/html")
+        ))
+    )
+)]
+#[tracing::instrument(name = "auth_cli_callback", skip_all, fields(state = %query.state))]
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_cli_callback<S: StateStore + ?Sized>(
+    req: HttpRequest,
     query: web::Query<AuthCallbackQuery>,
-    redis_pool: web::Data<RedisPool>,
-    config: web::Data<AppArgs>,
-) -> Result<HttpResponse> {
-    // Exchange the authorization code for access, ID, and refresh tokens
-    let token_res = exchange_code_for_tokens(&query.code, &config).await?;
-
-    // Load and remove the original authentication state from Redis to prevent replay attacks
-    let auth_state = load_and_consume_state(&redis_pool, &query.state).await?;
-
-    // Fetch JWKS to validate the ID token signature
-    let jwks = fetch_jwks(&config, &redis_pool).await?;
-    let header = decode_header(&token_res.id_token).map_err(|e| {
-        log::error!("Failed to decode token header: {}", e);
-        actix_web::error::ErrorUnauthorized("Invalid token header")
-    })?;
-
-    let kid = header.kid.ok_or_else(|| {
-        actix_web::error::ErrorUnauthorized("Token missing kid")
-    })?;
-
-    let jwk = jwks.find(&kid).ok_or_else(|| {
-        actix_web::error::ErrorUnauthorized("Specified key not found in JWKS")
-    })?;
-
-    let decoding_key = DecodingKey::from_jwk(jwk).map_err(|e| {
-        log::error!("Failed to create decoding key from JWK: {}", e);
-        actix_web::error::ErrorInternalServerError("Key processing error")
-    })?;
-
-    // Validate the ID token and extract claims
-    let claims = validate_id_token(&token_res.id_token, &config, &decoding_key)?;
-
-    // Store session data and mark the CLI as authenticated
-    mark_cli_authenticated(&redis_pool, &query.state, &claims, &auth_state, token_res.refresh_token).await?;
-
-    Ok(HttpResponse::Ok().body("Authentication successful! You can now close this window."))
-}
-
-/// Fetches the JSON Web Key Set (JWKS) from the identity provider.
-async fn fetch_jwks(config: &AppArgs, redis_pool: &RedisPool) -> Result<JwkSet, actix_web::Error> {
-    let cache_key = get_jwks_cache_key(&config.cognito.user_pool_id);
-
-    // Try to get from Redis first
-    if let Ok(Some(jwks)) = redis_get::<JwkSet>(redis_pool, &cache_key).await {
-        return Ok(jwks);
+    states: web::Data<S>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    metrics: web::Data<Metrics>,
+    verifier: web::Data<IdTokenVerifier>,
+    pages: web::Data<Pages>,
+) -> Result<HttpResponse, AppError> {
+    let config = config.load_full();
+    let client_ip = get_client_ip(&req);
+
+    // The identity provider redirects with an error when the user did not log in
+    if let Some(error) = &query.error {
+        let auth_state = states.get_state(&query.state).await.ok().flatten();
+        let e = AppError::InvalidRequest(format!("the identity provider returned `{}`", error));
+        audit
+            .record(
+                AuditEvent::failure(AuditAction::Callback, e.to_string())
+                    .device(auth_state.as_ref().and_then(|s| s.device_name.as_deref()))
+                    .ip(client_ip.as_deref())
+                    .state(&query.state),
+            )
+            .await;
+        let page = if error == "access_denied" { Page::Denied } else { Page::Error };
+        return pages.render_error(&req, page, auth_state.as_ref(), e);
     }
 
-    let url = format!(
-        "https://cognito-idp.{}.amazonaws.com/{}/.well-known/jwks.json",
-        config.cognito.region, config.cognito.user_pool_id
-    );
-
-    let res = reqwest::get(url).await.map_err(|e| {
-        log::error!("Failed to fetch JWKS: {}", e);
-        actix_web::error::ErrorInternalServerError("Failed to fetch identity provider keys")
-    })?;
-
-    let jwks = res.json::<JwkSet>().await.map_err(|e| {
-        log::error!("Failed to parse JWKS: {}", e);
-        actix_web::error::ErrorInternalServerError("Failed to parse identity provider keys")
-    })?;
-
-    // Store in Redis with a 24-hour TTL
-    let _ = redis_set_ex(redis_pool, &cache_key, &jwks, 24 * 3600).await;
-
-    Ok(jwks)
+    // Peek at the state first, so that failure pages can still name the device
+    let pending_state = states.get_state(&query.state).await.ok().flatten();
+
+    match complete_callback(&query, states.get_ref(), &config, &metrics, &verifier).await {
+        Ok((claims, auth_state, token)) => {
+            audit
+                .record(
+                    AuditEvent::success(AuditAction::Callback)
+                        .user(&claims.sub, claims.email.as_deref())
+                        .device(auth_state.device_name.as_deref())
+                        .ip(client_ip.as_deref())
+                        .state(&query.state),
+                )
+                .await;
+
+            let context = PageContext::new(Some(&auth_state)).with_confirmation(
+                &query.state,
+                &token,
+                claims.email.as_deref(),
+            );
+            Ok(pages.render(Page::Confirm, &context))
+        }
+        Err(e) => {
+            audit
+                .record(
+                    AuditEvent::failure(AuditAction::Callback, e.to_string())
+                        .ip(client_ip.as_deref())
+                        .state(&query.state),
+                )
+                .await;
+
+            pages.render_error(&req, Page::for_error(&e), pending_state.as_ref(), e)
+        }
+    }
 }
 
-/// Stores the session data in Redis and marks the CLI as authenticated.
-async fn mark_cli_authenticated(
-    redis_pool: &RedisPool,
-    state: &str,
-    claims: &IdTokenClaims,
-    auth_state: &CliAuthState,
-    refresh_token: Option<String>,
-) -> Result<(), actix_web::Error> {
-    // 1. Store the main session indexed by 'sub' (subject) so it can be found during renewal
-    let session_key = get_cli_session_key(&claims.sub);
-    let session_value = CliSessionData {
+/// Exchanges the authorization code, validates the ID token and keeps the login until the
+/// user confirms the device.
+///
+/// Returns the validated claims, the authentication state and the confirmation token.
+#[tracing::instrument(skip_all)]
+async fn complete_callback<S: StateStore + ?Sized>(
+    query: &AuthCallbackQuery,
+    states: &S,
+    config: &AppArgs,
+    metrics: &Metrics,
+    verifier: &IdTokenVerifier,
+) -> Result<(IdTokenClaims, CliAuthState, String), AppError> {
+    // Exchange the authorization code for access, ID, and refresh tokens
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| AppError::InvalidRequest("missing field `code`".to_string()))?;
+    let token_res = exchange_code_for_tokens(code, config, metrics).await?;
+
+    // The state stays until the confirmation, which consumes it
+    let auth_state = states.get_state(&query.state).await?.ok_or(AppError::InvalidState)?;
+
+    // Validate the ID token against the user pool JWKS and extract claims
+    let claims = verifier.verify(&token_res.id_token).await?;
+
+    // Keep the login under a token that only the page served to this browser knows
+    let token = generate_token()?;
+    let confirmation = PendingConfirmation {
+        token_hash: hash_secret(&token),
         user_sub: claims.sub.clone(),
         email: claims.email.clone(),
-        device_name: auth_state.device_name.clone(),
-        refresh_token,
-        active: true,
+        refresh_token: token_res.refresh_token,
+        expires_at: unix_now() + CONFIRMATION_TTL as i64,
     };
+    // A login already waiting for the state keeps it, so that another cannot swap identities
+    if !states.put_confirmation(&query.state, &confirmation, CONFIRMATION_TTL).await? {
+        return Err(AppError::InvalidState);
+    }
 
-    // Increase TTL to 30 days to allow long-term session renewals
-    redis_set_ex(redis_pool, &session_key, &session_value, 30 * 24 * 3600).await?;
-
-    // 2. Create a temporary pointer from state to sub so the CLI can check the status
-    // This pointer has a short duration (e.g., 10 minutes)
-    let state_key = get_cli_session_key(state);
-    let state_value = serde_json::json!(claims.sub);
-    redis_set_ex(redis_pool, &state_key, &state_value, 600).await
-}
-
-/// Validates the ID token against the identity provider's configuration.
-fn validate_id_token(
-    token: &str,
-    config: &AppArgs,
-    decoding_key: &DecodingKey,
-) -> Result<IdTokenClaims, actix_web::Error> {
-    let mut validation = Validation::new(Algorithm::RS256);
-    validation.set_audience(&[config.cognito.client_id.clone()]);
-
-    let token_data = jsonwebtoken::decode::<IdTokenClaims>(token, decoding_key, &validation)
-        .map_err(|e| {
-            log::error!("Token validation failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Invalid token")
-        })?;
-
-    Ok(token_data.claims)
-}
-
-/// Loads the authentication state from Redis and removes it.
-async fn load_and_consume_state(
-    redis_pool: &RedisPool,
-    state: &str,
-) -> Result<CliAuthState, actix_web::Error> {
-    let key = get_cli_state_key(state);
-
-    let auth_state: CliAuthState = redis_get(redis_pool, &key)
-        .await?
-        .ok_or_else(|| actix_web::error::ErrorBadRequest("Invalid or expired state"))?;
-
-    // Delete the state after use to prevent reuse
-    redis_del(redis_pool, &key).await?;
-
-    Ok(auth_state)
+    Ok((claims, auth_state, token))
 }
 
 /// Exchanges an authorization code for tokens using the identity provider's token endpoint.
+#[tracing::instrument(name = "cognito.exchange_code", skip_all)]
 async fn exchange_code_for_tokens(
     code: &str,
     config: &AppArgs,
-) -> Result<TokenResponse, actix_web::Error> {
+    metrics: &Metrics,
+) -> Result<TokenResponse, AppError> {
     let client = reqwest::Client::new();
 
     let params = [
@@ -168,6 +182,11 @@ async fn exchange_code_for_tokens(
         ("redirect_uri", &config.cognito.redirect_uri),
     ];
 
+    let timer = metrics
+        .cognito_token_duration
+        .with_label_values(&["authorization_code"])
+        .start_timer();
+
     let res = client
         .post(format!(
             "{}/oauth2/token",
@@ -179,9 +198,11 @@ async fn exchange_code_for_tokens(
         .await
         .map_err(|e| {
             log::error!("Token request error: {}", e);
-            actix_web::error::ErrorBadGateway("Token endpoint error")
+            AppError::IdentityProviderUnavailable(format!("Token endpoint error: {}", e))
         })?;
 
+    timer.observe_duration();
+
     if !res.status().is_success() {
         let status = res.status();
         let error_body = res.text().await.unwrap_or_default();
@@ -190,13 +211,17 @@ async fn exchange_code_for_tokens(
             status,
             error_body
         );
-        return Err(actix_web::error::ErrorBadRequest(
-            "Invalid authorization code",
-        ));
+        if status.is_server_error() {
+            return Err(AppError::IdentityProviderUnavailable(format!(
+                "Token endpoint returned {}",
+                status
+            )));
+        }
+        return Err(AppError::InvalidAuthorizationCode);
     }
 
     res.json::<TokenResponse>().await.map_err(|e| {
         log::error!("JSON parse error: {}", e);
-        actix_web::error::ErrorBadGateway("Invalid token response")
+        AppError::IdentityProviderUnavailable(format!("Invalid token response: {}", e))
     })
 }
@@ -0,0 +1,132 @@
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::error::AppError;
+use crate::handlers::auth::utils::{get_client_ip, hash_secret, unix_now, CLI_SESSION_TTL};
+use crate::pages::{Page, PageContext, Pages};
+use crate::schemas::auth::{CliAuthState, CliConfirmForm, CliSessionData, ConfirmDecision, PendingConfirmation};
+use crate::schemas::error::ProblemDetails;
+use crate::store::{SessionStore, StateStore};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Handler for the device confirmation form.
+///
+/// The confirmation page shown by the callback posts here. Confirming stores the session
+/// and lets the CLI fetch its credentials; rejecting drops the login, which the CLI then
+/// sees as expired. Either way the login cannot be confirmed again.
+#[utoipa::path(
+    post,
+    path = "/auth/cli/confirm",
+    tag = "auth",
+    summary = "CLI auth confirm",
+    description = "Confirms or rejects the device that started a login, from the page shown by the callback.",
+    request_body(content = CliConfirmForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Login confirmed or rejected", content_type = "text/html"),
+        (status = 400, description = "Unknown, expired or already confirmed login", content(
+            (ProblemDetails = "application/problem+json"),
+            (String = "text/html")
+        )),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable", content(
+            (ProblemDetails = "application/problem+json"),
+            (String = "text/html")
+        ))
+    )
+)]
+#[tracing::instrument(name = "auth_cli_confirm", skip_all, fields(state = %form.state))]
+pub async fn auth_cli_confirm<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    req: HttpRequest,
+    form: web::Form<CliConfirmForm>,
+    states: web::Data<S>,
+    sessions: web::Data<T>,
+    audit: web::Data<AuditLog>,
+    pages: web::Data<Pages>,
+) -> Result<HttpResponse, AppError> {
+    let client_ip = get_client_ip(&req);
+    let pending_state = states.get_state(&form.state).await.ok().flatten();
+
+    let (confirmation, auth_state) = match take_login(&form, states.get_ref()).await {
+        Ok(login) => login,
+        Err(e) => {
+            audit
+                .record(
+                    AuditEvent::failure(AuditAction::Confirm, e.to_string())
+                        .ip(client_ip.as_deref())
+                        .state(&form.state),
+                )
+                .await;
+            return pages.render_error(&req, Page::for_error(&e), pending_state.as_ref(), e);
+        }
+    };
+
+    let event = AuditEvent::success(AuditAction::Confirm)
+        .user(&confirmation.user_sub, confirmation.email.as_deref())
+        .device(auth_state.device_name.as_deref())
+        .ip(client_ip.as_deref())
+        .state(&form.state);
+
+    if form.decision == ConfirmDecision::Reject {
+        audit.record(event.failed("The user rejected the device")).await;
+        let context = PageContext {
+            detail: Some("You cancelled this login, the terminal will not be signed in.".to_string()),
+            ..PageContext::new(Some(&auth_state))
+        };
+        return Ok(pages.render(Page::Denied, &context));
+    }
+
+    let stored = mark_cli_authenticated(states.get_ref(), sessions.get_ref(), &form.state, &confirmation, &auth_state);
+    if let Err(e) = stored.await {
+        audit.record(event.failed(e.to_string())).await;
+        return pages.render_error(&req, Page::Error, Some(&auth_state), e);
+    }
+
+    audit.record(event).await;
+    Ok(pages.render(Page::Success, &PageContext::new(Some(&auth_state))))
+}
+
+/// Checks the confirmation token, then removes the login and its state so that neither can
+/// be used twice.
+async fn take_login<S: StateStore + ?Sized>(
+    form: &CliConfirmForm,
+    states: &S,
+) -> Result<(PendingConfirmation, CliAuthState), AppError> {
+    // The token is checked on the login taken, so that no other can be swapped in meanwhile
+    let confirmation = states.take_confirmation(&form.state).await?.ok_or(AppError::InvalidState)?;
+    if confirmation.token_hash != hash_secret(&form.token) {
+        // A wrong token puts the login back for the browser holding the right one
+        let ttl = confirmation.expires_at - unix_now();
+        if ttl > 0 {
+            states.put_confirmation(&form.state, &confirmation, ttl as u64).await?;
+        }
+        return Err(AppError::InvalidState);
+    }
+
+    let auth_state = states.take_state(&form.state).await?.ok_or(AppError::InvalidState)?;
+    Ok((confirmation, auth_state))
+}
+
+/// Stores the session data and marks the CLI as authenticated.
+#[tracing::instrument(skip_all)]
+async fn mark_cli_authenticated<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    states: &S,
+    sessions: &T,
+    state: &str,
+    confirmation: &PendingConfirmation,
+    auth_state: &CliAuthState,
+) -> Result<(), AppError> {
+    // 1. Store the main session indexed by 'sub' (subject) so it can be found during renewal
+    let session_value = CliSessionData {
+        user_sub: confirmation.user_sub.clone(),
+        email: confirmation.email.clone(),
+        device_name: auth_state.device_name.clone(),
+        refresh_token: confirmation.refresh_token.clone(),
+        active: true,
+        cli_version: auth_state.cli_version.clone(),
+    };
+
+    // Increase TTL to 30 days to allow long-term session renewals
+    sessions.put_session(&session_value, CLI_SESSION_TTL).await?;
+
+    // 2. Create a temporary pointer from state to sub so the CLI can check the status
+    // This pointer has a short duration (e.g., 10 minutes)
+    states.link_session(state, &confirmation.user_sub, 600).await
+}
@@ -0,0 +1,107 @@
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::{AppArgs, SharedConfig};
+use crate::error::AppError;
+use crate::handlers::auth::cli_renew::verify_refresh_token;
+use crate::handlers::auth::utils::{get_client_ip, CLI_SESSION_TTL};
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::schemas::error::ProblemDetails;
+use crate::schemas::auth::CliLogoutRequest;
+use crate::store::SessionStore;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Handler for CLI logout.
+///
+/// This endpoint identifies the user from the refresh token, revokes the token in Cognito
+/// and marks the stored session as inactive so it can no longer obtain credentials.
+#[utoipa::path(
+    post,
+    path = "/auth/cli/logout",
+    tag = "auth",
+    summary = "CLI auth logout",
+    description = "Ends a CLI session by revoking its refresh token and deactivating the stored session.",
+    request_body = CliLogoutRequest,
+    responses(
+        (status = 204, description = "Session ended"),
+        (status = 401, description = "Invalid refresh token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+#[tracing::instrument(name = "auth_cli_logout", skip_all)]
+pub async fn auth_cli_logout<T: SessionStore + ?Sized>(
+    req: HttpRequest,
+    body: web::Json<CliLogoutRequest>,
+    sessions: web::Data<T>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    metrics: web::Data<Metrics>,
+    verifier: web::Data<IdTokenVerifier>,
+) -> Result<HttpResponse, AppError> {
+    let config = config.load_full();
+    let client_ip = get_client_ip(&req);
+
+    // 1. Identify the user owning the refresh token
+    let (_, claims) = match verify_refresh_token(&body.refresh_token, &config, &verifier, &metrics).await {
+        Ok(res) => res,
+        Err(e) => {
+            audit
+                .record(AuditEvent::failure(AuditAction::Logout, e.to_string()).ip(client_ip.as_deref()))
+                .await;
+            return Err(e);
+        }
+    };
+
+    // 2. Revoke the refresh token in Cognito; the session is deactivated regardless
+    if let Err(e) = revoke_refresh_token(&body.refresh_token, &config).await {
+        log::warn!("Could not revoke refresh token in Cognito: {}", e);
+    }
+
+    // 3. Deactivate the session so status and renew calls are denied
+    let session_data = sessions.get_session(&claims.sub).await?;
+
+    let event = AuditEvent::success(AuditAction::Logout)
+        .user(&claims.sub, claims.email.as_deref())
+        .ip(client_ip.as_deref());
+
+    if let Some(mut session) = session_data {
+        session.active = false;
+        session.refresh_token = None;
+
+        if let Err(e) = sessions.put_session(&session, CLI_SESSION_TTL).await {
+            audit.record(event.failed("Failed to deactivate session")).await;
+            return Err(e);
+        }
+        audit.record(event.device(session.device_name.as_deref())).await;
+    } else {
+        audit.record(event).await;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Revokes a refresh token using Cognito's OAuth2 revocation endpoint.
+#[tracing::instrument(name = "cognito.revoke_token", skip_all)]
+pub async fn revoke_refresh_token(refresh_token: &str, config: &AppArgs) -> Result<(), String> {
+    let client = reqwest::Client::new();
+    let params = [
+        ("token", refresh_token),
+        ("client_id", &config.cognito.client_id),
+    ];
+
+    let res = client
+        .post(format!(
+            "{}/oauth2/revoke",
+            config.cognito.domain.trim_end_matches('/')
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(format!("revocation endpoint returned {}", res.status()));
+    }
+
+    Ok(())
+}
@@ -1,112 +1,180 @@
-use crate::config::AppArgs;
-use crate::db::{redis_get, redis_set_ex, RedisPool};
-use crate::handlers::auth::utils::{
-    get_cli_session_key, get_jwks_cache_key, get_role_session_name, validate_cli_session,
-};
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::{AppArgs, SharedConfig};
+use crate::error::AppError;
+use crate::handlers::auth::output::CredentialOutput;
+use crate::handlers::auth::utils::{get_client_ip, validate_cli_session};
+use crate::handlers::auth::version::{check_cli_version, warn_deprecated};
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::schemas::error::ProblemDetails;
 use crate::schemas::auth::{
-    CliAuthResponse, CliRenewRequest, CliSessionData, IdTokenClaims, TokenResponse,
+    CliAuthResponse, CliRenewRequest, CredentialFormatQuery, CredentialProcessOutput, IdTokenClaims, TokenResponse,
 };
-use actix_web::{post, web, HttpResponse, Responder};
-use jsonwebtoken::{decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use crate::store::{SessionStore, StateStore};
+use crate::sts::{session_credentials, CredentialCache};
+use actix_web::{web, HttpRequest, HttpResponse};
 
 /// Handler for CLI session renewal.
 ///
 /// This endpoint allows a CLI client to exchange a Cognito refresh token for new
-/// credentials, including AWS STS temporary credentials.
-#[post("/auth/cli/renew")]
-pub async fn auth_cli_renew(
+/// credentials, including AWS STS temporary credentials (reused from the credential cache
+/// while they have enough lifetime left).
+#[utoipa::path(
+    post,
+    path = "/auth/cli/renew",
+    tag = "auth",
+    summary = "CLI auth renew",
+    description = "Renews an expired CLI session using a refresh token to obtain new AWS STS credentials, in the \
+        format chosen with `format` or `Accept` (see `/auth/cli/status`).",
+    params(CredentialFormatQuery),
+    request_body = CliRenewRequest,
+    responses(
+        (status = 200, description = "Session renewed", content(
+            (CliAuthResponse = "application/json"),
+            (CredentialProcessOutput = "application/x-aws-credential-process+json"),
+            (String = "text/x-shellscript"),
+            (String = "text/x-aws-credentials")
+        )),
+        (status = 401, description = "Invalid refresh token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "CLI version below the supported minimum", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Identity provider or STS unavailable", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+#[tracing::instrument(name = "auth_cli_renew", skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_cli_renew<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    req: HttpRequest,
     body: web::Json<CliRenewRequest>,
+    format: web::Query<CredentialFormatQuery>,
     sts_client: web::Data<aws_sdk_sts::Client>,
-    redis_pool: web::Data<RedisPool>,
-    config: web::Data<AppArgs>,
-) -> impl Responder {
-    // 1. Exchange the Cognito refresh_token for new tokens (id_token, access_token)
-    // This automatically validates that the refresh_token is valid and has not been revoked in Cognito.
-    let token_res = match refresh_cognito_tokens(&body.refresh_token, &config).await {
+    cache: web::Data<CredentialCache>,
+    states: web::Data<S>,
+    sessions: web::Data<T>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    metrics: web::Data<Metrics>,
+    verifier: web::Data<IdTokenVerifier>,
+) -> Result<HttpResponse, AppError> {
+    let config = config.load_full();
+    let client_ip = get_client_ip(&req);
+    let output = CredentialOutput::negotiate(&req, &format)?;
+
+    // 0. Turn away CLIs sending a version older than the minimum
+    if let Err(e) = check_cli_version(&req, None, &config.cli) {
+        audit
+            .record(AuditEvent::failure(AuditAction::Renew, e.to_string()).ip(client_ip.as_deref()))
+            .await;
+        return Err(e);
+    }
+
+    // 1-2. Exchange the refresh token and validate the resulting ID token
+    let (token_res, claims) = match verify_refresh_token(&body.refresh_token, &config, &verifier, &metrics).await {
         Ok(res) => res,
         Err(e) => {
-            log::error!("Error refreshing Cognito tokens: {}", e);
-            return HttpResponse::Unauthorized().body("Invalid or expired refresh token");
+            audit
+                .record(AuditEvent::failure(AuditAction::Renew, e.to_string()).ip(client_ip.as_deref()))
+                .await;
+            return Err(e);
         }
     };
 
-    // 2. Validate the new ID Token against JWKS to ensure identity
-    let jwks = match fetch_jwks(&config, &redis_pool).await {
-        Ok(j) => j,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to fetch JWKS"),
-    };
-
-    let header = match decode_header(&token_res.id_token) {
-        Ok(h) => h,
-        Err(_) => return HttpResponse::Unauthorized().body("Invalid ID token header from Cognito"),
-    };
-
-    let kid = match header.kid {
-        Some(k) => k,
-        None => return HttpResponse::Unauthorized().body("ID token missing kid"),
-    };
-
-    let jwk = match jwks.find(&kid) {
-        Some(j) => j,
-        None => return HttpResponse::Unauthorized().body("Key not found in JWKS"),
-    };
+    // 3. Load the session using the 'sub' (unique user identifier)
+    let session_data = sessions.get_session(&claims.sub).await?;
 
-    let decoding_key = match DecodingKey::from_jwk(jwk) {
-        Ok(d) => d,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-
-    let claims = match validate_token(&token_res.id_token, &config, &decoding_key) {
-        Ok(c) => c,
-        Err(_) => return HttpResponse::Unauthorized().body("Invalid ID token claims"),
+    // CLIs that do not send their version are checked against the one recorded at login
+    let recorded_version = session_data.as_ref().and_then(|s| s.cli_version.clone());
+    let deprecation = match check_cli_version(&req, recorded_version.as_deref(), &config.cli) {
+        Ok(deprecation) => deprecation,
+        Err(e) => {
+            audit
+                .record(
+                    AuditEvent::failure(AuditAction::Renew, e.to_string())
+                        .user(&claims.sub, claims.email.as_deref())
+                        .ip(client_ip.as_deref()),
+                )
+                .await;
+            return Err(e);
+        }
     };
-
-    // 3. Load the session from Redis using the 'sub' (unique user identifier)
-    let session_data = load_session(&claims.sub, &redis_pool).await;
     let session = match validate_cli_session(session_data) {
         Ok(s) => s,
-        Err(res) => return res,
+        Err(status) => {
+            if let CliAuthResponse::DENIED = status {
+                audit
+                    .record(
+                        AuditEvent::failure(AuditAction::Deny, "Session is no longer active")
+                            .user(&claims.sub, claims.email.as_deref())
+                            .ip(client_ip.as_deref()),
+                    )
+                    .await;
+            }
+            metrics.observe_auth_response(&status);
+            return Ok(warn_deprecated(output.respond(status), deprecation));
+        }
     };
 
-    // 4. Generate AWS STS temporary credentials
-    let role_session_name = get_role_session_name(&session.user_sub);
+    // 4. Hand out AWS STS temporary credentials, reusing cached ones when possible
+    let event = AuditEvent::success(AuditAction::Renew)
+        .user(&session.user_sub, session.email.as_deref())
+        .device(session.device_name.as_deref())
+        .ip(client_ip.as_deref())
+        .role_arn(&config.sts.role_arn);
 
-    let creds = match sts_client
-        .assume_role()
-        .role_arn(&config.sts.role_arn)
-        .role_session_name(&role_session_name)
-        .set_external_id(config.sts.external_id.clone())
-        .send()
-        .await
+    let creds = match session_credentials(&session, &sts_client, &config, &**states, &cache, &metrics).await
     {
-        Ok(out) => match out.credentials {
-            Some(c) => c,
-            None => return HttpResponse::InternalServerError().finish(),
-        },
+        Ok(creds) => creds,
         Err(e) => {
-            log::error!("STS error: {:?}", e);
-            return HttpResponse::InternalServerError().finish();
+            audit.record(event.failed("Failed to assume role")).await;
+            return Err(e);
         }
     };
 
+    audit.record(event).await;
+
     // If Cognito does not return a new refresh_token (normal unless rotation is enabled),
     // we keep the existing one.
     let next_refresh_token = token_res.refresh_token.unwrap_or_else(|| body.refresh_token.clone());
 
-    HttpResponse::Ok().json(CliAuthResponse::AUTHORIZED {
-        access_key_id: creds.access_key_id().to_string(),
-        secret_access_key: creds.secret_access_key().to_string(),
-        session_token: creds.session_token().to_string(),
-        expires_at: creds.expiration().secs(),
+    let response = CliAuthResponse::AUTHORIZED {
+        access_key_id: creds.access_key_id,
+        secret_access_key: creds.secret_access_key,
+        session_token: creds.session_token,
+        expires_at: creds.expires_at,
         refresh_token: Some(next_refresh_token),
-    })
+    };
+    metrics.observe_auth_response(&response);
+
+    Ok(warn_deprecated(output.respond(response), deprecation))
+}
+
+/// Exchanges a refresh token with Cognito and validates the returned ID token.
+///
+/// Returns the new tokens together with the validated claims identifying the user.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn verify_refresh_token(
+    refresh_token: &str,
+    config: &AppArgs,
+    verifier: &IdTokenVerifier,
+    metrics: &Metrics,
+) -> Result<(TokenResponse, IdTokenClaims), AppError> {
+    // Exchange the Cognito refresh_token for new tokens (id_token, access_token)
+    // This automatically validates that the refresh_token is valid and has not been revoked in Cognito.
+    let token_res = refresh_cognito_tokens(refresh_token, config, metrics).await?;
+
+    // Validate the new ID Token against JWKS to ensure identity
+    let claims = verifier.verify(&token_res.id_token).await?;
+
+    Ok((token_res, claims))
 }
 
 /// Exchanges a refresh token for new tokens using Cognito's OAuth2 endpoint.
+#[tracing::instrument(name = "cognito.refresh_tokens", skip_all)]
 async fn refresh_cognito_tokens(
     refresh_token: &str,
     config: &AppArgs,
-) -> Result<TokenResponse, actix_web::Error> {
+    metrics: &Metrics,
+) -> Result<TokenResponse, AppError> {
     let client = reqwest::Client::new();
     let params = [
         ("grant_type", "refresh_token"),
@@ -114,6 +182,11 @@ async fn refresh_cognito_tokens(
         ("refresh_token", refresh_token),
     ];
 
+    let timer = metrics
+        .cognito_token_duration
+        .with_label_values(&["refresh_token"])
+        .start_timer();
+
     let res = client
         .post(format!(
             "{}/oauth2/token",
@@ -125,9 +198,11 @@ async fn refresh_cognito_tokens(
         .await
         .map_err(|e| {
             log::error!("Token refresh request error: {}", e);
-            actix_web::error::ErrorBadGateway("Cognito token endpoint error")
+            AppError::IdentityProviderUnavailable(format!("Cognito token endpoint error: {}", e))
         })?;
 
+    timer.observe_duration();
+
     if !res.status().is_success() {
         let status = res.status();
         let error_body = res.text().await.unwrap_or_default();
@@ -136,65 +211,17 @@ async fn refresh_cognito_tokens(
             status,
             error_body
         );
-        return Err(actix_web::error::ErrorUnauthorized("Invalid refresh token"));
+        if status.is_server_error() {
+            return Err(AppError::IdentityProviderUnavailable(format!(
+                "Cognito token endpoint returned {}",
+                status
+            )));
+        }
+        return Err(AppError::InvalidRefreshToken);
     }
 
     res.json::<TokenResponse>().await.map_err(|e| {
         log::error!("JSON parse error during token refresh: {}", e);
-        actix_web::error::ErrorBadGateway("Invalid token response from Cognito")
-    })
-}
-
-/// Fetches the JSON Web Key Set (JWKS) from Cognito.
-async fn fetch_jwks(config: &AppArgs, redis_pool: &RedisPool) -> Result<JwkSet, actix_web::Error> {
-    let cache_key = get_jwks_cache_key(&config.cognito.user_pool_id);
-
-    // Try to get from Redis first
-    if let Ok(Some(jwks)) = redis_get::<JwkSet>(redis_pool, &cache_key).await {
-        return Ok(jwks);
-    }
-
-    let url = format!(
-        "https://cognito-idp.{}.amazonaws.com/{}/.well-known/jwks.json",
-        config.cognito.region, config.cognito.user_pool_id
-    );
-
-    let jwks = reqwest::get(url)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to fetch JWKS: {}", e);
-            actix_web::error::ErrorInternalServerError("Failed to fetch identity provider keys")
-        })?
-        .json::<JwkSet>()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to parse JWKS: {}", e);
-            actix_web::error::ErrorInternalServerError("Failed to parse identity provider keys")
-        })?;
-
-    // Store in Redis with a 24-hour TTL
-    let _ = redis_set_ex(redis_pool, &cache_key, &jwks, 24 * 3600).await;
-
-    Ok(jwks)
-}
-
-/// Validates an ID token against the identity provider's configuration.
-fn validate_token(
-    token: &str,
-    config: &AppArgs,
-    decoding_key: &DecodingKey,
-) -> Result<IdTokenClaims, jsonwebtoken::errors::Error> {
-    let mut validation = Validation::new(Algorithm::RS256);
-    validation.set_audience(&[config.cognito.client_id.clone()]);
-    let token_data = jsonwebtoken::decode::<IdTokenClaims>(token, decoding_key, &validation)?;
-    Ok(token_data.claims)
-}
-
-/// Loads session data from Redis for a given user subject.
-async fn load_session(sub: &str, redis_pool: &RedisPool) -> Option<CliSessionData> {
-    let key = get_cli_session_key(sub);
-    redis_get::<CliSessionData>(redis_pool, &key).await.unwrap_or_else(|e| {
-        log::error!("Error loading session from Redis: {}", e);
-        None
+        AppError::IdentityProviderUnavailable(format!("Invalid token response from Cognito: {}", e))
     })
 }
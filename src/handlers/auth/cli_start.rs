@@ -1,21 +1,69 @@
-use crate::config::AppArgs;
-use crate::db::{redis_set_ex, RedisPool};
-use crate::handlers::auth::utils::get_cli_state_key;
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::SharedConfig;
+use crate::error::AppError;
+use crate::handlers::auth::utils::{generate_verification_code, get_client_ip};
+use crate::handlers::auth::version::{check_cli_version, reported_cli_version, warn_deprecated};
+use crate::schemas::error::ProblemDetails;
 use crate::schemas::auth::{CliAuthStartRequest, CliAuthStartResponse, CliAuthState};
-use actix_web::{post, web, HttpResponse, Result};
+use crate::shutdown::Shutdown;
+use crate::store::StateStore;
+use actix_web::{web, HttpRequest, HttpResponse};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 /// Handler to initiate the CLI authentication process.
 ///
-/// This endpoint generates a unique state, stores the device information in Redis,
-/// and returns an authorization URL that the user must open in their browser.
-#[post("/auth/cli/start")]
-pub async fn auth_cli_start(
+/// This endpoint generates a unique state, stores the device information in the state store,
+/// and returns an authorization URL that the user must open in their browser, together with
+/// the verification code the browser shows before the user confirms the device.
+#[utoipa::path(
+    post,
+    path = "/auth/cli/start",
+    tag = "auth",
+    summary = "CLI auth start",
+    description = "Initiates the CLI authentication process. Returns an authorization URL for the browser.",
+    request_body = CliAuthStartRequest,
+    responses(
+        (status = 200, description = "Authentication started", body = CliAuthStartResponse),
+        (status = 400, description = "Malformed request", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "CLI version below the supported minimum", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable or server shutting down", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+#[tracing::instrument(name = "auth_cli_start", skip_all)]
+pub async fn auth_cli_start<S: StateStore + ?Sized>(
+    req: HttpRequest,
     payload: web::Json<CliAuthStartRequest>,
-    redis_pool: web::Data<RedisPool>,
-    config: web::Data<AppArgs>,
-) -> Result<HttpResponse> {
+    states: web::Data<S>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    shutdown: web::Data<Shutdown>,
+) -> Result<HttpResponse, AppError> {
+    // Logins started now could not finish before the server stops
+    if shutdown.is_draining() {
+        shutdown.reject_login();
+        return Err(AppError::ShuttingDown);
+    }
+
+    let config = config.load_full();
+    let client_ip = get_client_ip(&req);
+
+    // Turn away CLIs older than the minimum version before they send the user to log in
+    let deprecation = match check_cli_version(&req, payload.cli_version.as_deref(), &config.cli) {
+        Ok(deprecation) => deprecation,
+        Err(e) => {
+            audit
+                .record(
+                    AuditEvent::failure(AuditAction::Start, e.to_string())
+                        .device(payload.device_name.as_deref())
+                        .ip(client_ip.as_deref()),
+                )
+                .await;
+            return Err(e);
+        }
+    };
+
     // Generate a unique state for this authentication request
     let state = Uuid::new_v4().to_string();
     let ttl_seconds = 300; // 5 minutes expiration
@@ -25,17 +73,37 @@ pub async fn auth_cli_start(
         .map(|d| d.as_secs() as i64)
         .unwrap_or_else(|_| 0);
 
-    // Prepare the state data to be stored in Redis
+    // Prepare the state data to be stored, with the details shown on the confirmation page
     let auth_state = CliAuthState {
         device_name: payload.device_name.clone(),
         os: payload.os.clone(),
-        cli_version: payload.cli_version.clone(),
+        cli_version: reported_cli_version(&req, payload.cli_version.as_deref()),
         created_at: now,
+        ip: client_ip.clone(),
+        verification_code: generate_verification_code()?,
     };
 
-    // Store the state in Redis with a TTL
-    let key = get_cli_state_key(&state);
-    redis_set_ex(&redis_pool, &key, &auth_state, ttl_seconds as u64).await?;
+    // Store the state with a TTL
+    if let Err(e) = states.put_state(&state, &auth_state, ttl_seconds as u64).await {
+        audit
+            .record(
+                AuditEvent::failure(AuditAction::Start, e.to_string())
+                    .device(auth_state.device_name.as_deref())
+                    .ip(client_ip.as_deref())
+                    .state(&state),
+            )
+            .await;
+        return Err(e);
+    }
+
+    audit
+        .record(
+            AuditEvent::success(AuditAction::Start)
+                .device(auth_state.device_name.as_deref())
+                .ip(client_ip.as_deref())
+                .state(&state),
+        )
+        .await;
 
     // Build the authorization URL for Cognito
     let auth_url = format!(
@@ -46,8 +114,11 @@ pub async fn auth_cli_start(
         state
     );
 
-    Ok(HttpResponse::Ok().json(CliAuthStartResponse {
+    let response = HttpResponse::Ok().json(CliAuthStartResponse {
         auth_url,
         expires_in: ttl_seconds as u64,
-    }))
+        interval: config.rate_limit.poll_interval_secs,
+        verification_code: auth_state.verification_code,
+    });
+    Ok(warn_deprecated(response, deprecation))
 }
@@ -1,95 +1,144 @@
-use crate::config::AppArgs;
-use crate::db::{redis_del, redis_get, RedisPool};
-use crate::handlers::auth::utils::{
-    get_cli_session_key, get_cli_state_key, get_role_session_name, validate_cli_session,
-};
-use crate::schemas::auth::{CliAuthResponse, CliAuthState, CliSessionData, CliStatusQuery};
-use actix_web::{get, web, HttpResponse, Responder};
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::SharedConfig;
+use crate::error::AppError;
+use crate::handlers::auth::output::CredentialOutput;
+use crate::handlers::auth::utils::{get_client_ip, validate_cli_session};
+use crate::handlers::auth::version::{check_cli_version, warn_deprecated};
+use crate::metrics::Metrics;
+use crate::schemas::error::ProblemDetails;
+use crate::schemas::auth::{CliAuthResponse, CliStatusQuery, CredentialFormatQuery, CredentialProcessOutput};
+use crate::store::{SessionStore, StateStore};
+use crate::sts::{session_credentials, CredentialCache};
+use actix_web::{web, HttpRequest, HttpResponse};
 
 /// Handler for checking CLI authentication status.
 ///
 /// The CLI polls this endpoint to check if the user has completed the authentication
-/// process in the browser. If authorized, it returns AWS STS credentials.
-#[get("/auth/cli/status")]
-pub async fn auth_cli_status(
+/// process in the browser. If authorized, it returns AWS STS credentials, reused from the
+/// credential cache while they have enough lifetime left.
+/// Clients polling faster than the configured interval receive `SLOW_DOWN`.
+#[utoipa::path(
+    get,
+    path = "/auth/cli/status",
+    tag = "auth",
+    summary = "CLI auth status",
+    description = "Polls the authentication status for a specific state. Returns AWS STS credentials if authorized, \
+        as `CliAuthResponse` JSON or, chosen with `format` or `Accept`, as `credential_process` JSON, shell exports or \
+        a `~/.aws/credentials` profile.",
+    params(CliStatusQuery, CredentialFormatQuery),
+    responses(
+        (status = 200, description = "Current authentication status", content(
+            (CliAuthResponse = "application/json"),
+            (CredentialProcessOutput = "application/x-aws-credential-process+json"),
+            (String = "text/x-shellscript"),
+            (String = "text/x-aws-credentials")
+        )),
+        (status = 403, description = "CLI version below the supported minimum", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "STS unavailable", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Storage unavailable", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+#[tracing::instrument(name = "auth_cli_status", skip_all, fields(state = %query.state))]
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_cli_status<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    req: HttpRequest,
     query: web::Query<CliStatusQuery>,
-    redis_pool: web::Data<RedisPool>,
+    format: web::Query<CredentialFormatQuery>,
+    states: web::Data<S>,
+    sessions: web::Data<T>,
     sts_client: web::Data<aws_sdk_sts::Client>,
-    config: web::Data<AppArgs>,
-) -> impl Responder {
-    let state_key = get_cli_session_key(&query.state);
+    cache: web::Data<CredentialCache>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, AppError> {
+    let config = config.load_full();
+    let output = CredentialOutput::negotiate(&req, &format)?;
+    // Older CLIs are turned away, deprecated ones are warned on every answer
+    let deprecation = check_cli_version(&req, None, &config.cli)?;
+    let reply = |status| warn_deprecated(respond(&metrics, &output, status), deprecation.clone());
+
+    // 0. Enforce the minimum poll interval for this state
+    let interval = config.rate_limit.poll_interval_secs;
+    if interval > 0 && !states.throttle_poll(&query.state, interval).await? {
+        return Ok(reply(CliAuthResponse::SLOW_DOWN { interval }));
+    }
 
     // 1. Try to get the user_sub (the pointer stored during the callback)
-    let user_sub: Option<String> = match redis_get(&redis_pool, &state_key).await {
-        Ok(data) => data,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+    let user_sub = states.linked_session(&query.state).await?;
 
     let sub = match user_sub {
         Some(s) => s,
         None => {
-            // Check if the initial state still exists in Redis
-            let initial_state_key = get_cli_state_key(&query.state);
-            let initial_state: Option<CliAuthState> = match redis_get(&redis_pool, &initial_state_key).await
-            {
-                Ok(data) => data,
-                Err(_) => return HttpResponse::InternalServerError().finish(),
-            };
+            // Check if the initial state still exists
+            let initial_state = states.get_state(&query.state).await?;
 
             // If the state is gone, the session is expired or never existed
             if initial_state.is_none() {
-                return HttpResponse::Ok().json(CliAuthResponse::EXPIRED);
+                return Ok(reply(CliAuthResponse::EXPIRED));
             }
             // If the state exists but no sub is linked yet, authentication is still pending
-            return HttpResponse::Ok().json(CliAuthResponse::PENDING);
+            return Ok(reply(CliAuthResponse::PENDING));
         }
     };
 
     // 2. Retrieve the actual session data using the subject (sub)
-    let session_key = get_cli_session_key(&sub);
-    let session_data: Option<CliSessionData> = match redis_get(&redis_pool, &session_key).await {
-        Ok(data) => data,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+    let session_data = sessions.get_session(&sub).await?;
+
+    let client_ip = get_client_ip(&req);
 
     // Validate that the session is still active
     let session = match validate_cli_session(session_data) {
         Ok(s) => s,
-        Err(res) => return res,
+        Err(status) => {
+            if let CliAuthResponse::DENIED = status {
+                audit
+                    .record(
+                        AuditEvent::failure(AuditAction::Deny, "Session is no longer active")
+                            .user(&sub, None)
+                            .ip(client_ip.as_deref())
+                            .state(&query.state),
+                    )
+                    .await;
+            }
+            return Ok(reply(status));
+        }
     };
 
-    // 3. Generate AWS STS temporary credentials for the CLI
-    let role_session_name = get_role_session_name(&session.user_sub);
-
-    let creds = match sts_client
-        .assume_role()
+    // 3. Hand out AWS STS temporary credentials, reusing cached ones when possible
+    let event = AuditEvent::success(AuditAction::StatusAuthorized)
+        .user(&session.user_sub, session.email.as_deref())
+        .device(session.device_name.as_deref())
+        .ip(client_ip.as_deref())
         .role_arn(&config.sts.role_arn)
-        .role_session_name(role_session_name.clone())
-        .set_external_id(config.sts.external_id.clone())
-        .send()
-        .await
+        .state(&query.state);
+
+    let creds = match session_credentials(&session, &sts_client, &config, &**states, &cache, &metrics).await
     {
-        Ok(output) => match output.credentials {
-            Some(c) => c,
-            None => {
-                log::error!("STS response missing credentials");
-                return HttpResponse::InternalServerError().finish();
-            }
-        },
+        Ok(creds) => creds,
         Err(e) => {
-            log::error!("Failed to assume role: {:?}", e);
-            return HttpResponse::InternalServerError().finish();
+            audit.record(event.failed("Failed to assume role")).await;
+            return Err(e);
         }
     };
 
     // Remove the temporary state pointer after successful authorization
-    let _ = redis_del(&redis_pool, &state_key).await;
+    let _ = states.unlink_session(&query.state).await;
 
-    HttpResponse::Ok().json(CliAuthResponse::AUTHORIZED {
-        access_key_id: creds.access_key_id().to_string(),
-        secret_access_key: creds.secret_access_key().to_string(),
-        session_token: creds.session_token().to_string(),
-        expires_at: creds.expiration().secs(),
+    audit.record(event).await;
+
+    Ok(reply(CliAuthResponse::AUTHORIZED {
+        access_key_id: creds.access_key_id,
+        secret_access_key: creds.secret_access_key,
+        session_token: creds.session_token,
+        expires_at: creds.expires_at,
         refresh_token: session.refresh_token,
-    })
+    }))
+}
+
+/// Counts the status in the metrics and writes it in the requested format.
+fn respond(metrics: &Metrics, output: &CredentialOutput, status: CliAuthResponse) -> HttpResponse {
+    metrics.observe_auth_response(&status);
+    output.respond(status)
 }
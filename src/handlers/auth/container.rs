@@ -0,0 +1,223 @@
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::config::{AppArgs, SharedConfig};
+use crate::error::AppError;
+use crate::handlers::auth::cli_renew::verify_refresh_token;
+use crate::handlers::auth::utils::{
+    generate_token, get_client_ip, hash_secret, session_fingerprint, validate_cli_session, CLI_SESSION_TTL,
+};
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::schemas::auth::{
+    ContainerCredentials, ContainerTokenData, ContainerTokenRequest, ContainerTokenResponse,
+};
+use crate::schemas::error::ProblemDetails;
+use crate::store::{SessionStore, StateStore};
+use crate::sts::{session_credentials, CredentialCache};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use mega_uploader_auth::format::iso_8601;
+use reqwest::Url;
+
+/// Path of the container credentials endpoint.
+pub const CONTAINER_CREDENTIALS_PATH: &str = "/auth/cli/container-credentials";
+
+/// Handler issuing a container credentials token for a CLI session.
+///
+/// The token lets any AWS SDK fetch the session's credentials through
+/// `AWS_CONTAINER_CREDENTIALS_FULL_URI` and `AWS_CONTAINER_AUTHORIZATION_TOKEN`, without a
+/// custom credential plugin. It stays valid while the session it was issued for is active.
+#[utoipa::path(
+    post,
+    path = "/auth/cli/container-token",
+    tag = "auth",
+    summary = "CLI container token",
+    description = "Exchanges a refresh token for a bearer token accepted by `/auth/cli/container-credentials`, \
+        together with the values to export as `AWS_CONTAINER_CREDENTIALS_FULL_URI` and \
+        `AWS_CONTAINER_AUTHORIZATION_TOKEN`.",
+    request_body = ContainerTokenRequest,
+    responses(
+        (status = 200, description = "Token issued", body = ContainerTokenResponse),
+        (status = 401, description = "Invalid refresh token or inactive session", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Identity provider unavailable", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+#[tracing::instrument(name = "auth_cli_container_token", skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_cli_container_token<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    req: HttpRequest,
+    body: web::Json<ContainerTokenRequest>,
+    states: web::Data<S>,
+    sessions: web::Data<T>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    metrics: web::Data<Metrics>,
+    verifier: web::Data<IdTokenVerifier>,
+) -> Result<HttpResponse, AppError> {
+    let config = config.load_full();
+    let client_ip = get_client_ip(&req);
+
+    // 1. Identify the user from the refresh token
+    let (token_res, claims) = match verify_refresh_token(&body.refresh_token, &config, &verifier, &metrics).await {
+        Ok(res) => res,
+        Err(e) => {
+            audit
+                .record(AuditEvent::failure(AuditAction::ContainerToken, e.to_string()).ip(client_ip.as_deref()))
+                .await;
+            return Err(e);
+        }
+    };
+
+    // 2. Only active sessions get a token
+    let session = match validate_cli_session(sessions.get_session(&claims.sub).await?) {
+        Ok(s) => s,
+        Err(_) => {
+            audit
+                .record(
+                    AuditEvent::failure(AuditAction::Deny, "Session is no longer active")
+                        .user(&claims.sub, claims.email.as_deref())
+                        .ip(client_ip.as_deref()),
+                )
+                .await;
+            return Err(AppError::InvalidRefreshToken);
+        }
+    };
+
+    // 3. Store the token by its hash, bound to the current session
+    let token = generate_token()?;
+
+    let data = ContainerTokenData {
+        user_sub: session.user_sub.clone(),
+        session_fingerprint: session_fingerprint(&session),
+    };
+    states.put_container_token(&hash_secret(&token), &data, CLI_SESSION_TTL).await?;
+
+    audit
+        .record(
+            AuditEvent::success(AuditAction::ContainerToken)
+                .user(&session.user_sub, session.email.as_deref())
+                .device(session.device_name.as_deref())
+                .ip(client_ip.as_deref()),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(ContainerTokenResponse {
+        token,
+        credentials_uri: credentials_uri(&config)?,
+        expires_in: CLI_SESSION_TTL,
+        refresh_token: token_res.refresh_token,
+    }))
+}
+
+/// Handler serving credentials in the container credentials provider format.
+///
+/// The token is only honoured while the session it was issued for is active, so that logged
+/// out, revoked and replaced sessions stop receiving credentials. The refresh token of the
+/// session is not exchanged here: it belongs to the CLI, and rotating it would lose it. The
+/// STS credentials come from the credential cache while they have enough lifetime left.
+#[utoipa::path(
+    get,
+    path = "/auth/cli/container-credentials",
+    tag = "auth",
+    summary = "Container credentials",
+    description = "Returns AWS STS credentials in the format of the ECS container credentials provider. The token \
+        from `/auth/cli/container-token` goes in the `Authorization` header, with or without a `Bearer` prefix.",
+    params(("Authorization" = String, Header, description = "Container credentials token")),
+    responses(
+        (status = 200, description = "Credentials of the session", body = ContainerCredentials),
+        (status = 401, description = "Invalid token or inactive session", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "STS unavailable", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+#[tracing::instrument(name = "auth_cli_container_credentials", skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn auth_cli_container_credentials<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    req: HttpRequest,
+    states: web::Data<S>,
+    sessions: web::Data<T>,
+    sts_client: web::Data<aws_sdk_sts::Client>,
+    cache: web::Data<CredentialCache>,
+    config: web::Data<SharedConfig>,
+    audit: web::Data<AuditLog>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, AppError> {
+    let config = config.load_full();
+    let client_ip = get_client_ip(&req);
+
+    // 1. Resolve the token to the session it was issued for
+    let Some(token) = bearer_token(&req) else {
+        return Err(AppError::InvalidContainerToken);
+    };
+    let Some(data) = states.get_container_token(&hash_secret(token)).await? else {
+        audit
+            .record(
+                AuditEvent::failure(AuditAction::ContainerCredentials, "Unknown container token")
+                    .ip(client_ip.as_deref()),
+            )
+            .await;
+        return Err(AppError::InvalidContainerToken);
+    };
+
+    let session = validate_cli_session(sessions.get_session(&data.user_sub).await?)
+        .ok()
+        .filter(|s| session_fingerprint(s) == data.session_fingerprint);
+    let Some(session) = session else {
+        audit
+            .record(
+                AuditEvent::failure(AuditAction::Deny, "Session is no longer active")
+                    .user(&data.user_sub, None)
+                    .ip(client_ip.as_deref()),
+            )
+            .await;
+        return Err(AppError::InvalidContainerToken);
+    };
+
+    let event = AuditEvent::success(AuditAction::ContainerCredentials)
+        .user(&session.user_sub, session.email.as_deref())
+        .device(session.device_name.as_deref())
+        .ip(client_ip.as_deref())
+        .role_arn(&config.sts.role_arn);
+
+    // 2. Hand out AWS STS temporary credentials, reusing cached ones when possible
+    let creds = match session_credentials(&session, &sts_client, &config, &**states, &cache, &metrics).await
+    {
+        Ok(creds) => creds,
+        Err(e) => {
+            audit.record(event.failed("Failed to assume role")).await;
+            return Err(e);
+        }
+    };
+
+    audit.record(event).await;
+
+    Ok(HttpResponse::Ok().json(ContainerCredentials {
+        access_key_id: creds.access_key_id,
+        secret_access_key: creds.secret_access_key,
+        token: creds.session_token,
+        expiration: iso_8601(creds.expires_at),
+        role_arn: config.sts.role_arn.clone(),
+    }))
+}
+
+/// Public URL of the container credentials endpoint.
+///
+/// It is derived from the configured callback URL, which is public and sits next to it under
+/// `/auth/cli/`, rather than from request headers that a client could set to a host of its
+/// choosing, which would then receive the token.
+fn credentials_uri(config: &AppArgs) -> Result<String, AppError> {
+    let endpoint = CONTAINER_CREDENTIALS_PATH.rsplit('/').next().unwrap_or_default();
+    Url::parse(&config.cognito.redirect_uri)
+        .and_then(|callback| callback.join(endpoint))
+        .map(String::from)
+        .map_err(|e| AppError::Internal(format!("invalid callback URL: {}", e)))
+}
+
+/// Reads the token of the `Authorization` header.
+///
+/// The AWS SDKs send `AWS_CONTAINER_AUTHORIZATION_TOKEN` verbatim, so the `Bearer` scheme is optional.
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?.trim();
+    let token = value.strip_prefix("Bearer ").unwrap_or(value).trim();
+    (!token.is_empty()).then_some(token)
+}
@@ -1,10 +1,18 @@
 pub mod cli_callback;
+pub mod cli_confirm;
+pub mod cli_logout;
 pub mod cli_renew;
 pub mod cli_start;
 pub mod cli_status;
+pub mod container;
+pub mod output;
 pub mod utils;
+pub mod version;
 
 pub use cli_callback::*;
+pub use cli_confirm::*;
+pub use cli_logout::*;
 pub use cli_renew::*;
 pub use cli_start::*;
 pub use cli_status::*;
+pub use container::*;
@@ -0,0 +1,130 @@
+use crate::error::AppError;
+use crate::schemas::auth::{CliAuthResponse, CredentialFormat, CredentialFormatQuery, CredentialProcessOutput};
+use actix_web::http::header::{ACCEPT, VARY};
+use actix_web::{HttpRequest, HttpResponse};
+use mega_uploader_auth::format::{env_exports, iso_8601};
+
+/// Media type selecting the `credential_process` format through the `Accept` header.
+pub const CREDENTIAL_PROCESS_MEDIA_TYPE: &str = "application/x-aws-credential-process+json";
+/// Media type selecting the shell `export` format through the `Accept` header.
+pub const ENV_MEDIA_TYPE: &str = "text/x-shellscript";
+/// Media type selecting the `~/.aws/credentials` profile format through the `Accept` header.
+pub const CREDENTIALS_MEDIA_TYPE: &str = "text/x-aws-credentials";
+
+/// Profile name used by the `credentials` format when none is given.
+const DEFAULT_PROFILE: &str = "default";
+
+/// How authorized credentials are written in the response body.
+///
+/// Only `AUTHORIZED` responses are rewritten; the other statuses keep their JSON form so that
+/// clients can go on polling whatever format they asked for.
+pub struct CredentialOutput {
+    format: CredentialFormat,
+    profile: String,
+    include_refresh_token: bool,
+}
+
+impl CredentialOutput {
+    /// Picks the format from `?format=`, or else from the supported `Accept` media type with the
+    /// highest quality (`q`), the first listed among equals.
+    pub fn negotiate(req: &HttpRequest, query: &CredentialFormatQuery) -> Result<Self, AppError> {
+        let format = query.format.unwrap_or_else(|| format_from_accept(req));
+
+        let profile = query.profile.clone().unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        let valid_profile = profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        if profile.is_empty() || !valid_profile {
+            return Err(AppError::InvalidRequest(
+                "profile must only contain letters, digits, '-', '_' and '.'".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            format,
+            profile,
+            include_refresh_token: query.include_refresh_token,
+        })
+    }
+
+    /// Builds the `200 OK` response for a status.
+    pub fn respond(&self, status: CliAuthResponse) -> HttpResponse {
+        let mut res = HttpResponse::Ok();
+        res.insert_header((VARY, "Accept"));
+
+        let CliAuthResponse::AUTHORIZED {
+            access_key_id,
+            secret_access_key,
+            session_token,
+            expires_at,
+            refresh_token,
+        } = &status
+        else {
+            return res.json(status);
+        };
+
+        // The text formats may carry the refresh token in a comment, which the shell and the AWS
+        // SDKs skip, so that the CLI can still renew the session
+        let refresh_comment = refresh_token
+            .as_deref()
+            .filter(|_| self.include_refresh_token)
+            .map(|token| format!("# refresh_token = {}\n", token))
+            .unwrap_or_default();
+
+        match self.format {
+            CredentialFormat::Json => res.json(status),
+            CredentialFormat::CredentialProcess => res.content_type(CREDENTIAL_PROCESS_MEDIA_TYPE).json(
+                CredentialProcessOutput {
+                    version: 1,
+                    access_key_id: access_key_id.clone(),
+                    secret_access_key: secret_access_key.clone(),
+                    session_token: session_token.clone(),
+                    expiration: iso_8601(*expires_at),
+                    refresh_token: refresh_token.clone(),
+                },
+            ),
+            CredentialFormat::Env => res.content_type(ENV_MEDIA_TYPE).body(format!(
+                "{}{}",
+                env_exports(access_key_id, secret_access_key, session_token, *expires_at),
+                refresh_comment
+            )),
+            CredentialFormat::Credentials => res.content_type(CREDENTIALS_MEDIA_TYPE).body(format!(
+                "[{}]\n\
+                 aws_access_key_id = {}\n\
+                 aws_secret_access_key = {}\n\
+                 aws_session_token = {}\n\
+                 {}",
+                self.profile, access_key_id, secret_access_key, session_token, refresh_comment
+            )),
+        }
+    }
+}
+
+/// Maps the preferred supported media type of the `Accept` header to its format.
+///
+/// Media types with `q=0` are refused; JSON is the default when none is supported.
+fn format_from_accept(req: &HttpRequest) -> CredentialFormat {
+    let Some(accept) = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return CredentialFormat::Json;
+    };
+
+    let mut preferred: Option<(f32, CredentialFormat)> = None;
+    for media in accept.split(',') {
+        let mut params = media.split(';');
+        let format = match params.next().unwrap_or_default().trim() {
+            CREDENTIAL_PROCESS_MEDIA_TYPE => CredentialFormat::CredentialProcess,
+            ENV_MEDIA_TYPE => CredentialFormat::Env,
+            CREDENTIALS_MEDIA_TYPE => CredentialFormat::Credentials,
+            "application/json" => CredentialFormat::Json,
+            _ => continue,
+        };
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+            .unwrap_or(0.0);
+        if quality > 0.0 && preferred.is_none_or(|(best, _)| quality > best) {
+            preferred = Some((quality, format));
+        }
+    }
+    preferred.map(|(_, format)| format).unwrap_or_default()
+}
@@ -1,5 +1,13 @@
+use crate::config::SharedConfig;
+use crate::error::AppError;
 use crate::schemas::auth::{CliAuthResponse, CliSessionData};
-use actix_web::HttpResponse;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::{web, HttpRequest};
+use aws_lc_rs::digest::{digest, SHA256};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Prefix used for session keys in Redis.
 pub const CLI_SESSION_KEY_PREFIX: &str = "auth:cli:session:";
@@ -7,15 +15,39 @@ pub const CLI_SESSION_KEY_PREFIX: &str = "auth:cli:session:";
 pub const CLI_STATE_KEY_PREFIX: &str = "auth:cli:state:";
 /// Prefix used for JWKS caching in Redis.
 pub const JWKS_CACHE_KEY_PREFIX: &str = "auth:jwks:";
+/// Prefix used for status poll throttling keys in Redis.
+pub const CLI_POLL_KEY_PREFIX: &str = "auth:cli:poll:";
+/// Prefix used for rate limiting counters in Redis.
+pub const RATE_LIMIT_KEY_PREFIX: &str = "auth:ratelimit:";
+/// Prefix used for cached STS credentials in Redis.
+pub const STS_CACHE_KEY_PREFIX: &str = "auth:sts:";
+/// Prefix used for container credentials tokens in Redis.
+pub const CONTAINER_TOKEN_KEY_PREFIX: &str = "auth:cli:container:";
+/// Prefix used for logins waiting for the user to confirm their device in Redis.
+pub const CLI_CONFIRM_KEY_PREFIX: &str = "auth:cli:confirm:";
 
-/// Returns the Redis key for a given CLI session or state pointer.
-pub fn get_cli_session_key(state: &str) -> String {
-    format!("{}{}", CLI_SESSION_KEY_PREFIX, state)
+/// Characters of verification codes, without the easily confused `0`, `O`, `1` and `I`.
+const VERIFICATION_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Lifetime of a stored CLI session, in seconds (30 days).
+pub const CLI_SESSION_TTL: u64 = 30 * 24 * 3600;
+
+/// Returns the Redis key for the CLI session of a given user.
+pub fn get_cli_session_key(sub: &str) -> String {
+    format!("{}{}", CLI_SESSION_KEY_PREFIX, sub)
+}
+
+/// Returns the Redis key pointing from a completed CLI authentication state to its user.
+///
+/// Keys of one login flow carry the state as a hash tag (`{state}`), so that Redis Cluster
+/// stores them in the same slot and they stay usable together.
+pub fn get_cli_state_session_key(state: &str) -> String {
+    format!("{}{{{}}}", CLI_SESSION_KEY_PREFIX, state)
 }
 
 /// Returns the Redis key for a given CLI authentication state.
 pub fn get_cli_state_key(state: &str) -> String {
-    format!("{}{}", CLI_STATE_KEY_PREFIX, state)
+    format!("{}{{{}}}", CLI_STATE_KEY_PREFIX, state)
 }
 
 /// Returns the Redis key for a given Cognito User Pool JWKS.
@@ -23,16 +55,83 @@ pub fn get_jwks_cache_key(user_pool_id: &str) -> String {
     format!("{}{}", JWKS_CACHE_KEY_PREFIX, user_pool_id)
 }
 
-/// Validates the CLI session data and returns a corresponding HTTP response if invalid.
-pub fn validate_cli_session(session_data: Option<CliSessionData>) -> Result<CliSessionData, HttpResponse> {
+/// Returns the Redis key that throttles status polling for a given CLI authentication state.
+pub fn get_cli_poll_key(state: &str) -> String {
+    format!("{}{{{}}}", CLI_POLL_KEY_PREFIX, state)
+}
+
+/// Returns the Redis key of a rate limiting counter for a route and client identifier.
+pub fn get_rate_limit_key(route: &str, scope: &str, id: &str) -> String {
+    format!("{}{}:{}:{}", RATE_LIMIT_KEY_PREFIX, route, scope, id)
+}
+
+/// Returns the Redis key of cached STS credentials, given the user and the cache entry ID.
+pub fn get_sts_cache_key(sub: &str, id: &str) -> String {
+    format!("{}{}:{}", STS_CACHE_KEY_PREFIX, sub, id)
+}
+
+/// Returns the Redis key of a container credentials token, given the token hash.
+pub fn get_container_token_key(token_hash: &str) -> String {
+    format!("{}{}", CONTAINER_TOKEN_KEY_PREFIX, token_hash)
+}
+
+/// Returns the Redis key of the login of a CLI authentication state waiting for confirmation.
+pub fn get_cli_confirm_key(state: &str) -> String {
+    format!("{}{{{}}}", CLI_CONFIRM_KEY_PREFIX, state)
+}
+
+/// Generates a random 256-bit token, encoded as URL-safe base64.
+pub fn generate_token() -> Result<String, AppError> {
+    let mut bytes = [0u8; 32];
+    aws_lc_rs::rand::fill(&mut bytes).map_err(|_| AppError::Internal("could not generate a token".to_string()))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Generates a verification code such as `KXR4-9PTM`, shown by both the CLI and the browser.
+pub fn generate_verification_code() -> Result<String, AppError> {
+    let mut bytes = [0u8; 8];
+    aws_lc_rs::rand::fill(&mut bytes).map_err(|_| AppError::Internal("could not generate a code".to_string()))?;
+    let chars: String = bytes
+        .iter()
+        .map(|b| VERIFICATION_CODE_ALPHABET[*b as usize % VERIFICATION_CODE_ALPHABET.len()] as char)
+        .collect();
+    Ok(format!("{}-{}", &chars[..4], &chars[4..]))
+}
+
+/// Hex-encoded SHA-256 of a secret, so that stored keys and records never hold it in clear.
+pub fn hash_secret(secret: &str) -> String {
+    digest(&SHA256, secret.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Returns the current Unix timestamp in seconds.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_else(|_| 0)
+}
+
+/// Identifies a login of a user by the hash of its refresh token.
+///
+/// A new login replaces the refresh token, invalidating what was issued for the previous one.
+pub fn session_fingerprint(session: &CliSessionData) -> String {
+    hash_secret(session.refresh_token.as_deref().unwrap_or_default())
+}
+
+/// Validates the CLI session data and returns the status to report to the CLI if invalid.
+pub fn validate_cli_session(session_data: Option<CliSessionData>) -> Result<CliSessionData, CliAuthResponse> {
     match session_data {
         Some(s) => {
             if !s.active {
-                return Err(HttpResponse::Ok().json(CliAuthResponse::DENIED));
+                return Err(CliAuthResponse::DENIED);
             }
             Ok(s)
         }
-        None => Err(HttpResponse::Ok().json(CliAuthResponse::EXPIRED)),
+        None => Err(CliAuthResponse::EXPIRED),
     }
 }
 
@@ -54,3 +153,68 @@ pub fn get_role_session_name(user_sub: &str) -> String {
         .take(64)
         .collect::<String>()
 }
+
+/// Returns the client IP address.
+///
+/// The peer address is used unless it is one of the configured trusted proxies. The
+/// Forwarded or X-Forwarded-For chain is then read from the right, skipping the trusted
+/// proxies, so that entries a client adds to its own request are never used.
+pub fn get_client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let config = req.app_data::<web::Data<SharedConfig>>().map(|config| config.load_full());
+    let trusted = config.as_ref().map_or(&[][..], |config| &config.server.trusted_proxies[..]);
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    let mut client = peer;
+    if is_trusted(&client) {
+        for hop in forwarded_chain(req.headers()).iter().rev() {
+            // Entries that are not addresses (e.g. `unknown`) end the trusted chain
+            let Ok(ip) = hop.parse::<IpAddr>() else { break };
+            client = ip;
+            if !is_trusted(&ip) {
+                break;
+            }
+        }
+    }
+    Some(client.to_string())
+}
+
+/// Lists the client and proxy addresses of the Forwarded header, or else of X-Forwarded-For,
+/// from the original client to the last proxy.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<String> {
+    let forwarded: Vec<String> = headers
+        .get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, node)| forwarded_node(node))
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(forwarded_node)
+        .collect()
+}
+
+/// Strips the quotes, IPv6 brackets and port of a forwarded node, e.g. `"[2001:db8::1]:4711"`.
+fn forwarded_node(node: &str) -> String {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest).to_string();
+    }
+    match node.split_once(':') {
+        // A single colon separates an IPv4 address from its port
+        Some((ip, port)) if !port.contains(':') => ip.to_string(),
+        _ => node.to_string(),
+    }
+}
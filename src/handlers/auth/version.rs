@@ -0,0 +1,68 @@
+use crate::config::CliVersionConfig;
+use crate::error::AppError;
+use crate::schemas::auth::{CLI_DEPRECATION_HEADER, CLI_VERSION_HEADER};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{HttpRequest, HttpResponse};
+use semver::Version;
+
+/// Checks the version of the calling CLI against the configured minimum and deprecated range.
+///
+/// The version is taken from the `X-CLI-Version` header or else from `reported`, the version
+/// given in the request body or recorded with the session. Clients reporting no version are
+/// let through. Returns the deprecation warning to attach to the response, if any.
+pub fn check_cli_version(
+    req: &HttpRequest,
+    reported: Option<&str>,
+    config: &CliVersionConfig,
+) -> Result<Option<String>, AppError> {
+    let Some(raw) = reported_cli_version(req, reported) else {
+        return Ok(None);
+    };
+
+    // Versions that do not parse cannot be shown to be recent enough
+    let version = Version::parse(raw.trim_start_matches('v')).ok();
+
+    if let Some(minimum) = &config.min_version
+        && version.as_ref().is_none_or(|v| v < minimum)
+    {
+        return Err(AppError::UpgradeRequired {
+            version: raw.clone(),
+            minimum_version: minimum.to_string(),
+            download_url: config.download_url.clone(),
+        });
+    }
+
+    let deprecated = match (&config.deprecated_versions, &version) {
+        (Some(range), Some(version)) => range.matches(version),
+        _ => false,
+    };
+    if !deprecated {
+        return Ok(None);
+    }
+
+    Ok(Some(match &config.download_url {
+        Some(url) => format!("CLI version {} is deprecated, upgrade from {}", raw, url),
+        None => format!("CLI version {} is deprecated, please upgrade", raw),
+    }))
+}
+
+/// Returns the version checked by [`check_cli_version`]: the `X-CLI-Version` header, or else
+/// `reported`.
+pub fn reported_cli_version(req: &HttpRequest, reported: Option<&str>) -> Option<String> {
+    let header = req.headers().get(CLI_VERSION_HEADER).and_then(|v| v.to_str().ok());
+    header
+        .or(reported)
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+}
+
+/// Adds the deprecation warning returned by [`check_cli_version`] to a response.
+pub fn warn_deprecated(mut response: HttpResponse, warning: Option<String>) -> HttpResponse {
+    if let Some(value) = warning.and_then(|w| HeaderValue::from_str(&w).ok())
+        && let Ok(name) = HeaderName::from_bytes(CLI_DEPRECATION_HEADER.as_bytes())
+    {
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
@@ -0,0 +1,142 @@
+use crate::config::{AppArgs, SharedConfig};
+use crate::id_token::IdTokenVerifier;
+use crate::schemas::health::{DependencyCheck, HealthStatus, LivenessResponse, ReadinessResponse};
+use crate::shutdown::Shutdown;
+use crate::store::{SessionStore, StateStore};
+use actix_web::rt::time::timeout;
+use actix_web::{get, web, HttpResponse, Responder};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Maximum time allowed for a single dependency check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness probe.
+///
+/// Answers as long as the HTTP server is able to process requests; it does not
+/// look at any dependency, so a storage outage never restarts the pod.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    summary = "Liveness probe",
+    description = "Answers as long as the server is running.",
+    responses((status = 200, description = "Server is alive", body = LivenessResponse))
+)]
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(LivenessResponse {
+        status: HealthStatus::Up,
+    })
+}
+
+/// Readiness probe.
+///
+/// Checks that the state and session stores answer (each check is named after its backend,
+/// e.g. `redis`), that the Cognito JWKS is cached or can be fetched, and that STS is
+/// configured. Responds with `503 Service Unavailable` when any dependency is down.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    summary = "Readiness probe",
+    description = "Reports the status and latency of the storage backends, the Cognito JWKS and STS. Fails while the server is shutting down.",
+    responses(
+        (status = 200, description = "All dependencies are up", body = ReadinessResponse),
+        (status = 503, description = "At least one dependency is down, or the server is shutting down", body = ReadinessResponse)
+    )
+)]
+pub async fn readyz<S: StateStore + ?Sized, T: SessionStore + ?Sized>(
+    states: web::Data<S>,
+    sessions: web::Data<T>,
+    sts_client: web::Data<aws_sdk_sts::Client>,
+    config: web::Data<SharedConfig>,
+    verifier: web::Data<IdTokenVerifier>,
+    shutdown: web::Data<Shutdown>,
+) -> impl Responder {
+    let mut checks = BTreeMap::new();
+
+    // Fail fast while shutting down so that load balancers stop routing traffic here
+    if shutdown.is_draining() {
+        checks.insert(
+            "shutdown",
+            DependencyCheck {
+                status: HealthStatus::Down,
+                latency_ms: 0.0,
+                error: Some("Server is shutting down".to_string()),
+            },
+        );
+        return HttpResponse::ServiceUnavailable().json(ReadinessResponse {
+            status: HealthStatus::Down,
+            checks,
+        });
+    }
+
+    let config = config.load_full();
+    checks.insert(states.backend(), check(states.ping()).await);
+    if sessions.backend() != states.backend() {
+        checks.insert(sessions.backend(), check(sessions.ping()).await);
+    }
+    checks.insert(
+        "jwks",
+        check(async {
+            verifier
+                .keys()
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .await,
+    );
+    checks.insert("sts", check(async { check_sts(&sts_client, &config) }).await);
+
+    let ready = checks.values().all(|c| c.status == HealthStatus::Up);
+    let body = ReadinessResponse {
+        status: if ready { HealthStatus::Up } else { HealthStatus::Down },
+        checks,
+    };
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Runs a dependency check with a timeout and measures its latency.
+async fn check<F>(probe: F) -> DependencyCheck
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let result = match timeout(CHECK_TIMEOUT, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("Timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    match result {
+        Ok(()) => DependencyCheck {
+            status: HealthStatus::Up,
+            latency_ms,
+            error: None,
+        },
+        Err(e) => DependencyCheck {
+            status: HealthStatus::Down,
+            latency_ms,
+            error: Some(e),
+        },
+    }
+}
+
+/// Verifies that STS has a region and a role to assume.
+fn check_sts(sts_client: &aws_sdk_sts::Client, config: &AppArgs) -> Result<(), String> {
+    if sts_client.config().region().is_none() {
+        return Err("No AWS region configured for STS".to_string());
+    }
+    if !config.sts.role_arn.starts_with("arn:") {
+        return Err("STS role ARN is not configured".to_string());
+    }
+    Ok(())
+}
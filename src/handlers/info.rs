@@ -1,9 +1,18 @@
 use actix_web::{get, HttpResponse, Responder};
+use crate::openapi::{routes, DOCS_PATH};
 use log::info;
 
 /// Handler for the root path that returns information about the API.
 ///
 /// It renders a simple HTML page with the project name, version, and available endpoints.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "info",
+    summary = "API info",
+    description = "HTML page with the project name, version and available endpoints.",
+    responses((status = 200, description = "Info page", content_type = "text/html"))
+)]
 #[get("/")]
 pub async fn info() -> impl Responder {
     info!("Processing request on root path");
@@ -12,6 +21,20 @@ pub async fn info() -> impl Responder {
     const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
     const PKG_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 
+    let endpoints = routes()
+        .iter()
+        .map(|route| {
+            format!(
+                r#"<div class="api-card">
+                        <div class="endpoint">{} {}</div>
+                        <div class="description">{}</div>
+                    </div>"#,
+                route.method, route.path, route.description
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n                    ");
+
     let html_content = format!(
         r#"
     <!DOCTYPE html>
@@ -144,41 +167,11 @@ pub async fn info() -> impl Responder {
                 <div class="info-section">
                     <h2>📡 Available APIs</h2>
 
-                    <div class="api-card">
-                        <div class="endpoint">GET /</div>
-                        <div class="description">
-                            Returns detailed information about the available APIs in the project.
-                            Main interactive documentation endpoint.
-                        </div>
-                    </div>
-
-                    <div class="api-card">
-                        <div class="endpoint">POST /auth/cli/start</div>
-                        <div class="description">
-                            Initiates the CLI authentication process. Returns an authorization URL for the browser.
-                        </div>
-                    </div>
-
-                    <div class="api-card">
-                        <div class="endpoint">GET /auth/cli/callback</div>
-                        <div class="description">
-                            Callback endpoint for the identity provider. Handles token exchange and session creation.
-                        </div>
-                    </div>
-
-                    <div class="api-card">
-                        <div class="endpoint">GET /auth/cli/status</div>
-                        <div class="description">
-                            Polls the authentication status for a specific state. Returns AWS STS credentials if authorized.
-                        </div>
-                    </div>
+                    <p class="description" style="margin-bottom: 15px;">
+                        Interactive documentation: <a href="{}" style="color: #667eea;">{}</a>
+                    </p>
 
-                    <div class="api-card">
-                        <div class="endpoint">POST /auth/cli/renew</div>
-                        <div class="description">
-                            Renews an expired CLI session using a refresh token to obtain new AWS STS credentials.
-                        </div>
-                    </div>
+                    {}
                 </div>
 
                 <div class="info-section">
@@ -187,7 +180,8 @@ pub async fn info() -> impl Responder {
                         <div class="description">
                             <strong>Framework:</strong> Actix Web 4<br>
                             <strong>Database:</strong> Redis with BB8 connection pool<br>
-                            <strong>Logging:</strong> env_logger + log<br>
+                            <strong>Logging:</strong> tracing + log<br>
+                            <strong>API docs:</strong> OpenAPI 3 (utoipa) + RapiDoc<br>
                             <strong>Serialization:</strong> Serde
                         </div>
                     </div>
@@ -201,7 +195,7 @@ pub async fn info() -> impl Responder {
     </body>
     </html>
     "#,
-        PKG_NAME, PKG_NAME, PKG_DESCRIPTION, PKG_VERSION
+        PKG_NAME, PKG_NAME, PKG_DESCRIPTION, PKG_VERSION, DOCS_PATH, DOCS_PATH, endpoints
     );
 
     HttpResponse::Ok()
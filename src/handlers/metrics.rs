@@ -0,0 +1,25 @@
+use crate::db::RedisPool;
+use crate::metrics::Metrics;
+use actix_web::{get, web, HttpResponse, Responder};
+
+/// Handler exposing the service metrics in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    summary = "Prometheus metrics",
+    description = "Exposes service metrics in the Prometheus text format.",
+    responses((status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub async fn metrics(metrics: web::Data<Metrics>, redis_pool: web::Data<RedisPool>) -> impl Responder {
+    match metrics.render(&redis_pool) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(prometheus::TEXT_FORMAT)
+            .body(body),
+        Err(e) => {
+            log::error!("Failed to render metrics: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub mod auth;
+pub mod health;
 pub mod info;
+pub mod metrics;
+pub mod redirect;
@@ -0,0 +1,42 @@
+use actix_web::http::header::{HOST, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Port of the HTTPS listener that plain HTTP requests are redirected to.
+pub struct HttpsPort(pub u16);
+
+/// Handler of the plain HTTP listener, redirecting every request to the same URL over HTTPS.
+///
+/// Uses `308 Permanent Redirect` so that clients repeat POST requests with their body. The
+/// host comes from the `Host` header only: forwarding headers are not trusted here, since they
+/// would let anyone redirect to a host of their choosing.
+pub async fn redirect_to_https(req: HttpRequest, https_port: web::Data<HttpsPort>) -> HttpResponse {
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().authority().map(|a| a.as_str()));
+    let Some(host) = host.map(strip_port).filter(|h| !h.is_empty()) else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let port = match https_port.0 {
+        443 => String::new(),
+        port => format!(":{}", port),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((LOCATION, format!("https://{}{}{}", host, port, path)))
+        .finish()
+}
+
+/// Removes the port from a `Host` header value, keeping IPv6 brackets.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
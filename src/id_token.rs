@@ -0,0 +1,331 @@
+use crate::config::AppArgs;
+use crate::db::{redis_get, redis_set_ex, RedisPool};
+use crate::error::AppError;
+use crate::handlers::auth::utils::get_jwks_cache_key;
+use crate::metrics::Metrics;
+use crate::schemas::auth::IdTokenClaims;
+use actix_web::rt::task::JoinHandle;
+use actix_web::rt::time::sleep;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use jsonwebtoken::{decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+
+/// Delay before retrying a failed background refresh.
+const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Maximum time to establish a connection to the JWKS endpoint.
+const JWKS_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Maximum time for a whole JWKS download, response body included.
+const JWKS_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Download of the keys, awaited by every caller that needs it.
+type Download = Shared<BoxFuture<'static, Result<Arc<JwkSet>, String>>>;
+
+/// JWKS as stored in Redis, with the time it was downloaded from Cognito.
+#[derive(Serialize, Deserialize)]
+struct CachedJwks {
+    keys: JwkSet,
+    /// Unix timestamp (seconds) of the download.
+    fetched_at: i64,
+}
+
+/// Downloads of the keys from Cognito.
+#[derive(Default)]
+struct FetchState {
+    /// Instant at which the last download started.
+    last: Option<Instant>,
+    /// Download in progress, if any.
+    in_flight: Option<Download>,
+}
+
+/// JWKS held in memory.
+struct MemoryJwks {
+    keys: Arc<JwkSet>,
+    /// Instant after which the keys must be refreshed.
+    expires_at: Instant,
+}
+
+/// Validates Cognito ID tokens against the user pool's JSON Web Key Set.
+///
+/// Keys are cached in memory and in Redis (shared between replicas) for `jwks_cache_ttl`.
+/// A token signed with an unknown `kid` triggers a single refetch, at most once per
+/// `jwks_refetch_cooldown`, so that Cognito key rotations are picked up immediately
+/// without letting forged tokens hammer Cognito. A background task refreshes the keys
+/// `jwks_refresh_margin` before they expire.
+///
+/// Concurrent callers share a single download, and no lock is held while it runs, so a
+/// slow JWKS endpoint only delays the requests that actually need new keys.
+pub struct IdTokenVerifier {
+    jwks_url: String,
+    issuer: String,
+    client_id: String,
+    cache_key: String,
+    ttl: Duration,
+    cooldown: Duration,
+    refresh_margin: Duration,
+    redis_pool: RedisPool,
+    metrics: Arc<Metrics>,
+    http: reqwest::Client,
+    memory: RwLock<Option<MemoryJwks>>,
+    fetch: Mutex<FetchState>,
+}
+
+impl IdTokenVerifier {
+    /// Creates a verifier for the configured Cognito user pool.
+    pub fn new(config: &AppArgs, redis_pool: RedisPool, metrics: Arc<Metrics>) -> Self {
+        Self {
+            jwks_url: config.cognito.jwks_url(),
+            issuer: config.cognito.issuer(),
+            client_id: config.cognito.client_id.clone(),
+            cache_key: get_jwks_cache_key(&config.cognito.user_pool_id),
+            ttl: Duration::from_secs(config.cognito.jwks_cache_ttl),
+            cooldown: Duration::from_secs(config.cognito.jwks_refetch_cooldown),
+            refresh_margin: Duration::from_secs(config.cognito.jwks_refresh_margin),
+            redis_pool,
+            metrics,
+            http: reqwest::Client::builder()
+                .connect_timeout(JWKS_CONNECT_TIMEOUT)
+                .timeout(JWKS_REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build the JWKS HTTP client"),
+            memory: RwLock::new(None),
+            fetch: Mutex::new(FetchState::default()),
+        }
+    }
+
+    /// Validates the signature, audience, issuer and expiry of an ID token and returns its claims.
+    #[tracing::instrument(name = "id_token.verify", skip_all)]
+    pub async fn verify(&self, id_token: &str) -> Result<IdTokenClaims, AppError> {
+        let header = decode_header(id_token).map_err(|e| {
+            log::error!("Failed to decode token header: {}", e);
+            AppError::InvalidIdToken("Invalid token header".to_string())
+        })?;
+
+        let kid = header
+            .kid
+            .ok_or_else(|| AppError::InvalidIdToken("Token missing kid".to_string()))?;
+
+        let decoding_key = match self.find_key(&kid).await? {
+            Some(key) => key,
+            None => {
+                // The key may have been rotated by Cognito since the JWKS was cached
+                log::info!("Unknown JWKS kid {}, refetching keys", kid);
+                self.refetch_on_miss().await?;
+                self.find_key(&kid).await?.ok_or_else(|| {
+                    AppError::InvalidIdToken("Specified key not found in JWKS".to_string())
+                })?
+            }
+        };
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(std::slice::from_ref(&self.client_id));
+        validation.set_issuer(std::slice::from_ref(&self.issuer));
+
+        let token_data = jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(|e| {
+                log::error!("Token validation failed: {}", e);
+                AppError::InvalidIdToken(e.to_string())
+            })?;
+
+        Ok(token_data.claims)
+    }
+
+    /// Returns the current key set, loading it from Redis or Cognito if needed.
+    pub async fn keys(&self) -> Result<Arc<JwkSet>, AppError> {
+        if let Some(keys) = self.memory_keys() {
+            return Ok(keys);
+        }
+
+        if let Some(keys) = self.load_from_redis().await {
+            return Ok(keys);
+        }
+
+        self.download().await
+    }
+
+    /// Downloads the keys from Cognito now, replacing the cached copies, whatever their age.
+    pub async fn refresh(&self) -> Result<Arc<JwkSet>, AppError> {
+        self.download().await
+    }
+
+    /// Spawns a background task refreshing the keys before they expire and returns its handle.
+    pub fn spawn_refresh(self: Arc<Self>) -> JoinHandle<()> {
+        actix_web::rt::spawn(async move {
+            loop {
+                let delay = match self.memory_expiry() {
+                    Some(expires_at) => expires_at
+                        .saturating_duration_since(Instant::now())
+                        .saturating_sub(self.refresh_margin)
+                        .max(self.cooldown.max(Duration::from_secs(1))),
+                    None => Duration::ZERO,
+                };
+                sleep(delay).await;
+
+                if let Err(e) = self.refresh().await {
+                    log::warn!("Background JWKS refresh failed: {}", e);
+                    sleep(REFRESH_RETRY_DELAY).await;
+                }
+            }
+        })
+    }
+
+    /// Looks up the decoding key of a `kid` in the current key set.
+    async fn find_key(&self, kid: &str) -> Result<Option<DecodingKey>, AppError> {
+        let keys = self.keys().await?;
+        match keys.find(kid) {
+            Some(jwk) => DecodingKey::from_jwk(jwk)
+                .map(Some)
+                .map_err(|e| AppError::Internal(format!("Key processing error: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    /// Downloads the keys again after a `kid` miss, unless a download happened within the cooldown.
+    async fn refetch_on_miss(&self) -> Result<(), AppError> {
+        match self.join_download(self.cooldown) {
+            Some(download) => self.finish_download(download).await.map(|_| ()),
+            None => {
+                log::debug!("JWKS refetch skipped, last download is within the cooldown");
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the in-memory keys if they have not expired.
+    fn memory_keys(&self) -> Option<Arc<JwkSet>> {
+        let memory = self.memory.read().unwrap_or_else(|e| e.into_inner());
+        memory
+            .as_ref()
+            .filter(|m| m.expires_at > Instant::now())
+            .map(|m| m.keys.clone())
+    }
+
+    /// Returns the instant at which the in-memory keys expire.
+    fn memory_expiry(&self) -> Option<Instant> {
+        let memory = self.memory.read().unwrap_or_else(|e| e.into_inner());
+        memory.as_ref().map(|m| m.expires_at)
+    }
+
+    /// Stores keys in memory, expiring `age` after they were downloaded.
+    fn store_in_memory(&self, keys: Arc<JwkSet>, age: Duration) -> Arc<JwkSet> {
+        let mut memory = self.memory.write().unwrap_or_else(|e| e.into_inner());
+        *memory = Some(MemoryJwks {
+            keys: keys.clone(),
+            expires_at: Instant::now() + self.ttl.saturating_sub(age),
+        });
+        keys
+    }
+
+    /// Loads keys cached in Redis by any replica.
+    async fn load_from_redis(&self) -> Option<Arc<JwkSet>> {
+        let cached = match redis_get::<CachedJwks>(&self.redis_pool, &self.cache_key).await {
+            Ok(cached) => cached?,
+            Err(e) => {
+                log::warn!("Could not read cached JWKS from Redis: {}", e);
+                return None;
+            }
+        };
+
+        let age = Duration::from_secs((unix_now() - cached.fetched_at).max(0) as u64);
+        if age >= self.ttl {
+            return None;
+        }
+
+        Some(self.store_in_memory(Arc::new(cached.keys), age))
+    }
+
+    /// Downloads the keys from Cognito and caches them in memory and in Redis.
+    async fn download(&self) -> Result<Arc<JwkSet>, AppError> {
+        match self.join_download(Duration::ZERO) {
+            Some(download) => self.finish_download(download).await,
+            None => Err(AppError::Internal("JWKS download was not started".to_string())),
+        }
+    }
+
+    /// Returns the download in progress, or starts one unless the last one began within `cooldown`.
+    fn join_download(&self, cooldown: Duration) -> Option<Download> {
+        let mut fetch = self.fetch.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(download) = &fetch.in_flight {
+            return Some(download.clone());
+        }
+        if fetch.last.is_some_and(|at| at.elapsed() < cooldown) {
+            return None;
+        }
+
+        let download = self.start_download();
+        fetch.last = Some(Instant::now());
+        fetch.in_flight = Some(download.clone());
+        Some(download)
+    }
+
+    /// Waits for a download and stores the keys in memory.
+    async fn finish_download(&self, download: Download) -> Result<Arc<JwkSet>, AppError> {
+        let result = download.clone().await;
+
+        let mut fetch = self.fetch.lock().unwrap_or_else(|e| e.into_inner());
+        if fetch.in_flight.as_ref().is_some_and(|d| d.ptr_eq(&download)) {
+            fetch.in_flight = None;
+        }
+        drop(fetch);
+
+        let keys = result.map_err(AppError::IdentityProviderUnavailable)?;
+        Ok(self.store_in_memory(keys, Duration::ZERO))
+    }
+
+    /// Creates the download of the keys, which also caches them in Redis.
+    fn start_download(&self) -> Download {
+        let http = self.http.clone();
+        let jwks_url = self.jwks_url.clone();
+        let redis_pool = self.redis_pool.clone();
+        let cache_key = self.cache_key.clone();
+        let ttl = self.ttl;
+        let metrics = self.metrics.clone();
+
+        async move {
+            let timer = metrics.jwks_fetch_duration.start_timer();
+
+            let keys = http
+                .get(&jwks_url)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(|e| {
+                    log::error!("Failed to fetch JWKS: {}", e);
+                    format!("Failed to fetch JWKS: {}", e)
+                })?
+                .json::<JwkSet>()
+                .await
+                .map_err(|e| {
+                    log::error!("Failed to parse JWKS: {}", e);
+                    format!("Failed to parse JWKS: {}", e)
+                })?;
+
+            timer.observe_duration();
+
+            let cached = CachedJwks {
+                keys,
+                fetched_at: unix_now(),
+            };
+            if let Err(e) = redis_set_ex(&redis_pool, &cache_key, &cached, ttl.as_secs()).await {
+                log::warn!("Could not cache JWKS in Redis: {}", e);
+            }
+
+            Ok(Arc::new(cached.keys))
+        }
+        .instrument(tracing::info_span!("cognito.fetch_jwks"))
+        .boxed()
+        .shared()
+    }
+}
+
+/// Returns the current Unix timestamp in seconds.
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_else(|_| 0)
+}
@@ -0,0 +1,9 @@
+//! Types and client of the Mega Uploader authentication API.
+//!
+//! [`schemas`] holds the request and response types of the service, which the server itself
+//! is built on, [`client`] an async client of the CLI login flow and [`format`] the text
+//! formats of credentials.
+
+pub mod client;
+pub mod format;
+pub mod schemas;
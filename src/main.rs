@@ -1,34 +1,88 @@
-use actix_web::{middleware::Logger, web, App, HttpServer};
-use clap::Parser;
+use actix_web::{web, App, HttpServer};
+use audit::{AuditLog, AuditSink, JsonLinesSink, RedisStreamSink};
+use config::AuditSinkKind;
 use log::{error, info};
+use mega_uploader_auth::schemas;
+use std::sync::Arc;
 
+mod admin;
+mod app;
+mod audit;
 mod config;
 mod db;
+mod error;
 mod handlers;
+mod id_token;
+mod metrics;
+mod middleware;
+mod openapi;
+mod pages;
 mod routes;
-mod schemas;
+mod shutdown;
+mod store;
+mod sts;
+mod telemetry;
+#[cfg(test)]
+mod tests;
+mod tls;
 mod utils;
 
 /// Entry point of the Mega Uploader Auth application.
 ///
-/// This function initializes the logging system, parses configuration arguments,
+/// This function parses configuration arguments, initializes logging and tracing,
 /// establishes a connection to Redis, sets up the AWS STS client, and starts
 /// the Actix Web HTTP server.
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // Load the configuration from the config file, environment variables and CLI arguments
+    let args = config::AppArgs::load();
+
+    if args.print_config {
+        print!("{}", args.to_redacted_toml());
+        return Ok(());
+    }
+
+    match &args.command {
+        None | Some(config::AppCommand::Serve) => {}
+        Some(config::AppCommand::Check) => report_config_check(&args.check().await),
+        Some(command) => run_admin_command(command, &args).await,
+    }
+
+    // Validate the configuration before touching any dependency
+    let config_errors = args.check().await;
+    if !config_errors.is_empty() {
+        eprintln!("Invalid configuration:");
+        for e in &config_errors {
+            eprintln!("  - {}", e);
+        }
+        std::process::exit(1);
+    }
+
     // Print the application banner
     utils::banner::print_banner();
 
-    // Initialize the logger
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-
-    // Parse CLI arguments or Environment Variables
-    let args = config::AppArgs::parse();
+    // Initialize logging and tracing
+    let tracer_provider = match telemetry::init(&args.telemetry) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    info!("Connecting to Redis at {}", args.redis.url);
+    let redacted_redis = args.redacted().redis;
+    match args.redis.mode {
+        config::RedisMode::Standalone => info!("Connecting to Redis at {}", redacted_redis.url),
+        config::RedisMode::Sentinel => info!(
+            "Connecting to Redis master '{}' through Sentinels {}",
+            args.redis.sentinel_master,
+            redacted_redis.nodes.join(", ")
+        ),
+        config::RedisMode::Cluster => info!("Connecting to Redis Cluster through {}", redacted_redis.nodes.join(", ")),
+    }
 
     // Create the Redis connection pool
-    let redis_pool = match db::create_pool(&args.redis.url).await {
+    let redis_pool = match db::create_pool(&args.redis).await {
         Ok(pool) => {
             info!("Redis pool created successfully");
             pool
@@ -39,25 +93,225 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    // Set up the state and session stores
+    let (state_store, session_store) = match store::create_stores(&args.storage, &redis_pool).await {
+        Ok(stores) => stores,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "Storing login flows in {} and sessions in {}",
+        state_store.backend(),
+        session_store.backend()
+    );
+
+    // Set up the audit log sinks
+    let audit_log = build_audit_log(&args, &redis_pool);
+
+    // Register the Prometheus metrics
+    let metrics = match metrics::Metrics::new() {
+        Ok(m) => Arc::new(m),
+        Err(e) => {
+            error!("Could not register metrics: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Set up the ID token verifier and keep its JWKS fresh in the background
+    let id_token_verifier = Arc::new(id_token::IdTokenVerifier::new(
+        &args,
+        redis_pool.clone(),
+        metrics.clone(),
+    ));
+    let jwks_refresh = id_token_verifier.clone().spawn_refresh();
+
     // Initialize AWS STS Client
     let aws_config = aws_config::load_from_env().await;
     let sts_client = aws_sdk_sts::Client::new(&aws_config);
+    let credential_cache = match sts::CredentialCache::new(args.sts.cache_key.as_deref()) {
+        Ok(cache) => cache,
+        Err(e) => {
+            error!("Could not set up the STS credential cache: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if args.sts.cache_key.is_none() && args.sts.cache_min_lifetime_secs > 0 {
+        info!("No STS_CACHE_KEY set, cached STS credentials are private to this process");
+    }
+
+    // Load the pages shown at the end of a login
+    let pages = match pages::Pages::load(args.pages.templates_dir.as_deref()) {
+        Ok(pages) => pages,
+        Err(e) => {
+            error!("Could not load the page templates: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Set up TLS when a certificate is configured
+    let tls_config = match (&args.server.tls_cert, &args.server.tls_key) {
+        (Some(cert), Some(key)) => match tls::server_config(&args.server, cert, key) {
+            Ok((tls_config, resolver)) => {
+                tls::spawn_cert_reloader(resolver);
+                Some(tls_config)
+            }
+            Err(e) => {
+                error!("Could not set up TLS: {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    info!("Starting server at {}://{}", scheme, args.server.addr);
+
+    let shared_config = Arc::new(config::SharedConfig::from_pointee(args.clone()));
+    config::spawn_reloader(shared_config.clone());
+    let shutdown = Arc::new(shutdown::Shutdown::default());
+    let app_data = app::AppData {
+        redis_pool: web::Data::new(redis_pool.clone()),
+        state_store: web::Data::from(state_store),
+        session_store: web::Data::from(session_store),
+        config: web::Data::from(shared_config),
+        sts_client: web::Data::new(sts_client),
+        credential_cache: web::Data::new(credential_cache),
+        pages: web::Data::new(pages),
+        audit_log: web::Data::new(audit_log),
+        metrics: web::Data::from(metrics),
+        id_token_verifier: web::Data::from(id_token_verifier),
+        shutdown: web::Data::from(shutdown.clone()),
+    };
+
+    // Shutdown signals are handled by the application so that readiness is flipped before draining
+    let server = HttpServer::new(move || app::app(app_data.clone()))
+        .disable_signals()
+        .shutdown_timeout(args.shutdown.drain_timeout_secs);
+
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(&args.server.addr, tls_config)?,
+        None => server.bind(&args.server.addr)?,
+    }
+    .run();
+
+    // Optional plain HTTP listener redirecting to HTTPS
+    let redirect_server = match &args.server.http_redirect_addr {
+        Some(redirect_addr) => {
+            info!("Redirecting http://{} to HTTPS", redirect_addr);
+            let https_port = web::Data::new(handlers::redirect::HttpsPort(https_port(&args.server.addr)));
+            Some(
+                HttpServer::new(move || {
+                    App::new()
+                        .app_data(https_port.clone())
+                        .default_service(web::to(handlers::redirect::redirect_to_https))
+                })
+                .workers(1)
+                .disable_signals()
+                .bind(redirect_addr)?
+                .run(),
+            )
+        }
+        None => None,
+    };
+
+    let mut handles = vec![server.handle()];
+    handles.extend(redirect_server.as_ref().map(|s| s.handle()));
+    shutdown::spawn_signal_handler(handles, shutdown.clone(), args.shutdown.clone());
+
+    let result = match redirect_server {
+        Some(redirect_server) => {
+            let (result, redirect_result) = tokio::join!(server, redirect_server);
+            result.and(redirect_result)
+        }
+        None => server.await,
+    };
+
+    // The server and its workers are gone; stop the last background user of the pool and
+    // release the Redis connections
+    jwks_refresh.abort();
+    let _ = jwks_refresh.await;
+    let state = redis_pool.state();
+    drop(redis_pool);
+    info!(
+        "Closed Redis pool ({} connection(s), {} idle)",
+        state.connections, state.idle_connections
+    );
+
+    shutdown.log_summary();
+    telemetry::shutdown(tracer_provider);
+    result
+}
+
+/// Builds the audit log from the configured sinks.
+///
+/// Exits the process if a sink cannot be opened, since audit events would otherwise be lost.
+fn build_audit_log(args: &config::AppArgs, redis_pool: &db::RedisPool) -> AuditLog {
+    let mut sinks: Vec<Arc<dyn AuditSink>> = Vec::new();
+
+    for kind in &args.audit.sinks {
+        match kind {
+            AuditSinkKind::Redis => sinks.push(Arc::new(RedisStreamSink::new(
+                redis_pool.clone(),
+                args.audit.stream.clone(),
+                args.audit.stream_max_len,
+            ))),
+            AuditSinkKind::Jsonl => match JsonLinesSink::open(&args.audit.file) {
+                Ok(sink) => sinks.push(Arc::new(sink)),
+                Err(e) => {
+                    error!("Could not open audit file {}: {}", args.audit.file.display(), e);
+                    std::process::exit(1);
+                }
+            },
+        }
+    }
+
+    info!("Audit log writing to {:?}", args.audit.sinks);
+    AuditLog::new(sinks)
+}
+
+/// Prints the result of the `check` command and exits, with a non-zero code on errors.
+fn report_config_check(errors: &[config::ConfigError]) -> ! {
+    if errors.is_empty() {
+        println!("Configuration OK");
+        std::process::exit(0);
+    }
+    eprintln!("Configuration check failed with {} error(s):", errors.len());
+    for e in errors {
+        eprintln!("  - {}", e);
+    }
+    std::process::exit(1);
+}
+
+/// Runs an administration command, prints its output and exits, with a non-zero code on errors.
+///
+/// Only the static configuration checks are run, so that the commands work without Cognito.
+async fn run_admin_command(command: &config::AppCommand, args: &config::AppArgs) -> ! {
+    let errors = args.validate();
+    if !errors.is_empty() {
+        eprintln!("Invalid configuration:");
+        for e in &errors {
+            eprintln!("  - {}", e);
+        }
+        std::process::exit(1);
+    }
+
+    match admin::run(command, args).await {
+        Ok(output) => {
+            print!("{}", output);
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    info!("Starting server at http://{}", args.server.addr);
-
-    let pool_data = web::Data::new(redis_pool);
-    let app_args_data = web::Data::new(args.clone());
-    let sts_data = web::Data::new(sts_client);
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(pool_data.clone())
-            .app_data(app_args_data.clone())
-            .app_data(sts_data.clone())
-            .wrap(Logger::default())
-            .configure(routes::config)
-    })
-        .bind(&args.server.addr)?
-        .run()
-        .await
+/// Port of the HTTPS listener, used to build redirect URLs.
+fn https_port(addr: &str) -> u16 {
+    addr.rsplit_once(':')
+        .and_then(|(_, port)| port.parse().ok())
+        .unwrap_or(443)
 }
@@ -0,0 +1,159 @@
+use crate::db::RedisPool;
+use crate::schemas::auth::CliAuthResponse;
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::Mutex;
+
+/// Buckets (in seconds) used for the latency of outbound calls.
+const LATENCY_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Prometheus metrics collected by the service.
+///
+/// All metrics are registered in a dedicated registry rendered by the `/metrics` endpoint.
+pub struct Metrics {
+    registry: Registry,
+    /// Requests handled per route pattern and HTTP status code.
+    pub http_requests: IntCounterVec,
+    /// `CliAuthResponse` variants returned to CLI clients.
+    pub auth_responses: IntCounterVec,
+    /// Latency of Cognito token endpoint calls per grant type.
+    pub cognito_token_duration: HistogramVec,
+    /// Latency of JWKS downloads from Cognito.
+    pub jwks_fetch_duration: Histogram,
+    /// Latency of STS `AssumeRole` calls.
+    pub assume_role_duration: Histogram,
+    /// STS credential cache lookups per result.
+    credential_cache_lookups: IntCounterVec,
+    credential_cache_hit_ratio: Gauge,
+    redis_pool_connections: IntGauge,
+    redis_pool_idle_connections: IntGauge,
+    redis_pool_gets_waited: IntCounter,
+    redis_pool_gets_timed_out: IntCounter,
+    /// Serializes the sampling of the pool statistics into the counters.
+    pool_sampling: Mutex<()>,
+}
+
+impl Metrics {
+    /// Creates and registers all metrics.
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("mega_auth".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests handled per route and status code"),
+            &["handler", "status"],
+        )?;
+        let auth_responses = IntCounterVec::new(
+            Opts::new("cli_auth_responses_total", "CLI authentication responses per status"),
+            &["status"],
+        )?;
+        let cognito_token_duration = HistogramVec::new(
+            HistogramOpts::new("cognito_token_duration_seconds", "Latency of Cognito token endpoint calls")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["grant_type"],
+        )?;
+        let jwks_fetch_duration = Histogram::with_opts(
+            HistogramOpts::new("jwks_fetch_duration_seconds", "Latency of JWKS downloads")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+        )?;
+        let assume_role_duration = Histogram::with_opts(
+            HistogramOpts::new("sts_assume_role_duration_seconds", "Latency of STS AssumeRole calls")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+        )?;
+        let credential_cache_lookups = IntCounterVec::new(
+            Opts::new("sts_credential_cache_lookups_total", "STS credential cache lookups per result"),
+            &["result"],
+        )?;
+        let credential_cache_hit_ratio = Gauge::new(
+            "sts_credential_cache_hit_ratio",
+            "Share of STS credential cache lookups served from the cache since startup",
+        )?;
+        let redis_pool_connections =
+            IntGauge::new("redis_pool_connections", "Connections currently managed by the Redis pool")?;
+        let redis_pool_idle_connections =
+            IntGauge::new("redis_pool_idle_connections", "Idle connections in the Redis pool")?;
+        let redis_pool_gets_waited = IntCounter::new(
+            "redis_pool_gets_waited_total",
+            "Connection checkouts that had to wait for a connection",
+        )?;
+        let redis_pool_gets_timed_out = IntCounter::new(
+            "redis_pool_gets_timed_out_total",
+            "Connection checkouts that timed out waiting for a connection",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(auth_responses.clone()))?;
+        registry.register(Box::new(cognito_token_duration.clone()))?;
+        registry.register(Box::new(jwks_fetch_duration.clone()))?;
+        registry.register(Box::new(assume_role_duration.clone()))?;
+        registry.register(Box::new(credential_cache_lookups.clone()))?;
+        registry.register(Box::new(credential_cache_hit_ratio.clone()))?;
+        registry.register(Box::new(redis_pool_connections.clone()))?;
+        registry.register(Box::new(redis_pool_idle_connections.clone()))?;
+        registry.register(Box::new(redis_pool_gets_waited.clone()))?;
+        registry.register(Box::new(redis_pool_gets_timed_out.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            auth_responses,
+            cognito_token_duration,
+            jwks_fetch_duration,
+            assume_role_duration,
+            credential_cache_lookups,
+            credential_cache_hit_ratio,
+            redis_pool_connections,
+            redis_pool_idle_connections,
+            redis_pool_gets_waited,
+            redis_pool_gets_timed_out,
+            pool_sampling: Mutex::new(()),
+        })
+    }
+
+    /// Counts a `CliAuthResponse` returned to a CLI client.
+    pub fn observe_auth_response(&self, response: &CliAuthResponse) {
+        self.auth_responses
+            .with_label_values(&[response.status_name()])
+            .inc();
+    }
+
+    /// Counts an STS credential cache lookup: `hit`, `miss` or `expiring`.
+    pub fn observe_credential_cache(&self, result: &str) {
+        self.credential_cache_lookups.with_label_values(&[result]).inc();
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    ///
+    /// Redis pool metrics are sampled from the pool state, and the credential cache hit ratio
+    /// computed from the lookup counters, at render time. The pool checkout counters are advanced
+    /// by what the pool counted since the previous render.
+    pub fn render(&self, pool: &RedisPool) -> Result<String, prometheus::Error> {
+        let state = pool.state();
+        self.redis_pool_connections.set(state.connections as i64);
+        self.redis_pool_idle_connections.set(state.idle_connections as i64);
+        {
+            let _sampling = self.pool_sampling.lock().unwrap_or_else(|e| e.into_inner());
+            advance(&self.redis_pool_gets_waited, state.statistics.get_waited);
+            advance(&self.redis_pool_gets_timed_out, state.statistics.get_timed_out);
+        }
+
+        let hits = self.credential_cache_lookups.with_label_values(&["hit"]).get();
+        let lookups: u64 = ["hit", "miss", "expiring"]
+            .iter()
+            .map(|result| self.credential_cache_lookups.with_label_values(&[result]).get())
+            .sum();
+        if lookups > 0 {
+            self.credential_cache_hit_ratio.set(hits as f64 / lookups as f64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+}
+
+/// Advances a counter to the running total kept by the Redis pool.
+fn advance(counter: &IntCounter, total: u64) {
+    counter.inc_by(total.saturating_sub(counter.get()));
+}
@@ -0,0 +1,24 @@
+use crate::metrics::Metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+
+/// Middleware counting every handled request by route pattern and status code.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let res = next.call(req).await?;
+
+    if let Some(metrics) = metrics {
+        let handler = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        metrics
+            .http_requests
+            .with_label_values(&[handler.as_str(), res.status().as_str()])
+            .inc();
+    }
+
+    Ok(res)
+}
@@ -0,0 +1,3 @@
+pub mod metrics;
+pub mod rate_limit;
+pub mod shutdown;
@@ -0,0 +1,143 @@
+use crate::config::{RateLimitConfig, SharedConfig};
+use crate::error::AppError;
+use crate::handlers::auth::utils::{get_client_ip, get_rate_limit_key};
+use crate::store::StateStore;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+
+/// Authentication routes protected by the rate limiter.
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimitedRoute {
+    Start,
+    Callback,
+    Confirm,
+    Status,
+    Renew,
+    Logout,
+    ContainerToken,
+    ContainerCredentials,
+}
+
+impl RateLimitedRoute {
+    /// Name used to build the counter keys of the route.
+    fn name(self) -> &'static str {
+        match self {
+            RateLimitedRoute::Start => "start",
+            RateLimitedRoute::Callback => "callback",
+            RateLimitedRoute::Confirm => "confirm",
+            RateLimitedRoute::Status => "status",
+            RateLimitedRoute::Renew => "renew",
+            RateLimitedRoute::Logout => "logout",
+            RateLimitedRoute::ContainerToken => "container_token",
+            RateLimitedRoute::ContainerCredentials => "container_credentials",
+        }
+    }
+
+    /// Returns the configured `(per_ip, per_state)` limits of the route.
+    fn limits(self, config: &RateLimitConfig) -> (u64, u64) {
+        match self {
+            RateLimitedRoute::Start => (config.start_per_ip, 0),
+            RateLimitedRoute::Callback => (config.callback_per_ip, 0),
+            RateLimitedRoute::Confirm => (config.confirm_per_ip, 0),
+            RateLimitedRoute::Status => (config.status_per_ip, config.status_per_state),
+            RateLimitedRoute::Renew => (config.renew_per_ip, 0),
+            RateLimitedRoute::Logout => (config.logout_per_ip, 0),
+            RateLimitedRoute::ContainerToken => (config.container_token_per_ip, 0),
+            RateLimitedRoute::ContainerCredentials => (config.container_credentials_per_ip, 0),
+        }
+    }
+}
+
+/// Query parameters inspected to key limits by authentication state.
+#[derive(Deserialize)]
+struct StateParam {
+    state: Option<String>,
+}
+
+/// Response of the rate limiting middleware around a service answering with `B` bodies.
+type Limited<B> = LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, Error>>;
+
+/// Rate limiting middleware of a route, to be wrapped with `from_fn`.
+pub fn limit<S, B>(route: RateLimitedRoute) -> impl Fn(ServiceRequest, Next<B>) -> Limited<B>
+where
+    S: StateStore + ?Sized,
+    B: MessageBody + 'static,
+{
+    move |req, next| Box::pin(enforce::<S, B>(route, req, next))
+}
+
+/// Counts the request against the client IP and state limits of the route.
+///
+/// Requests over a limit are answered with a `rate_limited` problem (`429 Too Many Requests`)
+/// and a `Retry-After` header. If the state store is unavailable the request is let through, since the
+/// handler will report the failure anyway.
+async fn enforce<S, B>(
+    route: RateLimitedRoute,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    S: StateStore + ?Sized,
+    B: MessageBody + 'static,
+{
+    let store = req.app_data::<web::Data<S>>().cloned();
+    let config = req.app_data::<web::Data<SharedConfig>>().map(|c| c.load_full());
+
+    if let (Some(store), Some(config)) = (store, config) {
+        let (per_ip, per_state) = route.limits(&config.rate_limit);
+        let window = config.rate_limit.window_secs;
+
+        // Forwarding headers are only honoured from trusted proxies
+        let ip = get_client_ip(req.request());
+        if let Some(ip) = ip.filter(|_| per_ip > 0)
+            && let Some(retry_after) = check_limit(store.get_ref(), route, "ip", &ip, per_ip, window).await
+        {
+            return Ok(too_many_requests(req, retry_after));
+        }
+
+        let state = web::Query::<StateParam>::from_query(req.query_string())
+            .ok()
+            .and_then(|q| q.into_inner().state);
+        if let Some(state) = state.filter(|_| per_state > 0)
+            && let Some(retry_after) = check_limit(store.get_ref(), route, "state", &state, per_state, window).await
+        {
+            return Ok(too_many_requests(req, retry_after));
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// Increments the counter of a client identifier and returns the seconds to wait if over the limit.
+async fn check_limit<S: StateStore + ?Sized>(
+    store: &S,
+    route: RateLimitedRoute,
+    scope: &str,
+    id: &str,
+    limit: u64,
+    window: u64,
+) -> Option<u64> {
+    let key = get_rate_limit_key(route.name(), scope, id);
+
+    match store.count_request(&key, window).await {
+        Ok((count, ttl)) if count > limit => {
+            log::warn!("Rate limit exceeded on {} for {} {}", route.name(), scope, id);
+            Some(ttl.max(1))
+        }
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("Rate limiting skipped on {}: {}", route.name(), e);
+            None
+        }
+    }
+}
+
+/// Builds the `429 Too Many Requests` response for a rejected request.
+fn too_many_requests<B>(req: ServiceRequest, retry_after: u64) -> ServiceResponse<EitherBody<B>> {
+    let response = AppError::RateLimited { retry_after }.error_response();
+    req.into_response(response).map_into_right_body()
+}
@@ -0,0 +1,20 @@
+use crate::shutdown::Shutdown;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+
+/// Middleware counting in-flight requests, so that a graceful shutdown can report what it drained.
+pub async fn track_in_flight(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(shutdown) = req.app_data::<web::Data<Shutdown>>().cloned() else {
+        return next.call(req).await;
+    };
+
+    let in_flight = shutdown.enter();
+    let res = next.call(req).await;
+    in_flight.finish();
+    res
+}
@@ -0,0 +1,110 @@
+use crate::handlers;
+use crate::schemas;
+use actix_web::{get, HttpResponse, Responder};
+use std::sync::LazyLock;
+use utoipa::openapi::path::Operation;
+use utoipa::openapi::OpenApi as OpenApiDocument;
+use utoipa::OpenApi;
+
+/// Path where the generated OpenAPI document is served.
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// Path where the interactive API docs are served.
+pub const DOCS_PATH: &str = "/docs";
+
+/// OpenAPI description of the service, generated from the handler annotations and schema types.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        handlers::info::info,
+        handlers::health::healthz,
+        handlers::health::readyz,
+        handlers::metrics::metrics,
+        openapi_json,
+        handlers::auth::cli_start::auth_cli_start,
+        handlers::auth::cli_callback::auth_cli_callback,
+        handlers::auth::cli_confirm::auth_cli_confirm,
+        handlers::auth::cli_status::auth_cli_status,
+        handlers::auth::cli_renew::auth_cli_renew,
+        handlers::auth::cli_logout::auth_cli_logout,
+        handlers::auth::container::auth_cli_container_token,
+        handlers::auth::container::auth_cli_container_credentials,
+    ),
+    components(schemas(
+        schemas::auth::CliAuthStartRequest,
+        schemas::auth::CliAuthStartResponse,
+        schemas::auth::CliConfirmForm,
+        schemas::auth::ConfirmDecision,
+        schemas::auth::CliRenewRequest,
+        schemas::auth::CliLogoutRequest,
+        schemas::auth::CliAuthResponse,
+        schemas::auth::CredentialFormat,
+        schemas::auth::CredentialProcessOutput,
+        schemas::auth::ContainerTokenRequest,
+        schemas::auth::ContainerTokenResponse,
+        schemas::auth::ContainerCredentials,
+        schemas::health::HealthStatus,
+        schemas::health::LivenessResponse,
+        schemas::health::DependencyCheck,
+        schemas::health::ReadinessResponse,
+        schemas::error::ProblemDetails,
+    )),
+    tags(
+        (name = "info", description = "Service information and API documentation"),
+        (name = "health", description = "Kubernetes liveness and readiness probes"),
+        (name = "metrics", description = "Prometheus metrics"),
+        (name = "auth", description = "CLI authentication flow"),
+    )
+)]
+pub struct ApiDoc;
+
+/// The generated document, built once on first use.
+static DOCUMENT: LazyLock<OpenApiDocument> = LazyLock::new(ApiDoc::openapi);
+
+/// A single route of the public API, as listed in the banner and the info page.
+pub struct RouteInfo {
+    pub method: &'static str,
+    pub path: String,
+    pub summary: String,
+    pub description: String,
+}
+
+/// Returns the route registry derived from the OpenAPI document.
+///
+/// Routes are sorted by path, as in the generated document.
+pub fn routes() -> Vec<RouteInfo> {
+    let mut routes = Vec::new();
+    for (path, item) in DOCUMENT.paths.paths.iter() {
+        let operations: [(&'static str, &Option<Operation>); 4] = [
+            ("GET", &item.get),
+            ("POST", &item.post),
+            ("PUT", &item.put),
+            ("DELETE", &item.delete),
+        ];
+        for (method, operation) in operations {
+            if let Some(operation) = operation {
+                routes.push(RouteInfo {
+                    method,
+                    path: path.clone(),
+                    summary: operation.summary.clone().unwrap_or_default(),
+                    description: operation.description.clone().unwrap_or_default(),
+                });
+            }
+        }
+    }
+    routes
+}
+
+/// Handler serving the generated OpenAPI document.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "info",
+    summary = "OpenAPI document",
+    description = "OpenAPI 3 description of this API. An interactive viewer is served at /docs.",
+    responses((status = 200, description = "OpenAPI document", content_type = "application/json"))
+)]
+#[get("/openapi.json")]
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(&*DOCUMENT)
+}
@@ -0,0 +1,201 @@
+use crate::error::AppError;
+use crate::schemas::auth::CliAuthState;
+use actix_web::http::header::{ContentType, ACCEPT, CACHE_CONTROL, CONTENT_SECURITY_POLICY, X_FRAME_OPTIONS};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use minijinja::Environment;
+use serde::Serialize;
+use std::path::Path;
+
+/// Built-in templates, each replaced by the file of the same name in the templates directory.
+///
+/// The pages extend `base.html`, so overriding it alone rebrands all of them.
+const TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", include_str!("../templates/base.html")),
+    ("confirm.html", include_str!("../templates/confirm.html")),
+    ("success.html", include_str!("../templates/success.html")),
+    ("denied.html", include_str!("../templates/denied.html")),
+    ("expired.html", include_str!("../templates/expired.html")),
+    ("error.html", include_str!("../templates/error.html")),
+];
+
+/// Page shown in the browser at the end of a login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    /// The user logged in and must confirm the device that started the login.
+    Confirm,
+    /// The user confirmed the device and the CLI can fetch its credentials.
+    Success,
+    /// The identity provider refused the login, or its result could not be verified.
+    Denied,
+    /// The login link is unknown, already used or expired.
+    Expired,
+    /// The login failed on the server side.
+    Error,
+}
+
+impl Page {
+    const ALL: [Page; 5] = [Page::Confirm, Page::Success, Page::Denied, Page::Expired, Page::Error];
+
+    fn template(self) -> &'static str {
+        match self {
+            Page::Confirm => "confirm.html",
+            Page::Success => "success.html",
+            Page::Denied => "denied.html",
+            Page::Expired => "expired.html",
+            Page::Error => "error.html",
+        }
+    }
+
+    /// Page describing a failed login.
+    pub fn for_error(error: &AppError) -> Self {
+        match error {
+            AppError::InvalidState | AppError::InvalidAuthorizationCode => Page::Expired,
+            AppError::InvalidIdToken(_) => Page::Denied,
+            _ => Page::Error,
+        }
+    }
+}
+
+/// Values available to the page templates.
+#[derive(Serialize, Default)]
+pub struct PageContext {
+    /// Device, OS and CLI version sent by the CLI that started the login, when known.
+    pub device_name: Option<String>,
+    pub os: Option<String>,
+    pub cli_version: Option<String>,
+    /// IP address and verification code of the CLI that started the login.
+    pub ip: Option<String>,
+    pub verification_code: Option<String>,
+    /// Login being confirmed: its state, the token proving the page was served to this
+    /// browser, and the email of the user.
+    pub state: Option<String>,
+    pub confirmation_token: Option<String>,
+    pub email: Option<String>,
+    /// HTTP status of the page.
+    pub status: u16,
+    /// Problem title, detail and code of a failed login.
+    pub title: Option<String>,
+    pub detail: Option<String>,
+    pub code: Option<String>,
+}
+
+impl PageContext {
+    /// Context of a login started from the given CLI.
+    pub fn new(auth_state: Option<&CliAuthState>) -> Self {
+        Self {
+            device_name: auth_state.and_then(|s| s.device_name.clone()),
+            os: auth_state.and_then(|s| s.os.clone()),
+            cli_version: auth_state.and_then(|s| s.cli_version.clone()),
+            ip: auth_state.and_then(|s| s.ip.clone()),
+            verification_code: auth_state.map(|s| s.verification_code.clone()),
+            status: StatusCode::OK.as_u16(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the form fields confirming the login of `state`.
+    pub fn with_confirmation(self, state: &str, token: &str, email: Option<&str>) -> Self {
+        Self {
+            state: Some(state.to_string()),
+            confirmation_token: Some(token.to_string()),
+            email: email.map(str::to_string),
+            ..self
+        }
+    }
+
+    /// Describes the error that made the login fail.
+    pub fn with_error(self, error: &AppError) -> Self {
+        let problem = error.problem();
+        Self {
+            status: problem.status,
+            title: Some(problem.title),
+            detail: problem.detail,
+            code: Some(problem.code),
+            ..self
+        }
+    }
+}
+
+/// Templates of the pages shown at the end of a login.
+pub struct Pages {
+    env: Environment<'static>,
+}
+
+impl Pages {
+    /// Loads the built-in templates, replaced by those found in `dir`.
+    ///
+    /// Every page is rendered once, so that broken templates are reported at startup.
+    pub fn load(dir: Option<&Path>) -> Result<Self, String> {
+        if let Some(dir) = dir
+            && !dir.is_dir()
+        {
+            return Err(format!("{} is not a directory", dir.display()));
+        }
+
+        let mut env = Environment::new();
+        for (name, built_in) in TEMPLATES {
+            let source = match dir.map(|dir| dir.join(name)).filter(|path| path.is_file()) {
+                Some(path) => std::fs::read_to_string(&path)
+                    .map_err(|e| format!("could not read {}: {}", path.display(), e))?,
+                None => built_in.to_string(),
+            };
+            env.add_template_owned(*name, source)
+                .map_err(|e| format!("invalid template {}: {}", name, e))?;
+        }
+
+        let pages = Self { env };
+        for page in Page::ALL {
+            pages
+                .try_render(page, &PageContext::default())
+                .map_err(|e| format!("could not render {}: {}", page.template(), e))?;
+        }
+        Ok(pages)
+    }
+
+    /// Renders a page, falling back to plain text if its template fails.
+    pub fn render(&self, page: Page, context: &PageContext) -> HttpResponse {
+        let status = StatusCode::from_u16(context.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        match self.try_render(page, context) {
+            // The confirmation page must not be framed by another site, nor cached with its token
+            Ok(html) => HttpResponse::build(status)
+                .content_type(ContentType::html())
+                .insert_header((X_FRAME_OPTIONS, "DENY"))
+                .insert_header((CONTENT_SECURITY_POLICY, "frame-ancestors 'none'"))
+                .insert_header((CACHE_CONTROL, "no-store"))
+                .body(html),
+            Err(e) => {
+                log::error!("Could not render {}: {}", page.template(), e);
+                let text = match page {
+                    Page::Confirm => "The confirmation page is unavailable. Please run the login command again.",
+                    Page::Success => "Authentication successful! You can now close this window.",
+                    _ => "Authentication failed. Please run the login command again.",
+                };
+                HttpResponse::build(status).content_type(ContentType::plaintext()).body(text)
+            }
+        }
+    }
+
+    /// Answers a failed login with a result page for browsers and problem details for other clients.
+    pub fn render_error(
+        &self,
+        req: &HttpRequest,
+        page: Page,
+        auth_state: Option<&CliAuthState>,
+        error: AppError,
+    ) -> Result<HttpResponse, AppError> {
+        let accepts_html = req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"));
+        if !accepts_html {
+            return Err(error);
+        }
+        Ok(self.render(page, &PageContext::new(auth_state).with_error(&error)))
+    }
+
+    fn try_render(&self, page: Page, context: &PageContext) -> Result<String, minijinja::Error> {
+        self.env.get_template(page.template())?.render(context)
+    }
+}
@@ -1,14 +1,88 @@
+use crate::error::AppError;
 use crate::handlers;
+use crate::middleware::rate_limit::{self, RateLimitedRoute};
+use crate::openapi::{self, DOCS_PATH, OPENAPI_PATH};
+use crate::store::{SessionStore, StateStore};
+use actix_web::middleware::from_fn;
 use actix_web::web;
+use utoipa_rapidoc::RapiDoc;
 
 /// Configures the application routes.
-pub fn config(cfg: &mut web::ServiceConfig) {
+///
+/// Handlers using storage read the state store from `web::Data<S>` and the session store
+/// from `web::Data<T>`.
+pub fn config<S, T>(cfg: &mut web::ServiceConfig)
+where
+    S: StateStore + ?Sized,
+    T: SessionStore + ?Sized,
+{
+    // Report malformed bodies and query strings as problem details
+    cfg.app_data(
+        web::JsonConfig::default()
+            .error_handler(|err, _| AppError::InvalidRequest(err.to_string()).into()),
+    );
+    cfg.app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _| AppError::InvalidRequest(err.to_string()).into()),
+    );
+    cfg.app_data(
+        web::FormConfig::default()
+            .error_handler(|err, _| AppError::InvalidRequest(err.to_string()).into()),
+    );
+
     // Info route
     cfg.service(handlers::info::info);
 
+    // API documentation routes
+    cfg.service(openapi::openapi_json);
+    cfg.service(RapiDoc::new(OPENAPI_PATH).path(DOCS_PATH));
+
+    // Health routes
+    cfg.service(handlers::health::healthz);
+    cfg.route("/readyz", web::get().to(handlers::health::readyz::<S, T>));
+
+    // Metrics route
+    cfg.service(handlers::metrics::metrics);
+
     // Authentication routes
-    cfg.service(handlers::auth::auth_cli_start);
-    cfg.service(handlers::auth::auth_cli_callback);
-    cfg.service(handlers::auth::auth_cli_status);
-    cfg.service(handlers::auth::auth_cli_renew);
+    cfg.service(
+        web::resource("/auth/cli/start")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::Start)))
+            .route(web::post().to(handlers::auth::auth_cli_start::<S>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/callback")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::Callback)))
+            .route(web::get().to(handlers::auth::auth_cli_callback::<S>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/confirm")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::Confirm)))
+            .route(web::post().to(handlers::auth::auth_cli_confirm::<S, T>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/status")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::Status)))
+            .route(web::get().to(handlers::auth::auth_cli_status::<S, T>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/renew")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::Renew)))
+            .route(web::post().to(handlers::auth::auth_cli_renew::<S, T>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/logout")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::Logout)))
+            .route(web::post().to(handlers::auth::auth_cli_logout::<T>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/container-token")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::ContainerToken)))
+            .route(web::post().to(handlers::auth::auth_cli_container_token::<S, T>)),
+    );
+    cfg.service(
+        web::resource("/auth/cli/container-credentials")
+            .wrap(from_fn(rate_limit::limit::<S, _>(RateLimitedRoute::ContainerCredentials)))
+            .route(web::get().to(handlers::auth::auth_cli_container_credentials::<S, T>)),
+    );
 }
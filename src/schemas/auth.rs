@@ -1,7 +1,14 @@
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Request header in which the CLI reports its version.
+pub const CLI_VERSION_HEADER: &str = "X-CLI-Version";
+
+/// Response header warning a CLI that its version is deprecated.
+pub const CLI_DEPRECATION_HEADER: &str = "X-CLI-Deprecation";
 
 /// Request payload to start the CLI authentication process.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct CliAuthStartRequest {
     /// Friendly name of the device initiating the request.
     pub device_name: Option<String>,
@@ -12,21 +19,63 @@ pub struct CliAuthStartRequest {
 }
 
 /// Response containing the authorization URL for the CLI client.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct CliAuthStartResponse {
     /// The URL the user must open in their browser to log in.
     pub auth_url: String,
     /// Time in seconds until the authorization request expires.
     pub expires_in: u64,
+    /// Minimum number of seconds the CLI must wait between two status polls.
+    pub interval: u64,
+    /// Short code the CLI displays, which the browser shows again before the user confirms the login.
+    pub verification_code: String,
 }
 
 /// Internal state stored during the authentication process.
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CliAuthState {
     pub device_name: Option<String>,
     pub os: Option<String>,
     pub cli_version: Option<String>,
     pub created_at: i64,
+    /// IP address the login was started from.
+    #[serde(default)]
+    pub ip: Option<String>,
+    /// Verification code displayed by the CLI.
+    #[serde(default)]
+    pub verification_code: String,
+}
+
+/// Login of a user waiting for them to confirm the device that started it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingConfirmation {
+    /// Hash of the confirmation token embedded in the confirmation page.
+    pub token_hash: String,
+    pub user_sub: String,
+    pub email: Option<String>,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp (seconds) at which the login expires unconfirmed.
+    #[serde(default)]
+    pub expires_at: i64,
+}
+
+/// Form submitted from the device confirmation page.
+#[derive(Deserialize, ToSchema)]
+pub struct CliConfirmForm {
+    /// State of the login being confirmed.
+    pub state: String,
+    /// Confirmation token of the page.
+    pub token: String,
+    /// `confirm` to let the device in, `reject` to cancel the login.
+    pub decision: ConfirmDecision,
+}
+
+/// Answer of the user on the device confirmation page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmDecision {
+    Confirm,
+    Reject,
 }
 
 /// Response from the identity provider containing OAuth2 tokens.
@@ -52,20 +101,116 @@ pub struct IdTokenClaims {
 }
 
 /// Query parameters for checking the status of an authentication request.
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 pub struct CliStatusQuery {
     /// The state identifier generated at the start of the process.
     pub state: String,
 }
 
+/// Formats in which authorized credentials can be returned.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialFormat {
+    /// The `CliAuthResponse` JSON.
+    #[default]
+    Json,
+    /// The JSON the AWS SDKs expect from a `credential_process` command.
+    CredentialProcess,
+    /// `export` statements for a POSIX shell.
+    Env,
+    /// A profile section for the `~/.aws/credentials` file.
+    Credentials,
+}
+
+/// Query parameters choosing how authorized credentials are returned.
+#[derive(Deserialize, IntoParams)]
+pub struct CredentialFormatQuery {
+    /// Output format, taking precedence over the `Accept` header.
+    pub format: Option<CredentialFormat>,
+    /// Profile name of the `credentials` format (`default` when omitted).
+    pub profile: Option<String>,
+    /// Whether the `env` and `credentials` formats carry the refresh token in a comment.
+    /// These outputs tend to end up in files, so it is left out unless asked for.
+    #[serde(default)]
+    pub include_refresh_token: bool,
+}
+
+/// Credentials in the shape read by the AWS SDKs from a `credential_process` command.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "PascalCase")]
+pub struct CredentialProcessOutput {
+    /// Version of the output format, always 1.
+    pub version: u8,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    /// ISO-8601 expiration of the credentials.
+    pub expiration: String,
+    /// Refresh token to renew the session with, which may have been rotated. The AWS SDKs
+    /// ignore it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
 /// Request payload to renew an expired session.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct CliRenewRequest {
     /// The refresh token previously issued by Cognito.
     pub refresh_token: String,
 }
 
-/// Session data stored in Redis after successful authentication.
+/// Request payload to end a CLI session.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CliLogoutRequest {
+    /// The refresh token issued to the CLI session being ended.
+    pub refresh_token: String,
+}
+
+/// Request payload to obtain a container credentials token for a CLI session.
+#[derive(Deserialize, ToSchema)]
+pub struct ContainerTokenRequest {
+    /// The refresh token issued to the CLI session.
+    pub refresh_token: String,
+}
+
+/// Token authorizing local tools to fetch credentials from the container credentials endpoint.
+#[derive(Serialize, ToSchema)]
+pub struct ContainerTokenResponse {
+    /// Value for `AWS_CONTAINER_AUTHORIZATION_TOKEN`.
+    pub token: String,
+    /// Value for `AWS_CONTAINER_CREDENTIALS_FULL_URI`.
+    pub credentials_uri: String,
+    /// Time in seconds until the token expires, unless its session ends first.
+    pub expires_in: u64,
+    /// New refresh token, when the identity provider rotated the one sent. It replaces the
+    /// previous one for later requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+/// Internal record of an issued container credentials token.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContainerTokenData {
+    pub user_sub: String,
+    /// Hash of the refresh token of the session the token was issued for, so that a later
+    /// login of the same user does not revive it.
+    pub session_fingerprint: String,
+}
+
+/// Credentials in the shape read by the AWS SDKs from a container credentials endpoint.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub token: String,
+    /// ISO-8601 expiration of the credentials.
+    pub expiration: String,
+    /// IAM role the credentials were issued for.
+    pub role_arn: String,
+}
+
+/// Session data stored after successful authentication.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CliSessionData {
     /// Unique identifier for the user.
@@ -77,6 +222,10 @@ pub struct CliSessionData {
     /// Whether the session is still valid.
     #[serde(default = "default_active")]
     pub active: bool,
+    /// Version the CLI reported when starting the login, checked on renewal when the CLI
+    /// does not send its version.
+    #[serde(default)]
+    pub cli_version: Option<String>,
 }
 
 /// Default value for the 'active' field in CliSessionData.
@@ -85,11 +234,19 @@ fn default_active() -> bool {
 }
 
 /// Possible responses for a CLI authentication status check.
-#[derive(Serialize)]
+///
+/// Variant names are part of the wire format, hence the upper-case spelling.
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(tag = "status")]
 pub enum CliAuthResponse {
     /// Authentication is still in progress.
     PENDING,
+    /// The CLI is polling too fast and must wait before polling again.
+    SLOW_DOWN {
+        /// Minimum number of seconds between two status polls.
+        interval: u64,
+    },
     /// The authentication request has expired.
     EXPIRED,
     /// Authentication was explicitly denied.
@@ -108,3 +265,16 @@ pub enum CliAuthResponse {
         refresh_token: Option<String>,
     },
 }
+
+impl CliAuthResponse {
+    /// Returns the wire name of the status, as found in the `status` field.
+    pub fn status_name(&self) -> &'static str {
+        match self {
+            CliAuthResponse::PENDING => "PENDING",
+            CliAuthResponse::SLOW_DOWN { .. } => "SLOW_DOWN",
+            CliAuthResponse::EXPIRED => "EXPIRED",
+            CliAuthResponse::DENIED => "DENIED",
+            CliAuthResponse::AUTHORIZED { .. } => "AUTHORIZED",
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+/// Status of the service or of one of its dependencies.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Response of the liveness probe.
+#[derive(Serialize, ToSchema)]
+pub struct LivenessResponse {
+    pub status: HealthStatus,
+}
+
+/// Result of checking a single dependency.
+#[derive(Serialize, ToSchema)]
+pub struct DependencyCheck {
+    pub status: HealthStatus,
+    /// Time spent checking the dependency, in milliseconds.
+    pub latency_ms: f64,
+    /// Reason of the failure when the dependency is down.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Response of the readiness probe.
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    /// `up` only when every dependency is up.
+    pub status: HealthStatus,
+    /// Per-dependency results, keyed by dependency name.
+    pub checks: BTreeMap<&'static str, DependencyCheck>,
+}
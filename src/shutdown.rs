@@ -0,0 +1,139 @@
+use crate::config::ShutdownConfig;
+use actix_web::dev::ServerHandle;
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shared shutdown state, used to flip readiness, refuse new logins and count in-flight requests.
+#[derive(Default)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    started_at: Mutex<Option<Instant>>,
+    in_flight: AtomicU64,
+    in_flight_at_signal: AtomicU64,
+    completed_while_draining: AtomicU64,
+    abandoned: AtomicU64,
+    rejected_logins: AtomicU64,
+}
+
+impl Shutdown {
+    /// Whether a shutdown has been requested.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Counts a login refused because the server is shutting down.
+    pub fn reject_login(&self) {
+        self.rejected_logins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks a request as in flight until the returned guard is finished or dropped.
+    pub fn enter(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight {
+            shutdown: self,
+            finished: false,
+        }
+    }
+
+    /// Starts draining: readiness fails and new logins are refused from now on.
+    pub fn begin(&self) {
+        *self.started_at.lock().unwrap() = Some(Instant::now());
+        self.in_flight_at_signal
+            .store(self.in_flight.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Logs how the shutdown went. Does nothing if the server stopped without a shutdown signal.
+    pub fn log_summary(&self) {
+        let Some(started_at) = *self.started_at.lock().unwrap() else {
+            return;
+        };
+        log::info!(
+            "Shutdown complete in {:.1}s: {} request(s) in flight at signal, {} completed while draining, \
+             {} abandoned at the drain timeout, {} login(s) refused",
+            started_at.elapsed().as_secs_f64(),
+            self.in_flight_at_signal.load(Ordering::SeqCst),
+            self.completed_while_draining.load(Ordering::SeqCst),
+            self.abandoned.load(Ordering::SeqCst),
+            self.rejected_logins.load(Ordering::SeqCst),
+        );
+    }
+}
+
+/// Guard of an in-flight request.
+///
+/// A guard dropped without [`InFlight::finish`] belongs to a request cut off by the drain timeout.
+pub struct InFlight<'a> {
+    shutdown: &'a Shutdown,
+    finished: bool,
+}
+
+impl InFlight<'_> {
+    /// Marks the request as completed.
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if self.shutdown.is_draining() {
+            let counter = if self.finished {
+                &self.shutdown.completed_while_draining
+            } else {
+                &self.shutdown.abandoned
+            };
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Waits for SIGTERM or SIGINT and shuts the servers down gracefully with [`drain`].
+pub fn spawn_signal_handler(servers: Vec<ServerHandle>, shutdown: Arc<Shutdown>, config: ShutdownConfig) {
+    actix_web::rt::spawn(async move {
+        let (mut terminate, mut interrupt) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+            (Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+            (Err(e), _) | (_, Err(e)) => {
+                log::error!("Could not listen for shutdown signals: {}", e);
+                return;
+            }
+        };
+
+        let name = tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+        };
+
+        drain(servers, &shutdown, &config, name).await;
+    });
+}
+
+/// Shuts the servers down gracefully after receiving `reason`.
+///
+/// Readiness is flipped first and kept failing for the configured delay, so that load
+/// balancers stop sending traffic while the listeners are still open. The servers then stop
+/// accepting connections and wait for in-flight requests, up to the drain timeout set on
+/// each server.
+pub async fn drain(servers: Vec<ServerHandle>, shutdown: &Shutdown, config: &ShutdownConfig, reason: &str) {
+    shutdown.begin();
+    log::info!(
+        "Received {}, failing readiness for {}s before draining {} in-flight request(s) (timeout {}s)",
+        reason,
+        config.readiness_delay_secs,
+        shutdown.in_flight.load(Ordering::SeqCst),
+        config.drain_timeout_secs
+    );
+
+    actix_web::rt::time::sleep(Duration::from_secs(config.readiness_delay_secs)).await;
+
+    log::info!(
+        "Stopping the listeners, {} request(s) in flight",
+        shutdown.in_flight.load(Ordering::SeqCst)
+    );
+    for server in servers {
+        server.stop(true).await;
+    }
+}
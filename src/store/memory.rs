@@ -0,0 +1,195 @@
+use crate::error::AppError;
+use crate::handlers::auth::utils::get_sts_cache_key;
+use crate::schemas::auth::{CliAuthState, CliSessionData, ContainerTokenData, PendingConfirmation};
+use crate::store::{SessionStats, SessionStore, StateStats, StateStore};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Store keeping everything in the process memory, for development and tests.
+///
+/// Nothing is shared between replicas and everything is lost on restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    states: Mutex<HashMap<String, Expiring<CliAuthState>>>,
+    confirmations: Mutex<HashMap<String, Expiring<PendingConfirmation>>>,
+    links: Mutex<HashMap<String, Expiring<String>>>,
+    polls: Mutex<HashMap<String, Expiring<()>>>,
+    counters: Mutex<HashMap<String, Expiring<u64>>>,
+    credentials: Mutex<HashMap<String, Expiring<String>>>,
+    container_tokens: Mutex<HashMap<String, Expiring<ContainerTokenData>>>,
+    sessions: Mutex<HashMap<String, Expiring<CliSessionData>>>,
+}
+
+/// Value with an expiration time.
+struct Expiring<T> {
+    value: T,
+    expires_at: Instant,
+}
+
+impl<T> Expiring<T> {
+    fn new(value: T, ttl: u64) -> Self {
+        Self {
+            value,
+            expires_at: Instant::now() + Duration::from_secs(ttl),
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        self.expires_at > Instant::now()
+    }
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Locks a map, dropping its expired entries first.
+fn live<T>(map: &Mutex<HashMap<String, Expiring<T>>>) -> MutexGuard<'_, HashMap<String, Expiring<T>>> {
+    let mut map = map.lock().unwrap_or_else(|e| e.into_inner());
+    map.retain(|_, entry| entry.is_live());
+    map
+}
+
+#[async_trait]
+impl StateStore for MemoryStore {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn put_state(&self, state: &str, data: &CliAuthState, ttl: u64) -> Result<(), AppError> {
+        live(&self.states).insert(state.to_string(), Expiring::new(data.clone(), ttl));
+        Ok(())
+    }
+
+    async fn get_state(&self, state: &str) -> Result<Option<CliAuthState>, AppError> {
+        Ok(live(&self.states).get(state).map(|e| e.value.clone()))
+    }
+
+    async fn take_state(&self, state: &str) -> Result<Option<CliAuthState>, AppError> {
+        Ok(live(&self.states).remove(state).map(|e| e.value))
+    }
+
+    async fn link_session(&self, state: &str, sub: &str, ttl: u64) -> Result<(), AppError> {
+        live(&self.links).insert(state.to_string(), Expiring::new(sub.to_string(), ttl));
+        Ok(())
+    }
+
+    async fn linked_session(&self, state: &str) -> Result<Option<String>, AppError> {
+        Ok(live(&self.links).get(state).map(|e| e.value.clone()))
+    }
+
+    async fn unlink_session(&self, state: &str) -> Result<(), AppError> {
+        live(&self.links).remove(state);
+        Ok(())
+    }
+
+    async fn throttle_poll(&self, state: &str, interval: u64) -> Result<bool, AppError> {
+        let mut polls = live(&self.polls);
+        if polls.contains_key(state) {
+            return Ok(false);
+        }
+        polls.insert(state.to_string(), Expiring::new((), interval));
+        Ok(true)
+    }
+
+    async fn count_request(&self, key: &str, window: u64) -> Result<(u64, u64), AppError> {
+        let mut counters = live(&self.counters);
+        let counter = counters
+            .entry(key.to_string())
+            .or_insert_with(|| Expiring::new(0, window));
+        counter.value += 1;
+        let remaining = counter.expires_at.saturating_duration_since(Instant::now());
+        Ok((counter.value, remaining.as_secs().max(1)))
+    }
+
+    async fn put_credentials(&self, sub: &str, id: &str, sealed: &str, ttl: u64) -> Result<(), AppError> {
+        live(&self.credentials).insert(get_sts_cache_key(sub, id), Expiring::new(sealed.to_string(), ttl));
+        Ok(())
+    }
+
+    async fn get_credentials(&self, sub: &str, id: &str) -> Result<Option<String>, AppError> {
+        Ok(live(&self.credentials).get(&get_sts_cache_key(sub, id)).map(|e| e.value.clone()))
+    }
+
+    async fn put_container_token(
+        &self,
+        token_hash: &str,
+        data: &ContainerTokenData,
+        ttl: u64,
+    ) -> Result<(), AppError> {
+        live(&self.container_tokens).insert(token_hash.to_string(), Expiring::new(data.clone(), ttl));
+        Ok(())
+    }
+
+    async fn get_container_token(&self, token_hash: &str) -> Result<Option<ContainerTokenData>, AppError> {
+        Ok(live(&self.container_tokens).get(token_hash).map(|e| e.value.clone()))
+    }
+
+    async fn put_confirmation(&self, state: &str, data: &PendingConfirmation, ttl: u64) -> Result<bool, AppError> {
+        let mut confirmations = live(&self.confirmations);
+        if confirmations.contains_key(state) {
+            return Ok(false);
+        }
+        confirmations.insert(state.to_string(), Expiring::new(data.clone(), ttl));
+        Ok(true)
+    }
+
+    async fn take_confirmation(&self, state: &str) -> Result<Option<PendingConfirmation>, AppError> {
+        Ok(live(&self.confirmations).remove(state).map(|e| e.value))
+    }
+
+    async fn purge_states(&self) -> Result<u64, AppError> {
+        let mut states = live(&self.states);
+        let purged = states.len() as u64;
+        states.clear();
+        live(&self.confirmations).clear();
+        live(&self.links).clear();
+        live(&self.polls).clear();
+        Ok(purged)
+    }
+
+    async fn state_stats(&self) -> Result<StateStats, AppError> {
+        Ok(StateStats {
+            pending: live(&self.states).len() as u64,
+            completed: live(&self.links).len() as u64,
+        })
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get_session(&self, sub: &str) -> Result<Option<CliSessionData>, AppError> {
+        Ok(live(&self.sessions).get(sub).map(|e| e.value.clone()))
+    }
+
+    async fn put_session(&self, session: &CliSessionData, ttl: u64) -> Result<(), AppError> {
+        live(&self.sessions).insert(session.user_sub.clone(), Expiring::new(session.clone(), ttl));
+        Ok(())
+    }
+
+    async fn session_stats(&self) -> Result<SessionStats, AppError> {
+        let sessions = live(&self.sessions);
+        let active = sessions.values().filter(|e| e.value.active).count() as u64;
+        Ok(SessionStats {
+            active,
+            inactive: sessions.len() as u64 - active,
+        })
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
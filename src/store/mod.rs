@@ -0,0 +1,178 @@
+pub mod memory;
+pub mod redis;
+pub mod sql;
+
+use crate::config::{SessionBackend, StateBackend, StorageConfig};
+use crate::db::RedisPool;
+use crate::error::AppError;
+use crate::schemas::auth::{CliAuthState, CliSessionData, ContainerTokenData, PendingConfirmation};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub use memory::MemoryStore;
+pub use redis::RedisStore;
+pub use sql::SqlSessionStore;
+
+/// Storage of the short-lived data of login flows: authentication states, the pointers from
+/// completed states to their session, logins waiting for device confirmation, status poll
+/// throttling, rate limiting counters, the cached STS credentials of each session and the
+/// container credentials tokens.
+///
+/// Every value expires on its own; `ttl` and `window` arguments are in seconds.
+#[async_trait]
+pub trait StateStore: Send + Sync + 'static {
+    /// Short name of the backend, used in logs and health checks.
+    fn backend(&self) -> &'static str;
+
+    /// Stores a new authentication state.
+    async fn put_state(&self, state: &str, data: &CliAuthState, ttl: u64) -> Result<(), AppError>;
+
+    /// Loads an authentication state, if it has not expired or been consumed.
+    async fn get_state(&self, state: &str) -> Result<Option<CliAuthState>, AppError>;
+
+    /// Loads and removes an authentication state, so that it cannot be used twice.
+    async fn take_state(&self, state: &str) -> Result<Option<CliAuthState>, AppError>;
+
+    /// Links a completed authentication state to the session of the user `sub`.
+    async fn link_session(&self, state: &str, sub: &str, ttl: u64) -> Result<(), AppError>;
+
+    /// Returns the user linked to a completed authentication state.
+    async fn linked_session(&self, state: &str) -> Result<Option<String>, AppError>;
+
+    /// Removes the link of a completed authentication state.
+    async fn unlink_session(&self, state: &str) -> Result<(), AppError>;
+
+    /// Records a status poll for a state.
+    ///
+    /// Returns `false` when the state was already polled less than `interval` seconds ago.
+    async fn throttle_poll(&self, state: &str, interval: u64) -> Result<bool, AppError>;
+
+    /// Increments a rate limiting counter that expires `window` seconds after its first hit.
+    ///
+    /// Returns the counter value after the increment and the seconds left in the window.
+    async fn count_request(&self, key: &str, window: u64) -> Result<(u64, u64), AppError>;
+
+    /// Stores sealed STS credentials of a user under a cache entry ID for `ttl` seconds.
+    async fn put_credentials(&self, sub: &str, id: &str, sealed: &str, ttl: u64) -> Result<(), AppError>;
+
+    /// Loads the sealed STS credentials of a cache entry, if they have not expired.
+    async fn get_credentials(&self, sub: &str, id: &str) -> Result<Option<String>, AppError>;
+
+    /// Stores a container credentials token, indexed by its hash, for `ttl` seconds.
+    async fn put_container_token(
+        &self,
+        token_hash: &str,
+        data: &ContainerTokenData,
+        ttl: u64,
+    ) -> Result<(), AppError>;
+
+    /// Loads a container credentials token by its hash, if it has not expired.
+    async fn get_container_token(&self, token_hash: &str) -> Result<Option<ContainerTokenData>, AppError>;
+
+    /// Stores the login of a state until the user confirms their device.
+    ///
+    /// Returns `false`, storing nothing, when a login of the state is already waiting.
+    async fn put_confirmation(&self, state: &str, data: &PendingConfirmation, ttl: u64) -> Result<bool, AppError>;
+
+    /// Loads and removes the login of a state waiting for confirmation.
+    async fn take_confirmation(&self, state: &str) -> Result<Option<PendingConfirmation>, AppError>;
+
+    /// Removes every login flow in progress: states, pending confirmations, session links and
+    /// poll throttles.
+    ///
+    /// Returns the number of authentication states removed.
+    async fn purge_states(&self) -> Result<u64, AppError>;
+
+    /// Counts the login flows in progress.
+    async fn state_stats(&self) -> Result<StateStats, AppError>;
+
+    /// Checks that the backend answers.
+    async fn ping(&self) -> Result<(), String>;
+}
+
+/// Storage of the CLI sessions, indexed by the Cognito subject of their user.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    /// Short name of the backend, used in logs and health checks.
+    fn backend(&self) -> &'static str;
+
+    /// Loads the session of a user, if it has not expired.
+    async fn get_session(&self, sub: &str) -> Result<Option<CliSessionData>, AppError>;
+
+    /// Creates or replaces the session of `session.user_sub`, valid for `ttl` seconds.
+    async fn put_session(&self, session: &CliSessionData, ttl: u64) -> Result<(), AppError>;
+
+    /// Past versions of the session of a user, oldest first.
+    ///
+    /// Only the SQL backend keeps a history; the others return nothing.
+    async fn session_history(&self, _sub: &str) -> Result<Vec<SessionHistoryEntry>, AppError> {
+        Ok(Vec::new())
+    }
+
+    /// Counts the stored sessions.
+    async fn session_stats(&self) -> Result<SessionStats, AppError>;
+
+    /// Checks that the backend answers.
+    async fn ping(&self) -> Result<(), String>;
+}
+
+/// Number of login flows in progress.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StateStats {
+    /// Flows waiting for the user to log in.
+    pub pending: u64,
+    /// Flows whose user logged in, waiting for the CLI to fetch its credentials.
+    pub completed: u64,
+}
+
+/// Number of stored sessions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Sessions that can still obtain credentials.
+    pub active: u64,
+    /// Sessions ended by a logout or a revocation.
+    pub inactive: u64,
+}
+
+/// Recorded version of a session.
+#[derive(Debug, Clone)]
+pub struct SessionHistoryEntry {
+    pub email: Option<String>,
+    pub device_name: Option<String>,
+    pub active: bool,
+    /// Unix timestamp (seconds) of the change.
+    pub recorded_at: i64,
+}
+
+/// Creates the configured state and session stores.
+///
+/// The Redis backends share the given pool, and the memory backends share a single store.
+pub async fn create_stores(
+    config: &StorageConfig,
+    redis_pool: &RedisPool,
+) -> Result<(Arc<dyn StateStore>, Arc<dyn SessionStore>), String> {
+    let memory = Arc::new(MemoryStore::new());
+    let redis = Arc::new(RedisStore::new(redis_pool.clone()));
+
+    let states: Arc<dyn StateStore> = match config.state_backend {
+        StateBackend::Redis => redis.clone(),
+        StateBackend::Memory => memory.clone(),
+    };
+
+    let sessions: Arc<dyn SessionStore> = match config.session_backend {
+        SessionBackend::Redis => redis,
+        SessionBackend::Memory => memory,
+        SessionBackend::Sql => {
+            let url = config
+                .database_url
+                .as_deref()
+                .ok_or("storage.database_url is required by the sql session backend")?;
+            let store = SqlSessionStore::connect(url)
+                .await
+                .map_err(|e| format!("Could not open the session database: {}", e))?;
+            Arc::new(store)
+        }
+    };
+
+    Ok((states, sessions))
+}
@@ -0,0 +1,162 @@
+use crate::db::{
+    redis_del, redis_get, redis_get_del, redis_incr_window, redis_keys, redis_set_ex, redis_set_nx_ex, RedisPool,
+};
+use crate::error::AppError;
+use crate::handlers::auth::utils::{
+    get_cli_confirm_key, get_cli_poll_key, get_cli_session_key, get_cli_state_key, get_cli_state_session_key, get_container_token_key,
+    get_sts_cache_key,
+    CLI_CONFIRM_KEY_PREFIX, CLI_POLL_KEY_PREFIX, CLI_SESSION_KEY_PREFIX, CLI_STATE_KEY_PREFIX,
+};
+use crate::schemas::auth::{CliAuthState, CliSessionData, ContainerTokenData, PendingConfirmation};
+use crate::store::{SessionStats, SessionStore, StateStats, StateStore};
+use async_trait::async_trait;
+
+/// Store keeping login flows and sessions in Redis, shared by every replica.
+#[derive(Clone)]
+pub struct RedisStore {
+    pool: RedisPool,
+}
+
+impl RedisStore {
+    /// Creates a store using the given connection pool.
+    pub fn new(pool: RedisPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl StateStore for RedisStore {
+    fn backend(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn put_state(&self, state: &str, data: &CliAuthState, ttl: u64) -> Result<(), AppError> {
+        redis_set_ex(&self.pool, &get_cli_state_key(state), data, ttl).await
+    }
+
+    async fn get_state(&self, state: &str) -> Result<Option<CliAuthState>, AppError> {
+        redis_get(&self.pool, &get_cli_state_key(state)).await
+    }
+
+    async fn take_state(&self, state: &str) -> Result<Option<CliAuthState>, AppError> {
+        redis_get_del(&self.pool, &get_cli_state_key(state)).await
+    }
+
+    async fn link_session(&self, state: &str, sub: &str, ttl: u64) -> Result<(), AppError> {
+        redis_set_ex(&self.pool, &get_cli_state_session_key(state), &sub, ttl).await
+    }
+
+    async fn linked_session(&self, state: &str) -> Result<Option<String>, AppError> {
+        redis_get(&self.pool, &get_cli_state_session_key(state)).await
+    }
+
+    async fn unlink_session(&self, state: &str) -> Result<(), AppError> {
+        redis_del(&self.pool, &get_cli_state_session_key(state)).await
+    }
+
+    async fn throttle_poll(&self, state: &str, interval: u64) -> Result<bool, AppError> {
+        redis_set_nx_ex(&self.pool, &get_cli_poll_key(state), &true, interval).await
+    }
+
+    async fn count_request(&self, key: &str, window: u64) -> Result<(u64, u64), AppError> {
+        redis_incr_window(&self.pool, key, window).await
+    }
+
+    async fn put_credentials(&self, sub: &str, id: &str, sealed: &str, ttl: u64) -> Result<(), AppError> {
+        redis_set_ex(&self.pool, &get_sts_cache_key(sub, id), &sealed, ttl).await
+    }
+
+    async fn get_credentials(&self, sub: &str, id: &str) -> Result<Option<String>, AppError> {
+        redis_get(&self.pool, &get_sts_cache_key(sub, id)).await
+    }
+
+    async fn put_container_token(
+        &self,
+        token_hash: &str,
+        data: &ContainerTokenData,
+        ttl: u64,
+    ) -> Result<(), AppError> {
+        redis_set_ex(&self.pool, &get_container_token_key(token_hash), data, ttl).await
+    }
+
+    async fn get_container_token(&self, token_hash: &str) -> Result<Option<ContainerTokenData>, AppError> {
+        redis_get(&self.pool, &get_container_token_key(token_hash)).await
+    }
+
+    async fn put_confirmation(&self, state: &str, data: &PendingConfirmation, ttl: u64) -> Result<bool, AppError> {
+        redis_set_nx_ex(&self.pool, &get_cli_confirm_key(state), data, ttl).await
+    }
+
+    async fn take_confirmation(&self, state: &str) -> Result<Option<PendingConfirmation>, AppError> {
+        redis_get_del(&self.pool, &get_cli_confirm_key(state)).await
+    }
+
+    async fn purge_states(&self) -> Result<u64, AppError> {
+        let states = redis_keys(&self.pool, &format!("{}*", CLI_STATE_KEY_PREFIX)).await?;
+        let confirmations = redis_keys(&self.pool, &format!("{}*", CLI_CONFIRM_KEY_PREFIX)).await?;
+        let links = redis_keys(&self.pool, &format!("{}{{*", CLI_SESSION_KEY_PREFIX)).await?;
+        let polls = redis_keys(&self.pool, &format!("{}*", CLI_POLL_KEY_PREFIX)).await?;
+        for key in states.iter().chain(&confirmations).chain(&links).chain(&polls) {
+            redis_del(&self.pool, key).await?;
+        }
+        Ok(states.len() as u64)
+    }
+
+    async fn state_stats(&self) -> Result<StateStats, AppError> {
+        let states = redis_keys(&self.pool, &format!("{}*", CLI_STATE_KEY_PREFIX)).await?;
+        let links = redis_keys(&self.pool, &format!("{}{{*", CLI_SESSION_KEY_PREFIX)).await?;
+        Ok(StateStats {
+            pending: states.len() as u64,
+            completed: links.len() as u64,
+        })
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        ping(&self.pool).await
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisStore {
+    fn backend(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn get_session(&self, sub: &str) -> Result<Option<CliSessionData>, AppError> {
+        redis_get(&self.pool, &get_cli_session_key(sub)).await
+    }
+
+    async fn put_session(&self, session: &CliSessionData, ttl: u64) -> Result<(), AppError> {
+        redis_set_ex(&self.pool, &get_cli_session_key(&session.user_sub), session, ttl).await
+    }
+
+    async fn session_stats(&self) -> Result<SessionStats, AppError> {
+        let mut stats = SessionStats::default();
+        for key in redis_keys(&self.pool, &format!("{}*", CLI_SESSION_KEY_PREFIX)).await? {
+            // Keys of completed login flows share the prefix and carry a `{state}` hash tag
+            if key[CLI_SESSION_KEY_PREFIX.len()..].starts_with('{') {
+                continue;
+            }
+            match redis_get::<CliSessionData>(&self.pool, &key).await? {
+                Some(session) if session.active => stats.active += 1,
+                Some(_) => stats.inactive += 1,
+                None => {}
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        ping(&self.pool).await
+    }
+}
+
+/// Sends a PING to Redis through the connection pool.
+pub async fn ping(pool: &RedisPool) -> Result<(), String> {
+    let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+    let _: String = redis::cmd("PING")
+        .query_async(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use crate::error::AppError;
+use crate::schemas::auth::CliSessionData;
+use crate::store::{SessionHistoryEntry, SessionStats, SessionStore};
+use async_trait::async_trait;
+use sqlx::any::{install_default_drivers, AnyPoolOptions};
+use sqlx::{AnyPool, Row};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Statements creating the tables on startup, for SQLite and Postgres alike.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS cli_sessions (
+        user_sub TEXT PRIMARY KEY,
+        email TEXT,
+        device_name TEXT,
+        refresh_token TEXT,
+        active BIGINT NOT NULL,
+        updated_at BIGINT NOT NULL,
+        expires_at BIGINT NOT NULL,
+        cli_version TEXT
+    )",
+    "CREATE TABLE IF NOT EXISTS cli_session_history (
+        user_sub TEXT NOT NULL,
+        email TEXT,
+        device_name TEXT,
+        active BIGINT NOT NULL,
+        recorded_at BIGINT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS cli_session_history_user_sub ON cli_session_history (user_sub, recorded_at)",
+];
+
+/// Columns added to existing tables since their creation, as `(table, column, type)`.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[("cli_sessions", "cli_version", "TEXT")];
+
+/// Session store backed by SQLite or Postgres, chosen by the database URL scheme.
+///
+/// Besides the current session of each user, every change is appended to the
+/// `cli_session_history` table (without the refresh token), which is never pruned.
+#[derive(Clone)]
+pub struct SqlSessionStore {
+    pool: AnyPool,
+}
+
+impl SqlSessionStore {
+    /// Connects to the database and creates the tables if needed.
+    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+        install_default_drivers();
+        let pool = AnyPoolOptions::new().connect(database_url).await?;
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        // SQLite has no `ADD COLUMN IF NOT EXISTS`, so the column is probed for first
+        for (table, column, kind) in ADDED_COLUMNS {
+            let probe = format!("SELECT {} FROM {} LIMIT 1", column, table);
+            if sqlx::query(&probe).fetch_optional(&pool).await.is_err() {
+                let alter = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, kind);
+                sqlx::query(&alter).execute(&pool).await?;
+            }
+        }
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqlSessionStore {
+    fn backend(&self) -> &'static str {
+        "sql"
+    }
+
+    #[tracing::instrument(name = "sql.get_session", skip_all, fields(db.system = "sql"))]
+    async fn get_session(&self, sub: &str) -> Result<Option<CliSessionData>, AppError> {
+        let row = sqlx::query(
+            "SELECT user_sub, email, device_name, refresh_token, active, cli_version FROM cli_sessions \
+             WHERE user_sub = $1 AND expires_at > $2",
+        )
+        .bind(sub)
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(storage_error)?;
+
+        row.map(|row| {
+            Ok(CliSessionData {
+                user_sub: row.try_get("user_sub")?,
+                email: row.try_get("email")?,
+                device_name: row.try_get("device_name")?,
+                refresh_token: row.try_get("refresh_token")?,
+                active: row.try_get::<i64, _>("active")? != 0,
+                cli_version: row.try_get("cli_version")?,
+            })
+        })
+        .transpose()
+        .map_err(|e: sqlx::Error| {
+            log::error!("Failed to read session row: {}", e);
+            AppError::DataCorruption(e.to_string())
+        })
+    }
+
+    #[tracing::instrument(name = "sql.put_session", skip_all, fields(db.system = "sql"))]
+    async fn put_session(&self, session: &CliSessionData, ttl: u64) -> Result<(), AppError> {
+        let now = now();
+        let mut tx = self.pool.begin().await.map_err(storage_error)?;
+
+        sqlx::query(
+            "INSERT INTO cli_sessions \
+             (user_sub, email, device_name, refresh_token, active, updated_at, expires_at, cli_version) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (user_sub) DO UPDATE SET email = excluded.email, device_name = excluded.device_name, \
+             refresh_token = excluded.refresh_token, active = excluded.active, \
+             updated_at = excluded.updated_at, expires_at = excluded.expires_at, cli_version = excluded.cli_version",
+        )
+        .bind(&session.user_sub)
+        .bind(&session.email)
+        .bind(&session.device_name)
+        .bind(&session.refresh_token)
+        .bind(session.active as i64)
+        .bind(now)
+        .bind(now + ttl as i64)
+        .bind(&session.cli_version)
+        .execute(&mut *tx)
+        .await
+        .map_err(storage_error)?;
+
+        sqlx::query(
+            "INSERT INTO cli_session_history (user_sub, email, device_name, active, recorded_at) \
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&session.user_sub)
+        .bind(&session.email)
+        .bind(&session.device_name)
+        .bind(session.active as i64)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(storage_error)?;
+
+        tx.commit().await.map_err(storage_error)
+    }
+
+    #[tracing::instrument(name = "sql.session_history", skip_all, fields(db.system = "sql"))]
+    async fn session_history(&self, sub: &str) -> Result<Vec<SessionHistoryEntry>, AppError> {
+        let rows = sqlx::query(
+            "SELECT email, device_name, active, recorded_at FROM cli_session_history \
+             WHERE user_sub = $1 ORDER BY recorded_at",
+        )
+        .bind(sub)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(storage_error)?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(SessionHistoryEntry {
+                    email: row.try_get("email")?,
+                    device_name: row.try_get("device_name")?,
+                    active: row.try_get::<i64, _>("active")? != 0,
+                    recorded_at: row.try_get("recorded_at")?,
+                })
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(|e| {
+                log::error!("Failed to read session history row: {}", e);
+                AppError::DataCorruption(e.to_string())
+            })
+    }
+
+    #[tracing::instrument(name = "sql.session_stats", skip_all, fields(db.system = "sql"))]
+    async fn session_stats(&self) -> Result<SessionStats, AppError> {
+        let rows = sqlx::query(
+            "SELECT active, COUNT(*) AS sessions FROM cli_sessions WHERE expires_at > $1 GROUP BY active",
+        )
+        .bind(now())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(storage_error)?;
+
+        let mut stats = SessionStats::default();
+        for row in rows {
+            let count = row.try_get::<i64, _>("sessions").map_err(storage_error)? as u64;
+            if row.try_get::<i64, _>("active").map_err(storage_error)? != 0 {
+                stats.active += count;
+            } else {
+                stats.inactive += count;
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Current Unix timestamp in seconds.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_else(|_| 0)
+}
+
+/// Maps a database failure to the storage error reported to clients.
+fn storage_error(e: sqlx::Error) -> AppError {
+    log::error!("Session database error: {}", e);
+    AppError::StorageUnavailable(e.to_string())
+}
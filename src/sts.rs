@@ -0,0 +1,243 @@
+use crate::config::AppArgs;
+use crate::error::AppError;
+use crate::handlers::auth::utils::{get_role_session_name, hash_secret, session_fingerprint, unix_now};
+use crate::metrics::Metrics;
+use crate::schemas::auth::CliSessionData;
+use crate::store::StateStore;
+use aws_lc_rs::aead::{Aad, Nonce, RandomizedNonceKey, AES_256_GCM, NONCE_LEN};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+/// Temporary AWS credentials handed out to a CLI.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    /// Unix timestamp (seconds) at which the credentials expire.
+    pub expires_at: i64,
+}
+
+/// Cache of the credentials issued to each session, so that CLIs polling or renewing again
+/// shortly after do not each cost an `AssumeRole` call.
+///
+/// Entries are sealed with AES-256-GCM before they reach the state store, bound to the login
+/// and to the role they were issued for, and expire once they no longer have the minimum
+/// lifetime left.
+pub struct CredentialCache {
+    key: RandomizedNonceKey,
+}
+
+/// Outcome of a cache lookup, counted in the metrics.
+enum Lookup {
+    Hit(StsCredentials),
+    Miss,
+    /// Cached credentials were found but expire too soon.
+    Expiring,
+}
+
+/// What cached credentials were issued for: a login of a user, and the role and external ID
+/// assumed for it.
+///
+/// Both the entry ID and the associated data of the encryption derive from it, so that a new
+/// login or a reloaded role never gets the credentials cached for another.
+struct CacheEntry<'a> {
+    user_sub: &'a str,
+    binding: String,
+}
+
+impl<'a> CacheEntry<'a> {
+    fn new(session: &'a CliSessionData, config: &AppArgs) -> Self {
+        let binding = [
+            session.user_sub.as_str(),
+            &session_fingerprint(session),
+            &config.sts.role_arn,
+            config.sts.external_id.as_deref().unwrap_or_default(),
+        ]
+        .join("\n");
+        Self {
+            user_sub: &session.user_sub,
+            binding,
+        }
+    }
+
+    /// Key of the entry in the state store, which does not reveal the refresh token.
+    fn id(&self) -> String {
+        hash_secret(&self.binding)
+    }
+
+    /// Associated data the entry is sealed with.
+    fn aad(&self) -> Aad<&[u8]> {
+        Aad::from(self.binding.as_bytes())
+    }
+}
+
+impl CredentialCache {
+    /// Creates a cache sealing entries with the given base64 key, or with a random key.
+    pub fn new(key: Option<&str>) -> Result<Self, String> {
+        let bytes = match key {
+            Some(key) => Self::decode_key(key)?,
+            None => {
+                let mut bytes = vec![0u8; AES_256_GCM.key_len()];
+                aws_lc_rs::rand::fill(&mut bytes).map_err(|_| "could not generate a cache key".to_string())?;
+                bytes
+            }
+        };
+        let key = RandomizedNonceKey::new(&AES_256_GCM, &bytes).map_err(|_| "invalid cache key".to_string())?;
+        Ok(Self { key })
+    }
+
+    /// Decodes a base64 cache key, checking that it is 256 bits long.
+    pub fn decode_key(key: &str) -> Result<Vec<u8>, String> {
+        let bytes = STANDARD
+            .decode(key.trim())
+            .map_err(|e| format!("must be base64-encoded: {}", e))?;
+        if bytes.len() != AES_256_GCM.key_len() {
+            return Err(format!(
+                "must be {} bytes long, got {}",
+                AES_256_GCM.key_len(),
+                bytes.len()
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// Looks up the credentials of an entry. Storage and decryption failures count as misses.
+    async fn lookup<S: StateStore + ?Sized>(&self, states: &S, entry: &CacheEntry<'_>, min_lifetime: u64) -> Lookup {
+        let sealed = match states.get_credentials(entry.user_sub, &entry.id()).await {
+            Ok(Some(sealed)) => sealed,
+            Ok(None) => return Lookup::Miss,
+            Err(e) => {
+                log::warn!("Could not read cached STS credentials: {}", e);
+                return Lookup::Miss;
+            }
+        };
+
+        let Some(creds) = self.open(entry, &sealed) else {
+            log::debug!("Discarding cached STS credentials that cannot be decrypted");
+            return Lookup::Miss;
+        };
+        if creds.expires_at - unix_now() < min_lifetime as i64 {
+            return Lookup::Expiring;
+        }
+        Lookup::Hit(creds)
+    }
+
+    /// Caches credentials until they reach the minimum lifetime.
+    async fn store<S: StateStore + ?Sized>(
+        &self,
+        states: &S,
+        entry: &CacheEntry<'_>,
+        creds: &StsCredentials,
+        min_lifetime: u64,
+    ) {
+        let ttl = creds.expires_at - unix_now() - min_lifetime as i64;
+        if ttl <= 0 {
+            return;
+        }
+        let Some(sealed) = self.seal(entry, creds) else {
+            log::warn!("Could not encrypt STS credentials for the cache");
+            return;
+        };
+        if let Err(e) = states.put_credentials(entry.user_sub, &entry.id(), &sealed, ttl as u64).await {
+            log::warn!("Could not cache STS credentials: {}", e);
+        }
+    }
+
+    /// Encrypts credentials as base64 of the nonce followed by the ciphertext and tag.
+    fn seal(&self, entry: &CacheEntry<'_>, creds: &StsCredentials) -> Option<String> {
+        let mut in_out = serde_json::to_vec(creds).ok()?;
+        let nonce = self
+            .key
+            .seal_in_place_append_tag(entry.aad(), &mut in_out)
+            .ok()?;
+        let mut sealed = nonce.as_ref().to_vec();
+        sealed.extend_from_slice(&in_out);
+        Some(STANDARD.encode(sealed))
+    }
+
+    /// Decrypts credentials sealed by [`CredentialCache::seal`] for the same entry.
+    fn open(&self, entry: &CacheEntry<'_>, sealed: &str) -> Option<StsCredentials> {
+        let mut sealed = STANDARD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let mut in_out = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed).ok()?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, entry.aad(), &mut in_out)
+            .ok()?;
+        serde_json::from_slice(plaintext).ok()
+    }
+}
+
+/// Returns credentials for a session, from the cache when they have enough lifetime left, or
+/// from a new `AssumeRole` call otherwise.
+pub async fn session_credentials<S: StateStore + ?Sized>(
+    session: &CliSessionData,
+    sts_client: &aws_sdk_sts::Client,
+    config: &AppArgs,
+    states: &S,
+    cache: &CredentialCache,
+    metrics: &Metrics,
+) -> Result<StsCredentials, AppError> {
+    let min_lifetime = config.sts.cache_min_lifetime_secs;
+    let entry = CacheEntry::new(session, config);
+    if min_lifetime > 0 {
+        match cache.lookup(states, &entry, min_lifetime).await {
+            Lookup::Hit(creds) => {
+                metrics.observe_credential_cache("hit");
+                return Ok(creds);
+            }
+            Lookup::Miss => metrics.observe_credential_cache("miss"),
+            Lookup::Expiring => metrics.observe_credential_cache("expiring"),
+        }
+    }
+
+    let creds = assume_role(&session.user_sub, sts_client, config, metrics).await?;
+    if min_lifetime > 0 {
+        cache.store(states, &entry, &creds, min_lifetime).await;
+    }
+    Ok(creds)
+}
+
+/// Assumes the configured role on behalf of a user.
+async fn assume_role(
+    user_sub: &str,
+    sts_client: &aws_sdk_sts::Client,
+    config: &AppArgs,
+    metrics: &Metrics,
+) -> Result<StsCredentials, AppError> {
+    let timer = metrics.assume_role_duration.start_timer();
+    let assumed = sts_client
+        .assume_role()
+        .role_arn(&config.sts.role_arn)
+        .role_session_name(get_role_session_name(user_sub))
+        .set_external_id(config.sts.external_id.clone())
+        .send()
+        .instrument(tracing::info_span!("sts.assume_role", role_arn = %config.sts.role_arn))
+        .await;
+    timer.observe_duration();
+
+    let creds = assumed
+        .map_err(|e| {
+            log::error!("Failed to assume role: {:?}", e);
+            AppError::StsUnavailable(e.to_string())
+        })?
+        .credentials
+        .ok_or_else(|| {
+            log::error!("STS response missing credentials");
+            AppError::StsUnavailable("STS response missing credentials".to_string())
+        })?;
+
+    Ok(StsCredentials {
+        access_key_id: creds.access_key_id().to_string(),
+        secret_access_key: creds.secret_access_key().to_string(),
+        session_token: creds.session_token().to_string(),
+        expires_at: creds.expiration().secs(),
+    })
+}
+
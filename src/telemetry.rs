@@ -0,0 +1,161 @@
+use crate::config::TelemetryConfig;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpMessage};
+use opentelemetry::global;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, SpanExporter};
+use opentelemetry_sdk::Resource;
+use std::time::Instant;
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Initializes logging and tracing for the whole process.
+///
+/// Log records from the `log` crate are forwarded to `tracing` and printed to stdout,
+/// filtered by `RUST_LOG` (defaults to `info`). When an OTLP endpoint is configured,
+/// spans are also exported to it. Incoming W3C `traceparent` headers are honoured.
+///
+/// Returns the tracer provider, which must be shut down on exit to flush pending spans.
+pub fn init(config: &TelemetryConfig) -> Result<Option<SdkTracerProvider>, String> {
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                .build()
+                .map_err(|e| format!("Could not build OTLP exporter: {}", e))?;
+            Some(build_provider(exporter, &config.service_name))
+        }
+        None => None,
+    };
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otel_layer = provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer(config.service_name.clone())));
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .try_init()
+        .map_err(|e| format!("Could not install tracing subscriber: {}", e))?;
+
+    Ok(provider)
+}
+
+/// Builds a tracer provider batching spans to the given exporter.
+pub fn build_provider<E>(exporter: E, service_name: &str) -> SdkTracerProvider
+where
+    E: SpanExporter + 'static,
+{
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name.to_string()).build())
+        .build();
+
+    global::set_tracer_provider(provider.clone());
+    provider
+}
+
+/// Root span of each request, which also logs the request once answered.
+///
+/// It records the same fields as [`DefaultRootSpanBuilder`] and replaces an access log
+/// middleware, so that each request is logged once, within its trace.
+pub struct RequestSpan;
+
+/// Time at which a request was received, kept in its extensions.
+struct ReceivedAt(Instant);
+
+impl RootSpanBuilder for RequestSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        request.extensions_mut().insert(ReceivedAt(Instant::now()));
+        DefaultRootSpanBuilder::on_request_start(request)
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        match outcome {
+            Ok(response) => {
+                let request = response.request();
+                let elapsed = request
+                    .extensions()
+                    .get::<ReceivedAt>()
+                    .map_or(0.0, |received| received.0.elapsed().as_secs_f64());
+                tracing::info!(
+                    parent: &span,
+                    "{} {} {} {:.6}s",
+                    request.method(),
+                    request.path(),
+                    response.status().as_u16(),
+                    elapsed
+                );
+            }
+            Err(error) => tracing::info!(parent: &span, "Request failed: {}", error),
+        }
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// Flushes and shuts down the tracer provider, if any.
+pub fn shutdown(provider: Option<SdkTracerProvider>) {
+    if let Some(provider) = provider
+        && let Err(e) = provider.shutdown()
+    {
+        log::warn!("Failed to shut down tracer provider: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use opentelemetry::trace::{SpanId, SpanKind, TraceId};
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+    use tracing_actix_web::TracingLogger;
+
+    #[actix_web::test]
+    async fn request_spans_continue_the_incoming_trace() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = build_provider(exporter.clone(), "mega-uploader-auth-test");
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let _subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .set_default();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(TracingLogger::<RequestSpan>::new())
+                .route("/healthz", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/healthz")
+            .insert_header(("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = spans
+            .iter()
+            .find(|span| span.span_kind == SpanKind::Server)
+            .unwrap_or_else(|| panic!("no request span in {:?}", spans));
+        assert_eq!(span.name, "GET /healthz");
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert_eq!(span.parent_span_id, SpanId::from_hex("00f067aa0ba902b7").unwrap());
+        assert!(span.parent_span_is_remote);
+
+        // The request is logged once, within its span
+        assert!(span.events.iter().any(|event| event.name.starts_with("GET /healthz 200 ")));
+    }
+}
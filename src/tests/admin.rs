@@ -0,0 +1,102 @@
+use crate::admin;
+use crate::store::{SessionStore, SqlSessionStore};
+use crate::tests::harness::{remove_sqlite_database, Backend, TestEnv};
+use uuid::Uuid;
+
+#[actix_web::test]
+async fn revoked_session_is_denied() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let refresh_token = env.login().await["refresh_token"].as_str().unwrap().to_string();
+
+    let output = admin::revoke_session(&*env.sessions, &env.audit_log(), &env.args, "user-1")
+        .await
+        .unwrap();
+    assert!(output.starts_with("Revoked the session of user-1 (laptop)"), "{}", output);
+    assert!(!env.oidc.is_valid_refresh_token(&refresh_token));
+    assert!(env.audit_trail().contains(&("revoke".to_string(), "success".to_string())));
+
+    let listing = admin::list_sessions(&*env.sessions, "user-1").await.unwrap();
+    assert!(listing.contains("Status:  inactive"), "{}", listing);
+}
+
+#[actix_web::test]
+async fn revoking_an_unknown_user_fails() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let result = admin::revoke_session(&*env.sessions, &env.audit_log(), &env.args, "nobody").await;
+    assert_eq!(result.unwrap_err(), "No session for user nobody");
+}
+
+#[actix_web::test]
+async fn purged_login_flows_expire() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (first, _) = env.begin().await;
+    let (second, auth_url) = env.begin().await;
+    env.authorize(&auth_url).await;
+    env.status(&first).await;
+
+    let stats = admin::stats(&*env.states, &*env.sessions).await.unwrap();
+    assert!(stats.starts_with("Login flows (redis): 1 pending, 1 awaiting credentials pickup"), "{}", stats);
+
+    let output = admin::purge_states(&*env.states).await.unwrap();
+    assert_eq!(output, "Purged 1 login flow(s) from redis\n");
+    // Rate limiting counters are not part of the login flows
+    let keys: Vec<String> = env.redis.keys().into_iter().filter(|k| !k.starts_with("auth:ratelimit:")).collect();
+    assert_eq!(keys, ["auth:cli:session:user-1", "auth:jwks:us-east-1_test"]);
+
+    let (_, body) = env.status(&first).await;
+    assert_eq!(body["status"], "EXPIRED");
+    let (_, body) = env.status(&second).await;
+    assert_eq!(body["status"], "EXPIRED");
+}
+
+#[actix_web::test]
+async fn sql_sessions_have_stats_and_history() {
+    let env = TestEnv::start(Backend::Sql, &[]).await;
+    env.login().await;
+    admin::revoke_session(&*env.sessions, &env.audit_log(), &env.args, "user-1")
+        .await
+        .unwrap();
+
+    let stats = admin::stats(&*env.states, &*env.sessions).await.unwrap();
+    assert!(stats.ends_with("Sessions (sql): 0 active, 1 inactive\n"), "{}", stats);
+
+    let listing = admin::list_sessions(&*env.sessions, "user-1").await.unwrap();
+    let history: Vec<&str> = listing.lines().skip_while(|l| !l.starts_with("History")).skip(1).collect();
+    assert_eq!(history.len(), 2, "{}", listing);
+    assert!(history[0].contains("active") && !history[0].contains("inactive"));
+    assert!(history[1].contains("inactive"));
+}
+
+#[actix_web::test]
+async fn sql_sessions_of_an_older_schema_are_migrated() {
+    let path = std::env::temp_dir().join(format!("mega-uploader-auth-{}.db", Uuid::new_v4()));
+    let url = format!("sqlite://{}?mode=rwc", path.display());
+    sqlx::any::install_default_drivers();
+    let pool = sqlx::AnyPool::connect(&url).await.unwrap();
+    sqlx::query(
+        "CREATE TABLE cli_sessions (user_sub TEXT PRIMARY KEY, email TEXT, device_name TEXT, \
+         refresh_token TEXT, active BIGINT NOT NULL, updated_at BIGINT NOT NULL, expires_at BIGINT NOT NULL)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO cli_sessions VALUES ('user-1', NULL, 'laptop', 'refresh-1', 1, 0, 9999999999)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    // Opening the store again must not try to add the column twice
+    SqlSessionStore::connect(&url).await.unwrap();
+    let store = SqlSessionStore::connect(&url).await.unwrap();
+    let mut session = store.get_session("user-1").await.unwrap().unwrap();
+    assert_eq!(session.device_name.as_deref(), Some("laptop"));
+    assert_eq!(session.cli_version, None);
+
+    session.cli_version = Some("1.0.0".to_string());
+    store.put_session(&session, 3600).await.unwrap();
+    let session = store.get_session("user-1").await.unwrap().unwrap();
+    assert_eq!(session.cli_version.as_deref(), Some("1.0.0"));
+
+    remove_sqlite_database(&path);
+}
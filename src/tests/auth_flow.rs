@@ -0,0 +1,308 @@
+use crate::tests::fake_oidc::OidcFaults;
+use crate::tests::fake_sts::EXPIRATION;
+use crate::tests::harness::{Backend, TestEnv, ROLE_ARN};
+use serde_json::Value;
+
+/// Status code and `code` field of a problem details response.
+async fn problem(res: reqwest::Response) -> (u16, String) {
+    let status = res.status().as_u16();
+    let body: Value = res.json().await.unwrap_or(Value::Null);
+    (status, body["code"].as_str().unwrap_or_default().to_string())
+}
+
+/// `POST /auth/cli/start` with the given X-Forwarded-For header.
+async fn start_login_forwarded_for(env: &TestEnv, forwarded_for: &str) -> reqwest::Response {
+    env.http
+        .post(format!("{}/auth/cli/start", env.url))
+        .header("X-Forwarded-For", forwarded_for)
+        .json(&serde_json::json!({ "device_name": "laptop" }))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Runs start → callback → status → renew against the given backend.
+async fn full_flow(backend: Backend) {
+    let env = TestEnv::start(backend, &[]).await;
+
+    let (state, auth_url) = env.begin().await;
+    let (code, body) = env.status(&state).await;
+    assert_eq!((code, body["status"].as_str()), (200, Some("PENDING")));
+
+    let callback = env.authorize(&auth_url).await;
+    assert_eq!(callback.status(), 200);
+
+    let (code, body) = env.status(&state).await;
+    assert_eq!(code, 200);
+    assert_eq!(body["status"], "AUTHORIZED");
+    assert!(body["access_key_id"].as_str().unwrap().starts_with("ASIA"));
+    assert_eq!(body["expires_at"], EXPIRATION);
+    let refresh_token = body["refresh_token"].as_str().unwrap().to_string();
+
+    let calls = env.sts.calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].role_arn, ROLE_ARN);
+    assert_eq!(calls[0].role_session_name, "cli-user-1");
+    assert_eq!(calls[0].external_id, None);
+
+    // The state is single use: once the credentials are handed out it is gone
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "EXPIRED");
+
+    let (code, body) = env.renew(&refresh_token).await;
+    assert_eq!(code, 200);
+    assert_eq!(body["status"], "AUTHORIZED");
+    assert_eq!(body["refresh_token"], refresh_token.as_str());
+    // The credentials issued moments ago are served from the cache
+    assert_eq!(env.sts.calls().len(), 1);
+
+    let trail = env.audit_trail();
+    let actions: Vec<&str> = trail.iter().map(|(a, _)| a.as_str()).collect();
+    assert_eq!(actions, ["start", "callback", "confirm", "status_authorized", "renew"]);
+    assert!(trail.iter().all(|(_, outcome)| outcome == "success"));
+}
+
+#[actix_web::test]
+async fn full_flow_with_redis() {
+    full_flow(Backend::Redis).await;
+}
+
+#[actix_web::test]
+async fn full_flow_in_memory() {
+    full_flow(Backend::Memory).await;
+}
+
+#[actix_web::test]
+async fn full_flow_with_sql_sessions() {
+    full_flow(Backend::Sql).await;
+}
+
+#[actix_web::test]
+async fn login_flow_keys_share_the_state_hash_tag() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (state, auth_url) = env.begin().await;
+    assert!(env.redis.keys().contains(&format!("auth:cli:state:{{{}}}", state)));
+
+    env.authorize(&auth_url).await;
+    let keys = env.redis.keys();
+    assert!(keys.contains(&format!("auth:cli:session:{{{}}}", state)));
+    assert!(keys.contains(&"auth:cli:session:user-1".to_string()));
+    assert!(!keys.contains(&format!("auth:cli:state:{{{}}}", state)));
+}
+
+#[actix_web::test]
+async fn unknown_state_is_expired() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (code, body) = env.status("no-such-state").await;
+    assert_eq!((code, body["status"].as_str()), (200, Some("EXPIRED")));
+}
+
+#[actix_web::test]
+async fn expired_state_is_reported_and_rejected_at_callback() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (state, auth_url) = env.begin().await;
+    env.redis.expire_now(&format!("auth:cli:state:{{{}}}", state));
+
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "EXPIRED");
+    assert_eq!(problem(env.authorize(&auth_url).await).await, (400, "invalid_state".to_string()));
+}
+
+#[actix_web::test]
+async fn replayed_callback_is_rejected() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (_, auth_url) = env.begin().await;
+
+    let res = env.http.get(&auth_url).send().await.unwrap();
+    let callback = res.headers()["location"].to_str().unwrap().to_string();
+    assert_eq!(env.http.get(&callback).send().await.unwrap().status(), 200);
+
+    // The authorization code is single use at the identity provider
+    let (code, error) = problem(env.http.get(&callback).send().await.unwrap()).await;
+    assert_eq!((code, error.as_str()), (400, "invalid_authorization_code"));
+}
+
+#[actix_web::test]
+async fn denied_login_never_completes() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    env.oidc.set_faults(OidcFaults {
+        deny_login: true,
+        ..Default::default()
+    });
+    let (state, auth_url) = env.begin().await;
+
+    let (code, error) = problem(env.authorize(&auth_url).await).await;
+    assert_eq!((code, error.as_str()), (400, "invalid_request"));
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "PENDING");
+}
+
+#[actix_web::test]
+async fn token_endpoint_outage_is_a_bad_gateway() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    env.oidc.set_faults(OidcFaults {
+        token_status: Some(503),
+        ..Default::default()
+    });
+    let (state, auth_url) = env.begin().await;
+
+    let (code, error) = problem(env.authorize(&auth_url).await).await;
+    assert_eq!((code, error.as_str()), (502, "identity_provider_unavailable"));
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "PENDING");
+    assert_eq!(env.audit_trail().last().unwrap(), &("callback".to_string(), "failure".to_string()));
+}
+
+#[actix_web::test]
+async fn id_token_signed_with_unknown_key_is_rejected() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    env.oidc.set_faults(OidcFaults {
+        rogue_signature: true,
+        ..Default::default()
+    });
+    let (state, auth_url) = env.begin().await;
+
+    let (code, error) = problem(env.authorize(&auth_url).await).await;
+    assert_eq!((code, error.as_str()), (401, "invalid_id_token"));
+    let (_, body) = env.status(&state).await;
+    assert_ne!(body["status"], "AUTHORIZED");
+    assert!(env.sts.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn id_token_for_another_client_is_rejected() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    env.oidc.set_faults(OidcFaults {
+        wrong_audience: true,
+        ..Default::default()
+    });
+    let (_, auth_url) = env.begin().await;
+
+    let (code, error) = problem(env.authorize(&auth_url).await).await;
+    assert_eq!((code, error.as_str()), (401, "invalid_id_token"));
+}
+
+#[actix_web::test]
+async fn sts_denial_can_be_retried() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (state, auth_url) = env.begin().await;
+    env.authorize(&auth_url).await;
+
+    env.sts.set_deny(true);
+    let (code, body) = env.status(&state).await;
+    assert_eq!((code, body["code"].as_str()), (502, Some("sts_unavailable")));
+
+    env.sts.set_deny(false);
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "AUTHORIZED");
+}
+
+#[actix_web::test]
+async fn renew_with_unknown_refresh_token_is_unauthorized() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (code, body) = env.renew("refresh-unknown").await;
+    assert_eq!((code, body["code"].as_str()), (401, Some("invalid_refresh_token")));
+}
+
+#[actix_web::test]
+async fn logout_revokes_and_denies_the_session() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let first = env.login().await["refresh_token"].as_str().unwrap().to_string();
+    let second = env.login().await["refresh_token"].as_str().unwrap().to_string();
+
+    assert_eq!(env.logout(&first).await, 204);
+    assert!(!env.oidc.is_valid_refresh_token(&first));
+
+    // The refresh token is revoked at the identity provider
+    let (code, _) = env.renew(&first).await;
+    assert_eq!(code, 401);
+
+    // Other tokens of the user still exist, but the session is no longer active
+    let (code, body) = env.renew(&second).await;
+    assert_eq!((code, body["status"].as_str()), (200, Some("DENIED")));
+    assert!(env.audit_trail().contains(&("deny".to_string(), "failure".to_string())));
+}
+
+#[actix_web::test]
+async fn storage_outage_is_service_unavailable() {
+    let env = TestEnv::start(Backend::Redis, &["--redis-connect-timeout", "100"]).await;
+    env.redis.set_down(true);
+
+    let (code, error) = problem(env.start_login().await).await;
+    assert_eq!((code, error.as_str()), (503, "storage_unavailable"));
+}
+
+#[actix_web::test]
+async fn fast_polling_is_slowed_down() {
+    let env = TestEnv::start(Backend::Redis, &["--poll-interval", "5"]).await;
+    let (state, _) = env.begin().await;
+
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "PENDING");
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "SLOW_DOWN");
+    assert_eq!(body["interval"], 5);
+}
+
+#[actix_web::test]
+async fn start_is_rate_limited_per_ip() {
+    let env = TestEnv::start(Backend::Memory, &["--rate-limit-start-per-ip", "2"]).await;
+    assert_eq!(env.start_login().await.status(), 200);
+    assert_eq!(env.start_login().await.status(), 200);
+
+    let res = env.start_login().await;
+    assert!(res.headers().contains_key("retry-after"));
+    assert_eq!(problem(res).await, (429, "rate_limited".to_string()));
+}
+
+#[actix_web::test]
+async fn forwarding_headers_of_untrusted_peers_are_ignored() {
+    let env = TestEnv::start(Backend::Memory, &["--rate-limit-start-per-ip", "1"]).await;
+    assert_eq!(start_login_forwarded_for(&env, "203.0.113.7").await.status(), 200);
+
+    let res = start_login_forwarded_for(&env, "203.0.113.8").await;
+    assert_eq!(problem(res).await, (429, "rate_limited".to_string()));
+}
+
+#[actix_web::test]
+async fn trusted_proxies_forward_the_client_ip() {
+    let env = TestEnv::start(
+        Backend::Memory,
+        &["--rate-limit-start-per-ip", "1", "--trusted-proxies", "127.0.0.0/8"],
+    )
+    .await;
+    assert_eq!(start_login_forwarded_for(&env, "203.0.113.7").await.status(), 200);
+    assert_eq!(start_login_forwarded_for(&env, "203.0.113.8").await.status(), 200);
+
+    // Entries left of the one added by the proxy come from the client and are not used
+    let res = start_login_forwarded_for(&env, "198.51.100.1, 203.0.113.7").await;
+    assert_eq!(problem(res).await, (429, "rate_limited".to_string()));
+}
+
+#[actix_web::test]
+async fn redis_rate_limit_counters_expire_with_their_window() {
+    let env = TestEnv::start(Backend::Redis, &["--rate-limit-start-per-ip", "2", "--rate-limit-window", "60"]).await;
+    assert_eq!(env.start_login().await.status(), 200);
+    assert_eq!(env.start_login().await.status(), 200);
+    assert_eq!(problem(env.start_login().await).await, (429, "rate_limited".to_string()));
+
+    let counters: Vec<String> = env.redis.keys().into_iter().filter(|k| k.starts_with("auth:ratelimit:")).collect();
+    assert_eq!(counters.len(), 1);
+    let ttl = env.redis.ttl(&counters[0]).expect("rate limit counter without expiry");
+    assert!(ttl.as_secs() <= 60, "counter expires in {:?}", ttl);
+}
+
+#[actix_web::test]
+async fn redis_pool_checkouts_are_exported_as_counters() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    env.login().await;
+
+    let body = env.http.get(format!("{}/metrics", env.url)).send().await.unwrap().text().await.unwrap();
+    assert!(body.contains("# TYPE mega_auth_redis_pool_gets_waited_total counter"));
+    assert!(body.contains("# TYPE mega_auth_redis_pool_gets_timed_out_total counter"));
+
+    // Scraping again does not count the same checkouts twice
+    let waited = env.metric("mega_auth_redis_pool_gets_waited_total").await;
+    assert_eq!(env.metric("mega_auth_redis_pool_gets_waited_total").await, waited);
+    assert_eq!(env.metric("mega_auth_redis_pool_gets_timed_out_total").await, Some(0.0));
+}
@@ -0,0 +1,125 @@
+use crate::pages::Pages;
+use crate::tests::fake_oidc::OidcFaults;
+use crate::tests::harness::{form_value, Backend, TestEnv};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, LOCATION};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Follows the login like a browser asking for HTML, returning the callback status and page.
+async fn browser_callback(env: &TestEnv, auth_url: &str) -> (u16, String) {
+    let res = env.http.get(auth_url).send().await.unwrap();
+    let callback = res.headers()[LOCATION].to_str().unwrap().to_string();
+    let res = env
+        .http
+        .get(callback)
+        .header(ACCEPT, "text/html,application/xhtml+xml;q=0.9")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+    (res.status().as_u16(), res.text().await.unwrap())
+}
+
+/// Creates an empty directory for custom templates.
+fn templates_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mega-auth-pages-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[actix_web::test]
+async fn success_page_names_the_device() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let (state, auth_url) = env.begin().await;
+
+    let (status, page) = browser_callback(&env, &auth_url).await;
+    assert_eq!(status, 200);
+    let res = env.confirm(&state, &form_value(&page, "token").unwrap(), "confirm").await;
+    assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(res.headers()["x-frame-options"], "DENY");
+
+    let page = res.text().await.unwrap();
+    assert!(page.contains("<h1>Login successful</h1>"), "{}", page);
+    assert!(page.contains("laptop") && page.contains("linux") && page.contains("1.0.0"));
+}
+
+#[actix_web::test]
+async fn failures_have_their_own_pages() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+
+    let (state, auth_url) = env.begin().await;
+    env.redis.expire_now(&format!("auth:cli:state:{{{}}}", state));
+    let (status, page) = browser_callback(&env, &auth_url).await;
+    assert_eq!(status, 400);
+    assert!(page.contains("Login link expired"));
+
+    env.oidc.set_faults(OidcFaults {
+        deny_login: true,
+        ..Default::default()
+    });
+    let (_, auth_url) = env.begin().await;
+    let (status, page) = browser_callback(&env, &auth_url).await;
+    assert_eq!(status, 400);
+    assert!(page.contains("Login denied") && page.contains("laptop"));
+
+    env.oidc.set_faults(OidcFaults {
+        token_status: Some(503),
+        ..Default::default()
+    });
+    let (_, auth_url) = env.begin().await;
+    let (status, page) = browser_callback(&env, &auth_url).await;
+    assert_eq!(status, 502);
+    assert!(page.contains("Login failed") && page.contains("identity_provider_unavailable"));
+}
+
+#[actix_web::test]
+async fn device_details_are_escaped() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let body: serde_json::Value = env
+        .http
+        .post(format!("{}/auth/cli/start", env.url))
+        .json(&json!({ "device_name": "<script>alert(1)</script>" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let (_, page) = browser_callback(&env, body["auth_url"].as_str().unwrap()).await;
+    assert!(!page.contains("<script>"));
+    assert!(page.contains("&lt;script&gt;"));
+}
+
+#[actix_web::test]
+async fn templates_can_be_replaced() {
+    let dir = templates_dir("brand");
+    std::fs::write(
+        dir.join("base.html"),
+        "<html><h1>Acme Uploads</h1>{% block content %}{% endblock %} on {{ device_name }}</html>",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("success.html"),
+        "{% extends \"base.html\" %}{% block content %}You are in{% endblock %}",
+    )
+    .unwrap();
+
+    let env = TestEnv::start(Backend::Memory, &["--pages-dir", dir.to_str().unwrap()]).await;
+    let (_, auth_url) = env.begin().await;
+    let res = env.authorize(&auth_url).await;
+    assert_eq!(res.text().await.unwrap(), "<html><h1>Acme Uploads</h1>You are in on laptop</html>");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[actix_web::test]
+async fn broken_templates_are_rejected_at_startup() {
+    let dir = templates_dir("broken");
+    std::fs::write(dir.join("error.html"), "{% block content %}").unwrap();
+
+    assert!(Pages::load(Some(&dir)).err().unwrap().contains("error.html"));
+    assert!(Pages::load(Some(&dir.join("missing"))).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,199 @@
+use crate::tests::harness::{Backend, TestEnv};
+use mega_uploader_auth::client::{AuthClient, ClientError};
+use mega_uploader_auth::schemas::auth::CliAuthStartRequest;
+use serde_json::{json, Value};
+
+const DOWNLOAD_URL: &str = "https://downloads.example.com/mega-auth";
+
+/// Logs in with the harness CLI (version 1.0.0) and returns the state and refresh token.
+async fn logged_in(env: &TestEnv) -> (String, String) {
+    let (state, auth_url) = env.begin().await;
+    env.authorize(&auth_url).await;
+    let (_, body) = env.status(&state).await;
+    (state, body["refresh_token"].as_str().unwrap().to_string())
+}
+
+#[actix_web::test]
+async fn outdated_cli_cannot_start_a_login() {
+    let env = TestEnv::start(
+        Backend::Memory,
+        &["--cli-min-version", "1.2.0", "--cli-download-url", DOWNLOAD_URL],
+    )
+    .await;
+
+    let res = env.start_login().await;
+    assert_eq!(res.status(), 403);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["code"], "upgrade_required");
+    assert_eq!(body["minimum_version"], "1.2.0");
+    assert_eq!(body["download_url"], DOWNLOAD_URL);
+    assert!(body["detail"].as_str().unwrap().contains("1.0.0"));
+    assert!(env.audit_trail().contains(&("start".to_string(), "failure".to_string())));
+
+    // Versions that cannot be compared are not trusted to be recent enough
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/start", env.url))
+        .json(&json!({ "cli_version": "nightly" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/start", env.url))
+        .json(&json!({ "cli_version": "1.2.0" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+}
+
+#[actix_web::test]
+async fn status_and_renew_check_the_version_header() {
+    let env = TestEnv::start(
+        Backend::Memory,
+        &["--cli-min-version", "1.0.0", "--cli-download-url", DOWNLOAD_URL],
+    )
+    .await;
+    let (state, refresh_token) = logged_in(&env).await;
+
+    let res = env
+        .http
+        .get(format!("{}/auth/cli/status?state={}", env.url, state))
+        .header("X-CLI-Version", "0.9.0")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+
+    let renew = |version: Option<&'static str>| {
+        let mut req = env
+            .http
+            .post(format!("{}/auth/cli/renew", env.url))
+            .json(&json!({ "refresh_token": refresh_token }));
+        if let Some(version) = version {
+            req = req.header("X-CLI-Version", version);
+        }
+        req.send()
+    };
+    assert_eq!(renew(Some("0.9.0")).await.unwrap().status(), 403);
+    assert_eq!(renew(Some("1.0.0")).await.unwrap().status(), 200);
+    // Without the header, the version reported when the login started is checked
+    assert_eq!(renew(None).await.unwrap().status(), 200);
+}
+
+#[actix_web::test]
+async fn renew_without_header_checks_the_version_recorded_at_login() {
+    let env = TestEnv::start(
+        Backend::Sql,
+        &["--cli-min-version", "1.0.0", "--cli-download-url", DOWNLOAD_URL],
+    )
+    .await;
+    let (_, refresh_token) = logged_in(&env).await;
+
+    let mut session = env.sessions.get_session("user-1").await.unwrap().unwrap();
+    assert_eq!(session.cli_version.as_deref(), Some("1.0.0"));
+    // As if the login had been made by a CLI that predates the minimum
+    session.cli_version = Some("0.9.0".to_string());
+    env.sessions.put_session(&session, 3600).await.unwrap();
+
+    let renew = |version: Option<&'static str>| {
+        let mut req = env
+            .http
+            .post(format!("{}/auth/cli/renew", env.url))
+            .json(&json!({ "refresh_token": refresh_token }));
+        if let Some(version) = version {
+            req = req.header("X-CLI-Version", version);
+        }
+        req.send()
+    };
+    let res = renew(None).await.unwrap();
+    assert_eq!(res.status(), 403);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["code"], "upgrade_required");
+    assert!(env.audit_trail().contains(&("renew".to_string(), "failure".to_string())));
+
+    // The header of an upgraded CLI takes precedence over the recorded version
+    assert_eq!(renew(Some("1.1.0")).await.unwrap().status(), 200);
+}
+
+#[actix_web::test]
+async fn deprecated_versions_are_warned() {
+    let env = TestEnv::start(
+        Backend::Redis,
+        &["--cli-deprecated-versions", "<1.1.0", "--cli-download-url", DOWNLOAD_URL],
+    )
+    .await;
+
+    let res = env.start_login().await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.headers()["x-cli-deprecation"],
+        format!("CLI version 1.0.0 is deprecated, upgrade from {}", DOWNLOAD_URL).as_str()
+    );
+
+    let (_, refresh_token) = logged_in(&env).await;
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/renew", env.url))
+        .header("X-CLI-Version", "1.0.3")
+        .json(&json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert!(res.headers().contains_key("x-cli-deprecation"));
+
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/renew", env.url))
+        .header("X-CLI-Version", "1.1.0")
+        .json(&json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    assert!(!res.headers().contains_key("x-cli-deprecation"));
+}
+
+#[actix_web::test]
+async fn client_reports_its_version() {
+    let env = TestEnv::start(
+        Backend::Memory,
+        &[
+            "--cli-min-version",
+            "1.0.0",
+            "--cli-deprecated-versions",
+            "<2.0.0",
+            "--cli-download-url",
+            DOWNLOAD_URL,
+        ],
+    )
+    .await;
+    let request = CliAuthStartRequest {
+        device_name: None,
+        os: None,
+        cli_version: None,
+    };
+
+    let outdated = AuthClient::new(&env.url).with_cli_version("0.5.0");
+    match outdated.start(&request).await {
+        Err(ClientError::UpgradeRequired {
+            minimum_version,
+            download_url,
+        }) => {
+            assert_eq!(minimum_version, "1.0.0");
+            assert_eq!(download_url.as_deref(), Some(DOWNLOAD_URL));
+        }
+        other => panic!("expected UpgradeRequired, got {:?}", other.map(|l| l.state)),
+    }
+
+    let deprecated = AuthClient::new(&env.url).with_cli_version("1.5.0");
+    deprecated.start(&request).await.unwrap();
+    assert!(deprecated.deprecation_warning().unwrap().contains("1.5.0"));
+
+    let current = AuthClient::new(&env.url).with_cli_version("2.0.0");
+    current.start(&request).await.unwrap();
+    assert_eq!(current.deprecation_warning(), None);
+}
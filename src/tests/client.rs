@@ -0,0 +1,86 @@
+use crate::tests::harness::{Backend, TestEnv};
+use mega_uploader_auth::client::{AuthClient, ClientError, LoginRequest};
+use mega_uploader_auth::schemas::auth::{CliAuthResponse, CliAuthStartRequest};
+
+fn start_request() -> CliAuthStartRequest {
+    CliAuthStartRequest {
+        device_name: Some("laptop".to_string()),
+        os: Some("linux".to_string()),
+        cli_version: Some("1.0.0".to_string()),
+    }
+}
+
+/// Starts a login with the client and completes it in the "browser" while the client waits.
+async fn client_login(env: &TestEnv, client: &AuthClient) -> mega_uploader_auth::client::Credentials {
+    let login = client.start(&start_request()).await.unwrap();
+    let (authorized, browser) = tokio::join!(client.wait_for_authorization(&login), env.authorize(&login.auth_url));
+    assert_eq!(browser.status(), 200);
+    authorized.unwrap()
+}
+
+#[actix_web::test]
+async fn login_renew_and_logout() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let client = AuthClient::new(&env.url);
+
+    let creds = client_login(&env, &client).await;
+    assert!(creds.access_key_id.starts_with("ASIA"));
+    assert_eq!(creds.expires_at, 4_070_908_800);
+    let refresh_token = creds.refresh_token.expect("refresh token");
+
+    let renewed = client.renew(&refresh_token).await.unwrap();
+    assert_eq!(renewed.access_key_id, creds.access_key_id);
+
+    client.logout(&refresh_token).await.unwrap();
+    let err = client.renew(&refresh_token).await.unwrap_err();
+    assert!(err.is_session_ended(), "{:?}", err);
+}
+
+#[actix_web::test]
+async fn waiting_follows_slow_down() {
+    let env = TestEnv::start(Backend::Memory, &["--poll-interval", "2"]).await;
+    let client = AuthClient::new(&env.url);
+
+    // Poll faster than the service allows: the client must slow down, not fail
+    let mut login = client.start(&start_request()).await.unwrap();
+    assert_eq!(login.interval, 2);
+    login.interval = 1;
+    assert!(matches!(client.status(&login.state).await, Ok(CliAuthResponse::PENDING)));
+    assert_eq!(env.authorize(&login.auth_url).await.status(), 200);
+
+    let creds = client.wait_for_authorization(&login).await.unwrap();
+    assert!(creds.refresh_token.is_some());
+
+    let metrics = env.http.get(format!("{}/metrics", env.url)).send().await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("mega_auth_cli_auth_responses_total{status=\"SLOW_DOWN\"}"));
+}
+
+#[actix_web::test]
+async fn unknown_login_request_expires() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let client = AuthClient::new(&env.url);
+
+    let login = LoginRequest {
+        state: "unknown".to_string(),
+        auth_url: String::new(),
+        expires_in: 60,
+        interval: 1,
+        verification_code: String::new(),
+    };
+    assert!(matches!(client.wait_for_authorization(&login).await, Err(ClientError::Expired)));
+}
+
+#[actix_web::test]
+async fn service_errors_are_typed() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+
+    let err = AuthClient::new(&env.url).renew("not-a-token").await.unwrap_err();
+    let ClientError::Rejected(problem) = &err else {
+        panic!("unexpected error {:?}", err);
+    };
+    assert_eq!(problem.code, "invalid_refresh_token");
+    assert!(err.is_session_ended());
+
+    let unreachable = AuthClient::new("http://127.0.0.1:9").renew("x").await.unwrap_err();
+    assert!(matches!(unreachable, ClientError::Unavailable(_)));
+}
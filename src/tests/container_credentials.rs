@@ -0,0 +1,152 @@
+use crate::tests::fake_oidc::OidcFaults;
+use crate::tests::harness::{Backend, TestEnv, ROLE_ARN};
+use reqwest::header::AUTHORIZATION;
+use serde_json::{json, Value};
+
+/// `POST /auth/cli/container-token`, returning the status code and JSON body.
+async fn container_token(env: &TestEnv, refresh_token: &str) -> (u16, Value) {
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/container-token", env.url))
+        .json(&json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    (res.status().as_u16(), res.json().await.unwrap_or(Value::Null))
+}
+
+/// `GET` on the credentials URI with the given `Authorization` header.
+async fn container_credentials(env: &TestEnv, uri: &str, authorization: Option<&str>) -> (u16, Value) {
+    let mut req = env.http.get(uri);
+    if let Some(authorization) = authorization {
+        req = req.header(AUTHORIZATION, authorization);
+    }
+    let res = req.send().await.unwrap();
+    (res.status().as_u16(), res.json().await.unwrap_or(Value::Null))
+}
+
+/// Logs in and obtains a container token, returning the login body, token and credentials URI.
+async fn login_with_token(env: &TestEnv) -> (Value, String, String) {
+    let login = env.login().await;
+    let (code, body) = container_token(env, login["refresh_token"].as_str().unwrap()).await;
+    assert_eq!(code, 200, "{}", body);
+    assert_eq!(body["expires_in"], 30 * 24 * 3600);
+    assert_eq!(body["credentials_uri"], format!("{}/auth/cli/container-credentials", env.url));
+    (
+        login,
+        body["token"].as_str().unwrap().to_string(),
+        body["credentials_uri"].as_str().unwrap().to_string(),
+    )
+}
+
+#[actix_web::test]
+async fn serves_credentials_in_container_format() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (login, token, uri) = login_with_token(&env).await;
+    assert_eq!(uri, format!("{}/auth/cli/container-credentials", env.url));
+
+    // The SDKs send the token verbatim, so both forms are accepted
+    for authorization in [token.clone(), format!("Bearer {}", token)] {
+        let (code, body) = container_credentials(&env, &uri, Some(&authorization)).await;
+        assert_eq!(code, 200, "{}", body);
+        assert_eq!(body["AccessKeyId"], login["access_key_id"]);
+        assert_eq!(body["SecretAccessKey"], login["secret_access_key"]);
+        assert_eq!(body["Token"], login["session_token"]);
+        assert_eq!(body["Expiration"], "2099-01-01T00:00:00Z");
+        assert_eq!(body["RoleArn"], ROLE_ARN);
+    }
+
+    // Credentials come from the cache and the token is only stored hashed
+    assert_eq!(env.sts.calls().len(), 1);
+    assert!(env.redis.keys().iter().all(|k| !k.contains(&token)));
+    assert!(env.audit_trail().contains(&("container_credentials".to_string(), "success".to_string())));
+}
+
+#[actix_web::test]
+async fn missing_or_unknown_token_is_rejected() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let uri = format!("{}/auth/cli/container-credentials", env.url);
+
+    for authorization in [None, Some("Bearer "), Some("Bearer not-a-token")] {
+        let (code, body) = container_credentials(&env, &uri, authorization).await;
+        assert_eq!(code, 401, "{:?}", authorization);
+        assert_eq!(body["code"], "invalid_container_token");
+    }
+    assert!(env.sts.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn token_ends_with_its_session() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let (login, token, uri) = login_with_token(&env).await;
+
+    assert_eq!(env.logout(login["refresh_token"].as_str().unwrap()).await, 204);
+
+    let (code, body) = container_credentials(&env, &uri, Some(&token)).await;
+    assert_eq!(code, 401);
+    assert_eq!(body["code"], "invalid_container_token");
+    assert!(env.audit_trail().contains(&("deny".to_string(), "failure".to_string())));
+
+    // Ended sessions cannot obtain new tokens either
+    let (code, _) = container_token(&env, login["refresh_token"].as_str().unwrap()).await;
+    assert_eq!(code, 401);
+}
+
+#[actix_web::test]
+async fn new_login_invalidates_previous_tokens() {
+    let env = TestEnv::start(Backend::Sql, &[]).await;
+    let (_, old_token, uri) = login_with_token(&env).await;
+    let (_, new_token, _) = login_with_token(&env).await;
+
+    let (code, _) = container_credentials(&env, &uri, Some(&old_token)).await;
+    assert_eq!(code, 401);
+    let (code, _) = container_credentials(&env, &uri, Some(&new_token)).await;
+    assert_eq!(code, 200);
+}
+
+#[actix_web::test]
+async fn rotated_refresh_tokens_are_not_lost() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let login = env.login().await;
+    let refresh_token = login["refresh_token"].as_str().unwrap();
+    env.oidc.set_faults(OidcFaults {
+        rotate_refresh_tokens: true,
+        ..Default::default()
+    });
+
+    // The rotated token goes back to the CLI, which renews with it from then on
+    let (code, body) = container_token(&env, refresh_token).await;
+    assert_eq!(code, 200, "{}", body);
+    let rotated = body["refresh_token"].as_str().unwrap();
+    assert!(!env.oidc.is_valid_refresh_token(refresh_token));
+    assert!(env.oidc.is_valid_refresh_token(rotated));
+
+    // Serving credentials leaves the token of the CLI alone
+    let uri = body["credentials_uri"].as_str().unwrap();
+    for _ in 0..2 {
+        let (code, _) = container_credentials(&env, uri, body["token"].as_str()).await;
+        assert_eq!(code, 200);
+    }
+    assert!(env.oidc.is_valid_refresh_token(rotated));
+
+    let (code, renewed) = env.renew(rotated).await;
+    assert_eq!(code, 200, "{}", renewed);
+}
+
+#[actix_web::test]
+async fn credentials_uri_ignores_forwarding_headers() {
+    let env = TestEnv::start(Backend::Memory, &["--trusted-proxies", "127.0.0.1/32"]).await;
+    let login = env.login().await;
+
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/container-token", env.url))
+        .header("X-Forwarded-Host", "evil.example.com")
+        .header("X-Forwarded-Proto", "https")
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["credentials_uri"], format!("{}/auth/cli/container-credentials", env.url));
+}
@@ -0,0 +1,181 @@
+use crate::tests::harness::{Backend, TestEnv};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde_json::{json, Value};
+
+/// Completes a login up to the callback and returns its state.
+async fn authorized_state(env: &TestEnv) -> String {
+    let (state, auth_url) = env.begin().await;
+    assert_eq!(env.authorize(&auth_url).await.status(), 200);
+    state
+}
+
+#[actix_web::test]
+async fn status_in_credential_process_format() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let state = authorized_state(&env).await;
+
+    let res = env
+        .http
+        .get(format!("{}/auth/cli/status?state={}&format=credential_process", env.url, state))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()[CONTENT_TYPE], "application/x-aws-credential-process+json");
+    let body: Value = res.json().await.unwrap();
+
+    assert_eq!(body["Version"], 1);
+    assert!(body["AccessKeyId"].as_str().unwrap().starts_with("ASIA"));
+    assert!(body["SecretAccessKey"].is_string());
+    assert!(body["SessionToken"].is_string());
+    assert_eq!(body["Expiration"], "2099-01-01T00:00:00Z");
+    assert!(body["RefreshToken"].is_string());
+}
+
+#[actix_web::test]
+async fn renew_after_a_status_in_a_text_format() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+
+    for format in ["env", "credentials"] {
+        let state = authorized_state(&env).await;
+        let body = env
+            .http
+            .get(format!(
+                "{}/auth/cli/status?state={}&format={}&include_refresh_token=true",
+                env.url, state, format
+            ))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let refresh_token = body
+            .lines()
+            .find_map(|line| line.strip_prefix("# refresh_token = "))
+            .unwrap_or_else(|| panic!("no refresh token in the {} format", format));
+
+        let (code, renewed) = env.renew(refresh_token).await;
+        assert_eq!(code, 200, "{}", format);
+        assert_eq!(renewed["status"], "AUTHORIZED");
+    }
+}
+
+#[actix_web::test]
+async fn renew_in_env_format_through_accept() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let login = env.login().await;
+
+    let res = env
+        .http
+        .post(format!("{}/auth/cli/renew", env.url))
+        .header(ACCEPT, "text/x-shellscript, application/json;q=0.5")
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()[CONTENT_TYPE], "text/x-shellscript");
+    let body = res.text().await.unwrap();
+
+    let expected = format!(
+        "export AWS_ACCESS_KEY_ID='{}'\nexport AWS_SECRET_ACCESS_KEY='{}'\nexport AWS_SESSION_TOKEN='{}'\n\
+         export AWS_CREDENTIAL_EXPIRATION='2099-01-01T00:00:00Z'\n",
+        login["access_key_id"].as_str().unwrap(),
+        login["secret_access_key"].as_str().unwrap(),
+        login["session_token"].as_str().unwrap(),
+    );
+    assert_eq!(body, expected);
+}
+
+#[actix_web::test]
+async fn renew_as_credentials_profile() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let login = env.login().await;
+
+    let body = env
+        .http
+        .post(format!("{}/auth/cli/renew?format=credentials&profile=mega-dev", env.url))
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let mut lines = body.lines();
+    assert_eq!(lines.next(), Some("[mega-dev]"));
+    assert_eq!(
+        lines.next(),
+        Some(format!("aws_access_key_id = {}", login["access_key_id"].as_str().unwrap()).as_str())
+    );
+    assert!(lines.next().unwrap().starts_with("aws_secret_access_key = "));
+    assert!(lines.next().unwrap().starts_with("aws_session_token = "));
+    assert!(!body.contains("refresh_token"), "refresh token written without being asked for");
+}
+
+#[actix_web::test]
+async fn accept_quality_values_are_honoured() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let login = env.login().await;
+
+    for (accept, content_type) in [
+        ("text/x-shellscript;q=0.2, text/x-aws-credentials", "text/x-aws-credentials"),
+        ("application/json;q=0.5, text/x-shellscript;q=0.9", "text/x-shellscript"),
+        ("text/x-shellscript;q=0, text/html", "application/json"),
+    ] {
+        let res = env
+            .http
+            .post(format!("{}/auth/cli/renew", env.url))
+            .header(ACCEPT, accept)
+            .json(&json!({ "refresh_token": login["refresh_token"] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], content_type, "{}", accept);
+    }
+}
+
+#[actix_web::test]
+async fn other_statuses_stay_json() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let (state, _) = env.begin().await;
+
+    let res = env
+        .http
+        .get(format!("{}/auth/cli/status?state={}", env.url, state))
+        .header(ACCEPT, "application/x-aws-credential-process+json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["status"], "PENDING");
+}
+
+#[actix_web::test]
+async fn unknown_format_or_bad_profile_is_rejected() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+
+    for query in ["format=yaml", "format=credentials&profile=a%20b"] {
+        let res = env
+            .http
+            .get(format!("{}/auth/cli/status?state=x&{}", env.url, query))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400, "{}", query);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["code"], "invalid_request");
+    }
+}
+
+#[test]
+fn expirations_are_iso_8601_utc() {
+    use mega_uploader_auth::format::iso_8601;
+
+    assert_eq!(iso_8601(0), "1970-01-01T00:00:00Z");
+    assert_eq!(iso_8601(951_782_400), "2000-02-29T00:00:00Z");
+    assert_eq!(iso_8601(1_709_251_199), "2024-02-29T23:59:59Z");
+    assert_eq!(iso_8601(4_070_908_800), "2099-01-01T00:00:00Z");
+    assert_eq!(iso_8601(-86_400), "1969-12-31T00:00:00Z");
+}
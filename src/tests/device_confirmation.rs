@@ -0,0 +1,110 @@
+use crate::tests::harness::{form_value, Backend, TestEnv};
+use serde_json::Value;
+
+/// Starts a login and opens it in the browser, returning the state, the verification code
+/// given to the CLI and the confirmation page.
+async fn open_confirmation(env: &TestEnv) -> (String, String, String) {
+    let body: Value = env.start_login().await.json().await.unwrap();
+    let auth_url = body["auth_url"].as_str().unwrap();
+    let code = body["verification_code"].as_str().unwrap().to_string();
+
+    let res = env.open_login(auth_url).await;
+    assert_eq!(res.status(), 200);
+    let page = res.text().await.unwrap();
+    let state = form_value(&page, "state").unwrap();
+    (state, code, page)
+}
+
+#[actix_web::test]
+async fn login_waits_for_the_device_confirmation() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (state, code, page) = open_confirmation(&env).await;
+
+    assert!(code.len() == 9 && code.as_bytes()[4] == b'-', "{}", code);
+    assert!(page.contains(&code));
+    for detail in ["laptop", "linux", "1.0.0", "127.0.0.1", "user-1@example.com"] {
+        assert!(page.contains(detail), "the page should show {}", detail);
+    }
+
+    // Logged in but not confirmed: the CLI keeps waiting and no session exists yet
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "PENDING");
+    assert!(env.redis.get("auth:cli:session:user-1").is_none());
+    assert!(env.redis.keys().contains(&format!("auth:cli:confirm:{{{}}}", state)));
+
+    let token = form_value(&page, "token").unwrap();
+    assert_eq!(env.confirm(&state, &token, "confirm").await.status(), 200);
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "AUTHORIZED");
+    assert!(!env.redis.keys().iter().any(|k| k.starts_with("auth:cli:confirm:")));
+
+    // The confirmation is single use
+    assert_eq!(env.confirm(&state, &token, "confirm").await.status(), 400);
+}
+
+#[actix_web::test]
+async fn rejected_device_gets_no_credentials() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let (state, _, page) = open_confirmation(&env).await;
+    let token = form_value(&page, "token").unwrap();
+
+    let res = env.confirm(&state, &token, "reject").await;
+    assert_eq!(res.status(), 200);
+    assert!(res.text().await.unwrap().contains("Login denied"));
+
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "EXPIRED");
+    assert_eq!(env.confirm(&state, &token, "confirm").await.status(), 400);
+    assert!(env.audit_trail().contains(&("confirm".to_string(), "failure".to_string())));
+}
+
+#[actix_web::test]
+async fn confirmation_needs_the_token_of_the_page() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    let (state, _, page) = open_confirmation(&env).await;
+
+    // Knowing the state, as the author of a phishing link does, is not enough
+    let res = env.confirm(&state, "guessed-token", "confirm").await;
+    assert_eq!(res.status(), 400);
+    let (_, body) = env.status(&state).await;
+    assert_eq!(body["status"], "PENDING");
+
+    let token = form_value(&page, "token").unwrap();
+    assert_eq!(env.confirm(&state, &token, "confirm").await.status(), 200);
+
+    let res = env.confirm(&state, &token, "maybe").await;
+    assert_eq!(res.status(), 400);
+}
+
+#[actix_web::test]
+async fn expired_login_cannot_be_confirmed() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (state, _, page) = open_confirmation(&env).await;
+    env.redis.expire_now(&format!("auth:cli:state:{{{}}}", state));
+
+    let res = env.confirm(&state, &form_value(&page, "token").unwrap(), "confirm").await;
+    assert_eq!(res.status(), 400);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_state");
+    assert!(env.redis.get("auth:cli:session:user-1").is_none());
+}
+
+#[actix_web::test]
+async fn waiting_login_cannot_be_replaced() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let body: Value = env.start_login().await.json().await.unwrap();
+    let auth_url = body["auth_url"].as_str().unwrap();
+    let page = env.open_login(auth_url).await.text().await.unwrap();
+    let state = form_value(&page, "state").unwrap();
+
+    // Another account completing a login for the same state is turned away
+    env.oidc.set_user("user-2", "user-2@example.com");
+    assert_eq!(env.open_login(auth_url).await.status(), 400);
+
+    // Wrong tokens leave the waiting login in place
+    assert_eq!(env.confirm(&state, "guessed-token", "confirm").await.status(), 400);
+    let token = form_value(&page, "token").unwrap();
+    assert_eq!(env.confirm(&state, &token, "confirm").await.status(), 200);
+    assert!(env.redis.get("auth:cli:session:user-1").is_some());
+    assert!(env.redis.get("auth:cli:session:user-2").is_none());
+}
@@ -0,0 +1,248 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rsa::pkcs1::EncodeRsaPrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// RSA keys shared by every test: the published one, and one the JWKS does not know.
+///
+/// Generating RSA keys is slow in debug builds, so it is done once per test run.
+static KEYS: LazyLock<(SigningKey, SigningKey)> =
+    LazyLock::new(|| (SigningKey::generate("fake-key-1"), SigningKey::generate("rogue-key")));
+
+/// RSA key signing ID tokens, with its public part as a JWK.
+struct SigningKey {
+    kid: String,
+    encoding: EncodingKey,
+    jwk: serde_json::Value,
+}
+
+impl SigningKey {
+    fn generate(kid: &str) -> Self {
+        let private = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).expect("generate RSA key");
+        let der = private.to_pkcs1_der().expect("encode RSA key");
+        let jwk = json!({
+            "kty": "RSA",
+            "alg": "RS256",
+            "use": "sig",
+            "kid": kid,
+            "n": URL_SAFE_NO_PAD.encode(private.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(private.e().to_bytes_be()),
+        });
+        Self {
+            kid: kid.to_string(),
+            encoding: EncodingKey::from_rsa_der(der.as_bytes()),
+            jwk,
+        }
+    }
+}
+
+/// How the fake identity provider misbehaves, to exercise failure paths.
+#[derive(Default, Clone, Copy)]
+pub struct OidcFaults {
+    /// The user refuses the login on the authorize page.
+    pub deny_login: bool,
+    /// Status returned by the token endpoint instead of tokens.
+    pub token_status: Option<u16>,
+    /// Time the token endpoint takes to answer.
+    pub token_delay: Option<Duration>,
+    /// Status returned by the JWKS endpoint instead of the keys.
+    pub jwks_status: Option<u16>,
+    /// ID tokens are signed with a key missing from the JWKS.
+    pub rogue_signature: bool,
+    /// ID tokens are issued for another client.
+    pub wrong_audience: bool,
+    /// Refresh grants return a new refresh token and revoke the one used, like Cognito with
+    /// refresh token rotation once the grace period is over.
+    pub rotate_refresh_tokens: bool,
+}
+
+/// Local stand-in for the Cognito hosted UI and user pool.
+///
+/// Serves the JWKS, the authorize endpoint (which logs the configured user in without any
+/// page and redirects back to the callback), and the token and revoke endpoints.
+pub struct FakeOidc {
+    base_url: String,
+    state: Arc<OidcState>,
+}
+
+struct OidcState {
+    issuer: String,
+    client_id: String,
+    user: Mutex<(String, String)>,
+    codes: Mutex<HashMap<String, String>>,
+    refresh_tokens: Mutex<HashMap<String, String>>,
+    faults: Mutex<OidcFaults>,
+}
+
+impl FakeOidc {
+    /// Starts the provider on a random local port, issuing tokens for `client_id`.
+    pub fn start(client_id: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fake oidc");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(OidcState {
+            issuer: base_url.clone(),
+            client_id: client_id.to_string(),
+            user: Mutex::new(("user-1".to_string(), "user-1@example.com".to_string())),
+            codes: Mutex::new(HashMap::new()),
+            refresh_tokens: Mutex::new(HashMap::new()),
+            faults: Mutex::new(OidcFaults::default()),
+        });
+        LazyLock::force(&KEYS);
+
+        let data = web::Data::from(state.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/.well-known/jwks.json", web::get().to(jwks))
+                .route("/oauth2/authorize", web::get().to(authorize))
+                .route("/oauth2/token", web::post().to(token))
+                .route("/oauth2/revoke", web::post().to(revoke))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("listen fake oidc")
+        .run();
+        actix_web::rt::spawn(server);
+
+        Self { base_url, state }
+    }
+
+    /// Base URL, used as the Cognito domain and as the token issuer.
+    pub fn url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Sets the user logged in by the authorize endpoint from now on.
+    pub fn set_user(&self, sub: &str, email: &str) {
+        *self.state.user.lock().unwrap() = (sub.to_string(), email.to_string());
+    }
+
+    /// Sets how the provider misbehaves from now on.
+    pub fn set_faults(&self, faults: OidcFaults) {
+        *self.state.faults.lock().unwrap() = faults;
+    }
+
+    /// Whether a refresh token is still accepted.
+    pub fn is_valid_refresh_token(&self, refresh_token: &str) -> bool {
+        self.state.refresh_tokens.lock().unwrap().contains_key(refresh_token)
+    }
+}
+
+impl OidcState {
+    fn id_token(&self, sub: &str, email: &str) -> String {
+        let faults = *self.faults.lock().unwrap();
+        let key = if faults.rogue_signature { &KEYS.1 } else { &KEYS.0 };
+        let audience = if faults.wrong_audience { "another-client" } else { self.client_id.as_str() };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(key.kid.clone());
+        let claims = json!({
+            "sub": sub,
+            "email": email,
+            "iss": self.issuer,
+            "aud": audience,
+            "token_use": "id",
+            "iat": now,
+            "exp": now + 3600,
+        });
+        encode(&header, &claims, &key.encoding).expect("sign ID token")
+    }
+}
+
+async fn jwks(state: web::Data<OidcState>) -> HttpResponse {
+    if let Some(status) = state.faults.lock().unwrap().jwks_status {
+        return HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap()).finish();
+    }
+    HttpResponse::Ok().json(json!({ "keys": [KEYS.0.jwk] }))
+}
+
+async fn authorize(state: web::Data<OidcState>, query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let redirect_uri = query.get("redirect_uri").cloned().unwrap_or_default();
+    let login_state = query.get("state").cloned().unwrap_or_default();
+
+    let location = if state.faults.lock().unwrap().deny_login {
+        format!("{}?error=access_denied&state={}", redirect_uri, login_state)
+    } else {
+        let code = Uuid::new_v4().to_string();
+        let sub = state.user.lock().unwrap().0.clone();
+        state.codes.lock().unwrap().insert(code.clone(), sub);
+        format!("{}?code={}&state={}", redirect_uri, code, login_state)
+    };
+
+    HttpResponse::Found().insert_header((LOCATION, location)).finish()
+}
+
+async fn token(state: web::Data<OidcState>, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    let delay = state.faults.lock().unwrap().token_delay;
+    if let Some(delay) = delay {
+        actix_web::rt::time::sleep(delay).await;
+    }
+    if let Some(status) = state.faults.lock().unwrap().token_status {
+        return HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap())
+            .json(json!({ "error": "server_error" }));
+    }
+    if form.get("client_id") != Some(&state.client_id) {
+        return HttpResponse::BadRequest().json(json!({ "error": "invalid_client" }));
+    }
+
+    let (sub, refresh_token) = match form.get("grant_type").map(String::as_str) {
+        Some("authorization_code") => {
+            let code = form.get("code").cloned().unwrap_or_default();
+            let Some(sub) = state.codes.lock().unwrap().remove(&code) else {
+                return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+            };
+            let refresh_token = format!("refresh-{}", Uuid::new_v4());
+            state
+                .refresh_tokens
+                .lock()
+                .unwrap()
+                .insert(refresh_token.clone(), sub.clone());
+            (sub, Some(refresh_token))
+        }
+        Some("refresh_token") => {
+            let refresh_token = form.get("refresh_token").cloned().unwrap_or_default();
+            let mut refresh_tokens = state.refresh_tokens.lock().unwrap();
+            let Some(sub) = refresh_tokens.get(&refresh_token).cloned() else {
+                return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+            };
+            if !state.faults.lock().unwrap().rotate_refresh_tokens {
+                // Like Cognito without rotation, no new refresh token is returned
+                (sub, None)
+            } else {
+                refresh_tokens.remove(&refresh_token);
+                let rotated = format!("refresh-{}", Uuid::new_v4());
+                refresh_tokens.insert(rotated.clone(), sub.clone());
+                (sub, Some(rotated))
+            }
+        }
+        _ => return HttpResponse::BadRequest().json(json!({ "error": "unsupported_grant_type" })),
+    };
+
+    let email = state.user.lock().unwrap().1.clone();
+    HttpResponse::Ok().json(json!({
+        "access_token": format!("access-{}", Uuid::new_v4()),
+        "id_token": state.id_token(&sub, &email),
+        "refresh_token": refresh_token,
+        "expires_in": 3600,
+        "token_type": "Bearer",
+    }))
+}
+
+async fn revoke(state: web::Data<OidcState>, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    if let Some(token) = form.get("token") {
+        state.refresh_tokens.lock().unwrap().remove(token);
+    }
+    HttpResponse::Ok().finish()
+}
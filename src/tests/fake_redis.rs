@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Ephemeral Redis speaking enough RESP2 for the service: strings with expiry, `GETDEL`, counters,
+/// `MULTI`/`EXEC`, `SCAN` and `XADD`.
+///
+/// Every test gets its own server on a random port, so nothing leaks between tests.
+pub struct FakeRedis {
+    port: u16,
+    data: Arc<Mutex<HashMap<String, Entry>>>,
+    down: Arc<AtomicBool>,
+}
+
+struct Entry {
+    value: Stored,
+    expires_at: Option<Instant>,
+}
+
+enum Stored {
+    String(Vec<u8>),
+    Stream(usize),
+}
+
+/// RESP reply.
+enum Reply {
+    Ok,
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl FakeRedis {
+    /// Starts the server on a random local port.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fake redis");
+        let port = listener.local_addr().unwrap().port();
+        let data = Arc::new(Mutex::new(HashMap::new()));
+        let down = Arc::new(AtomicBool::new(false));
+
+        let (data_, down_) = (data.clone(), down.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (data, down) = (data_.clone(), down_.clone());
+                thread::spawn(move || serve(stream, data, down));
+            }
+        });
+
+        Self { port, data, down }
+    }
+
+    /// Connection URL of the server.
+    pub fn url(&self) -> String {
+        format!("redis://127.0.0.1:{}", self.port)
+    }
+
+    /// Makes every command fail (or work again), as during an outage.
+    pub fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+    }
+
+    /// Names of the live keys, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut data = self.data.lock().unwrap();
+        purge(&mut data);
+        let mut keys: Vec<String> = data.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Value of a string key, if it exists.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let mut data = self.data.lock().unwrap();
+        purge(&mut data);
+        match data.get(key) {
+            Some(Entry { value: Stored::String(v), .. }) => Some(String::from_utf8_lossy(v).into_owned()),
+            _ => None,
+        }
+    }
+
+    /// Time left before a key expires, if it exists and has an expiry.
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        let mut data = self.data.lock().unwrap();
+        purge(&mut data);
+        data.get(key)?.expires_at.map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Removes a key, as if it had expired.
+    pub fn expire_now(&self, key: &str) {
+        self.data.lock().unwrap().remove(key);
+    }
+}
+
+fn purge(data: &mut HashMap<String, Entry>) {
+    let now = Instant::now();
+    data.retain(|_, e| e.expires_at.is_none_or(|at| at > now));
+}
+
+fn serve(stream: TcpStream, data: Arc<Mutex<HashMap<String, Entry>>>, down: Arc<AtomicBool>) {
+    let mut writer = stream.try_clone().expect("clone fake redis stream");
+    let mut reader = BufReader::new(stream);
+    let mut queued: Option<Vec<Vec<String>>> = None;
+
+    while let Some(args) = read_command(&mut reader) {
+        let name = args.first().map(|a| a.to_ascii_uppercase()).unwrap_or_default();
+        let reply = if down.load(Ordering::SeqCst) {
+            Reply::Error("ERR fake outage".to_string())
+        } else {
+            match (name.as_str(), &mut queued) {
+                ("MULTI", _) => {
+                    queued = Some(Vec::new());
+                    Reply::Ok
+                }
+                ("EXEC", Some(_)) => {
+                    let commands = queued.take().unwrap_or_default();
+                    let mut data = data.lock().unwrap();
+                    Reply::Array(commands.iter().map(|c| execute(c, &mut data)).collect())
+                }
+                (_, Some(commands)) => {
+                    commands.push(args);
+                    Reply::Status("QUEUED")
+                }
+                (_, None) => execute(&args, &mut data.lock().unwrap()),
+            }
+        };
+
+        let mut out = Vec::new();
+        encode(&reply, &mut out);
+        if writer.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+    let count: usize = read_line(reader)?.strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(reader)?.strip_prefix('$')?.parse().ok()?;
+        let mut buf = vec![0; len + 2];
+        reader.read_exact(&mut buf).ok()?;
+        buf.truncate(len);
+        args.push(String::from_utf8_lossy(&buf).into_owned());
+    }
+    Some(args)
+}
+
+fn read_line(reader: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end().to_string()),
+    }
+}
+
+fn execute(args: &[String], data: &mut HashMap<String, Entry>) -> Reply {
+    purge(data);
+    let name = args[0].to_ascii_uppercase();
+    let key = args.get(1).cloned().unwrap_or_default();
+
+    match name.as_str() {
+        "PING" => Reply::Status("PONG"),
+        "CLIENT" | "SELECT" => Reply::Ok,
+        "GET" => match data.get(&key) {
+            Some(Entry { value: Stored::String(v), .. }) => Reply::Bulk(Some(v.clone())),
+            Some(_) => Reply::Error("WRONGTYPE".to_string()),
+            None => Reply::Bulk(None),
+        },
+        "SET" => {
+            let mut ttl = None;
+            let mut nx = false;
+            let mut options = args[3..].iter();
+            while let Some(option) = options.next() {
+                match option.to_ascii_uppercase().as_str() {
+                    "EX" => ttl = options.next().and_then(|s| s.parse::<u64>().ok()),
+                    "NX" => nx = true,
+                    other => return Reply::Error(format!("ERR unsupported SET option {}", other)),
+                }
+            }
+            if nx && data.contains_key(&key) {
+                return Reply::Bulk(None);
+            }
+            data.insert(
+                key,
+                Entry {
+                    value: Stored::String(args[2].clone().into_bytes()),
+                    expires_at: ttl.map(|s| Instant::now() + Duration::from_secs(s)),
+                },
+            );
+            Reply::Ok
+        }
+        "SETEX" => {
+            let ttl = args.get(2).and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+            data.insert(
+                key,
+                Entry {
+                    value: Stored::String(args[3].clone().into_bytes()),
+                    expires_at: Some(Instant::now() + Duration::from_secs(ttl)),
+                },
+            );
+            Reply::Ok
+        }
+        "SCAN" => {
+            // Every key is returned in one batch, with the final cursor
+            let pattern = match args.iter().position(|a| a.eq_ignore_ascii_case("MATCH")) {
+                Some(i) => args.get(i + 1).cloned().unwrap_or_default(),
+                None => "*".to_string(),
+            };
+            let keys = data
+                .keys()
+                .filter(|k| glob_match(&pattern, k))
+                .map(|k| Reply::Bulk(Some(k.clone().into_bytes())))
+                .collect();
+            Reply::Array(vec![Reply::Bulk(Some(b"0".to_vec())), Reply::Array(keys)])
+        }
+        "GETDEL" => match data.get(&key) {
+            Some(Entry { value: Stored::String(_), .. }) => match data.remove(&key) {
+                Some(Entry { value: Stored::String(v), .. }) => Reply::Bulk(Some(v)),
+                _ => Reply::Bulk(None),
+            },
+            Some(_) => Reply::Error("WRONGTYPE".to_string()),
+            None => Reply::Bulk(None),
+        },
+        "DEL" => Reply::Integer(args[1..].iter().filter(|k| data.remove(*k).is_some()).count() as i64),
+        "INCR" | "INCRBY" => {
+            let by = if name == "INCR" { Some(1) } else { args.get(2).and_then(|s| s.parse::<i64>().ok()) };
+            let Some(by) = by else {
+                return Reply::Error("ERR value is not an integer or out of range".to_string());
+            };
+            let entry = data.entry(key).or_insert(Entry {
+                value: Stored::String(b"0".to_vec()),
+                expires_at: None,
+            });
+            let Stored::String(value) = &mut entry.value else {
+                return Reply::Error("WRONGTYPE".to_string());
+            };
+            let next = String::from_utf8_lossy(value).parse::<i64>().unwrap_or(0) + by;
+            *value = next.to_string().into_bytes();
+            Reply::Integer(next)
+        }
+        "TTL" => match data.get(&key) {
+            None => Reply::Integer(-2),
+            Some(Entry { expires_at: None, .. }) => Reply::Integer(-1),
+            Some(Entry { expires_at: Some(at), .. }) => {
+                Reply::Integer(at.saturating_duration_since(Instant::now()).as_secs_f64().ceil() as i64)
+            }
+        },
+        "EXPIRE" => match (data.get_mut(&key), args.get(2).and_then(|s| s.parse::<u64>().ok())) {
+            // NX only sets an expiry on keys that have none
+            (Some(entry), Some(_)) if entry.expires_at.is_some() && args[3..].iter().any(|a| a.eq_ignore_ascii_case("NX")) => {
+                Reply::Integer(0)
+            }
+            (Some(entry), Some(secs)) => {
+                entry.expires_at = Some(Instant::now() + Duration::from_secs(secs));
+                Reply::Integer(1)
+            }
+            _ => Reply::Integer(0),
+        },
+        "XADD" => {
+            let entry = data.entry(key).or_insert(Entry {
+                value: Stored::Stream(0),
+                expires_at: None,
+            });
+            let Stored::Stream(len) = &mut entry.value else {
+                return Reply::Error("WRONGTYPE".to_string());
+            };
+            *len += 1;
+            Reply::Bulk(Some(format!("{}-0", len).into_bytes()))
+        }
+        other => Reply::Error(format!("ERR unknown command '{}'", other)),
+    }
+}
+
+/// Matches a key against a glob pattern, supporting only `*`.
+fn glob_match(pattern: &str, key: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == key,
+        Some((prefix, rest)) => {
+            let Some(tail) = key.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=tail.len()).any(|i| tail.is_char_boundary(i) && glob_match(rest, &tail[i..]))
+        }
+    }
+}
+
+fn encode(reply: &Reply, out: &mut Vec<u8>) {
+    match reply {
+        Reply::Ok => out.extend_from_slice(b"+OK\r\n"),
+        Reply::Status(s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+        Reply::Error(e) => out.extend_from_slice(format!("-{}\r\n", e).as_bytes()),
+        Reply::Integer(i) => out.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+        Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+        Reply::Bulk(Some(v)) => {
+            out.extend_from_slice(format!("${}\r\n", v.len()).as_bytes());
+            out.extend_from_slice(v);
+            out.extend_from_slice(b"\r\n");
+        }
+        Reply::Array(items) => {
+            out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                encode(item, out);
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use actix_web::{web, App, HttpResponse, HttpServer};
+use aws_sdk_sts::config::{BehaviorVersion, Credentials, Region};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Expiration of the issued credentials (2099-01-01T00:00:00Z).
+pub const EXPIRATION: i64 = 4_070_908_800;
+
+/// `AssumeRole` call received by [`FakeSts`].
+#[derive(Debug, Clone)]
+pub struct AssumeRoleCall {
+    pub role_arn: String,
+    pub role_session_name: String,
+    pub external_id: Option<String>,
+}
+
+/// Local stand-in for AWS STS, answering `AssumeRole` with random credentials.
+pub struct FakeSts {
+    base_url: String,
+    state: Arc<StsState>,
+}
+
+#[derive(Default)]
+struct StsState {
+    calls: Mutex<Vec<AssumeRoleCall>>,
+    deny: AtomicBool,
+}
+
+impl FakeSts {
+    /// Starts the endpoint on a random local port.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fake sts");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(StsState::default());
+
+        let data = web::Data::from(state.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .default_service(web::post().to(assume_role))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("listen fake sts")
+        .run();
+        actix_web::rt::spawn(server);
+
+        Self { base_url, state }
+    }
+
+    /// STS client sending its requests to this endpoint.
+    pub fn client(&self) -> aws_sdk_sts::Client {
+        let config = aws_sdk_sts::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(&self.base_url)
+            .credentials_provider(Credentials::new("test", "test", None, None, "fake-sts"))
+            .build();
+        aws_sdk_sts::Client::from_conf(config)
+    }
+
+    /// Makes `AssumeRole` fail with `AccessDenied` (or succeed again).
+    pub fn set_deny(&self, deny: bool) {
+        self.state.deny.store(deny, Ordering::SeqCst);
+    }
+
+    /// `AssumeRole` calls received so far.
+    pub fn calls(&self) -> Vec<AssumeRoleCall> {
+        self.state.calls.lock().unwrap().clone()
+    }
+}
+
+async fn assume_role(state: web::Data<StsState>, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    if form.get("Action").map(String::as_str) != Some("AssumeRole") {
+        return HttpResponse::BadRequest().finish();
+    }
+    state.calls.lock().unwrap().push(AssumeRoleCall {
+        role_arn: form.get("RoleArn").cloned().unwrap_or_default(),
+        role_session_name: form.get("RoleSessionName").cloned().unwrap_or_default(),
+        external_id: form.get("ExternalId").cloned(),
+    });
+
+    if state.deny.load(Ordering::SeqCst) {
+        return HttpResponse::Forbidden().content_type("text/xml").body(
+            r#"<ErrorResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <Error><Type>Sender</Type><Code>AccessDenied</Code><Message>Not authorized to perform sts:AssumeRole</Message></Error>
+  <RequestId>fake</RequestId>
+</ErrorResponse>"#,
+        );
+    }
+
+    let id = Uuid::new_v4().simple().to_string().to_uppercase();
+    HttpResponse::Ok().content_type("text/xml").body(format!(
+        r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ASIA{}</AccessKeyId>
+      <SecretAccessKey>secret-{}</SecretAccessKey>
+      <SessionToken>token-{}</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+    <AssumedRoleUser>
+      <AssumedRoleId>AROAFAKE:{}</AssumedRoleId>
+      <Arn>{}</Arn>
+    </AssumedRoleUser>
+  </AssumeRoleResult>
+  <ResponseMetadata><RequestId>fake</RequestId></ResponseMetadata>
+</AssumeRoleResponse>"#,
+        &id[..16],
+        id,
+        id,
+        form.get("RoleSessionName").cloned().unwrap_or_default(),
+        form.get("RoleArn").cloned().unwrap_or_default(),
+    ))
+}
@@ -0,0 +1,359 @@
+use crate::app::{app, AppData};
+use crate::audit::{AuditEvent, AuditLog, AuditSink};
+use crate::config::{AppArgs, SharedConfig};
+use crate::db;
+use crate::id_token::IdTokenVerifier;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+use crate::store::{MemoryStore, RedisStore, SessionStore, SqlSessionStore, StateStore};
+use crate::pages::Pages;
+use crate::sts::CredentialCache;
+use crate::tests::fake_oidc::FakeOidc;
+use crate::tests::fake_redis::FakeRedis;
+use crate::tests::fake_sts::FakeSts;
+use actix_web::dev::ServerHandle;
+use actix_web::{web, HttpServer};
+use async_trait::async_trait;
+use clap::Parser;
+use reqwest::header::LOCATION;
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Client ID the fake identity provider issues tokens for.
+pub const CLIENT_ID: &str = "test-client";
+/// Role assumed through the fake STS.
+pub const ROLE_ARN: &str = "arn:aws:iam::123456789012:role/cli";
+
+/// Backend of the state and session stores under test.
+#[derive(Debug, Clone, Copy)]
+pub enum Backend {
+    /// The Redis stores, talking to [`FakeRedis`].
+    Redis,
+    /// The in-memory stores.
+    Memory,
+    /// In-memory states and SQL sessions, in a temporary SQLite database.
+    Sql,
+}
+
+/// Audit sink keeping the events in memory.
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<AuditEvent>>,
+}
+
+#[async_trait]
+impl AuditSink for RecordingSink {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    async fn write(&self, event: &AuditEvent) -> Result<(), String> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+}
+
+/// The service listening on a random local port, wired to fake Cognito, STS and Redis.
+pub struct TestEnv {
+    pub url: String,
+    pub oidc: FakeOidc,
+    pub sts: FakeSts,
+    pub redis: FakeRedis,
+    pub http: reqwest::Client,
+    /// Configuration the service was started with.
+    pub args: AppArgs,
+    /// Live configuration of the service, which tests can swap like a reload does.
+    pub config: Arc<SharedConfig>,
+    pub states: Arc<dyn StateStore>,
+    pub sessions: Arc<dyn SessionStore>,
+    audit: Arc<RecordingSink>,
+    /// Shutdown state of the service.
+    pub shutdown: Arc<Shutdown>,
+    /// Handle stopping the service.
+    pub server: ServerHandle,
+    /// SQLite database of the `Sql` backend, deleted when the environment is dropped.
+    sqlite_path: Option<PathBuf>,
+}
+
+impl TestEnv {
+    /// Starts the fakes and the service with the given backend and extra command line arguments.
+    pub async fn start(backend: Backend, extra_args: &[&str]) -> Self {
+        let oidc = FakeOidc::start(CLIENT_ID);
+        let sts = FakeSts::start();
+        let redis = FakeRedis::start();
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind service");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let redirect_uri = format!("{}/auth/cli/callback", url);
+
+        let mut argv = vec![
+            "mega-uploader-auth",
+            "--url",
+            &redis.url(),
+            "--domain",
+            oidc.url(),
+            "--cognito-issuer-url",
+            oidc.url(),
+            "--client-id",
+            CLIENT_ID,
+            "--redirect-uri",
+            &redirect_uri,
+            "--user-pool-id",
+            "us-east-1_test",
+            "--region",
+            "us-east-1",
+            "--role-arn",
+            ROLE_ARN,
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+        // Tests poll as fast as they like unless they set an interval
+        if !extra_args.contains(&"--poll-interval") {
+            argv.extend(["--poll-interval".to_string(), "0".to_string()]);
+        }
+        argv.extend(extra_args.iter().map(|a| a.to_string()));
+        let args = AppArgs::try_parse_from(argv).expect("test arguments");
+        let errors = args.validate();
+        assert!(errors.is_empty(), "invalid test configuration: {:?}", errors);
+
+        let redis_pool = db::create_pool(&args.redis).await.expect("redis pool");
+        let mut sqlite_path = None;
+        let (states, sessions): (Arc<dyn StateStore>, Arc<dyn SessionStore>) = match backend {
+            Backend::Redis => {
+                let store = Arc::new(RedisStore::new(redis_pool.clone()));
+                (store.clone(), store)
+            }
+            Backend::Memory => {
+                let store = Arc::new(MemoryStore::new());
+                (store.clone(), store)
+            }
+            Backend::Sql => {
+                let path = std::env::temp_dir().join(format!("mega-uploader-auth-{}.db", Uuid::new_v4()));
+                let url = format!("sqlite://{}?mode=rwc", path.display());
+                let sessions = SqlSessionStore::connect(&url).await.expect("sqlite session store");
+                sqlite_path = Some(path);
+                (Arc::new(MemoryStore::new()), Arc::new(sessions))
+            }
+        };
+
+        let metrics = Arc::new(Metrics::new().expect("metrics"));
+        let verifier = Arc::new(IdTokenVerifier::new(&args, redis_pool.clone(), metrics.clone()));
+        let audit = Arc::new(RecordingSink::default());
+        let config = Arc::new(SharedConfig::from_pointee(args.clone()));
+        let shutdown = Arc::new(Shutdown::default());
+
+        let app_data = AppData {
+            redis_pool: web::Data::new(redis_pool),
+            state_store: web::Data::from(states.clone()),
+            session_store: web::Data::from(sessions.clone()),
+            config: web::Data::from(config.clone()),
+            sts_client: web::Data::new(sts.client()),
+            credential_cache: web::Data::new(CredentialCache::new(None).expect("credential cache")),
+            pages: web::Data::new(Pages::load(args.pages.templates_dir.as_deref()).expect("page templates")),
+            audit_log: web::Data::new(AuditLog::new(vec![audit.clone()])),
+            metrics: web::Data::from(metrics),
+            id_token_verifier: web::Data::from(verifier),
+            shutdown: web::Data::from(shutdown.clone()),
+        };
+
+        let server = HttpServer::new(move || app(app_data.clone()))
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .expect("listen service")
+            .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        Self {
+            url,
+            oidc,
+            sts,
+            redis,
+            http,
+            args,
+            config,
+            states,
+            sessions,
+            audit,
+            shutdown,
+            server: handle,
+            sqlite_path,
+        }
+    }
+
+    /// Value of a metric line in the Prometheus exposition of the service.
+    pub async fn metric(&self, line_prefix: &str) -> Option<f64> {
+        let body = self
+            .http
+            .get(format!("{}/metrics", self.url))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        body.lines()
+            .find(|l| l.starts_with(line_prefix))
+            .and_then(|l| l.rsplit(' ').next())
+            .and_then(|v| v.parse().ok())
+    }
+
+    /// Audit log writing to the same recording sink as the service.
+    pub fn audit_log(&self) -> AuditLog {
+        AuditLog::new(vec![self.audit.clone()])
+    }
+
+    /// Audit events recorded so far, as `(action, outcome)` pairs.
+    pub fn audit_trail(&self) -> Vec<(String, String)> {
+        self.audit
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                let event = serde_json::to_value(e).unwrap();
+                (
+                    event["action"].as_str().unwrap_or_default().to_string(),
+                    event["outcome"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    /// `POST /auth/cli/start`, returning the response.
+    pub async fn start_login(&self) -> reqwest::Response {
+        self.http
+            .post(format!("{}/auth/cli/start", self.url))
+            .json(&json!({ "device_name": "laptop", "os": "linux", "cli_version": "1.0.0" }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Starts a login and returns its state and authorization URL.
+    pub async fn begin(&self) -> (String, String) {
+        let res = self.start_login().await;
+        assert_eq!(res.status(), 200);
+        let body: Value = res.json().await.unwrap();
+        let auth_url = body["auth_url"].as_str().unwrap().to_string();
+        let state = reqwest::Url::parse(&auth_url)
+            .unwrap()
+            .query_pairs()
+            .find(|(k, _)| k == "state")
+            .map(|(_, v)| v.into_owned())
+            .unwrap();
+        (state, auth_url)
+    }
+
+    /// Opens the authorization URL like a browser: the fake provider logs the user in and
+    /// redirects to the callback, which is followed. Returns the callback response, the device
+    /// confirmation page when the login succeeded.
+    pub async fn open_login(&self, auth_url: &str) -> reqwest::Response {
+        let res = self.http.get(auth_url).send().await.unwrap();
+        assert_eq!(res.status(), 302, "authorize should redirect to the callback");
+        let callback = res.headers()[LOCATION].to_str().unwrap().to_string();
+        self.http.get(callback).send().await.unwrap()
+    }
+
+    /// Logs the user in like [`Self::open_login`] and confirms the device on the page shown.
+    /// Returns the confirmation response, or the callback response when it shows no page to confirm.
+    pub async fn authorize(&self, auth_url: &str) -> reqwest::Response {
+        let callback = self.open_login(auth_url).await;
+        if callback.status() != 200 {
+            return callback;
+        }
+        let page = callback.text().await.unwrap();
+        let state = form_value(&page, "state").expect("the callback should show the confirmation page");
+        let token = form_value(&page, "token").unwrap();
+        self.confirm(&state, &token, "confirm").await
+    }
+
+    /// `POST /auth/cli/confirm` with the form of the confirmation page.
+    pub async fn confirm(&self, state: &str, token: &str, decision: &str) -> reqwest::Response {
+        self.http
+            .post(format!("{}/auth/cli/confirm", self.url))
+            .form(&[("state", state), ("token", token), ("decision", decision)])
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// `GET /auth/cli/status`, returning the status code and JSON body.
+    pub async fn status(&self, state: &str) -> (u16, Value) {
+        let res = self
+            .http
+            .get(format!("{}/auth/cli/status?state={}", self.url, urlencoding::encode(state)))
+            .send()
+            .await
+            .unwrap();
+        (res.status().as_u16(), res.json().await.unwrap_or(Value::Null))
+    }
+
+    /// `POST /auth/cli/renew`, returning the status code and JSON body.
+    pub async fn renew(&self, refresh_token: &str) -> (u16, Value) {
+        let res = self
+            .http
+            .post(format!("{}/auth/cli/renew", self.url))
+            .json(&json!({ "refresh_token": refresh_token }))
+            .send()
+            .await
+            .unwrap();
+        (res.status().as_u16(), res.json().await.unwrap_or(Value::Null))
+    }
+
+    /// `POST /auth/cli/logout`, returning the status code.
+    pub async fn logout(&self, refresh_token: &str) -> u16 {
+        self.http
+            .post(format!("{}/auth/cli/logout", self.url))
+            .json(&json!({ "refresh_token": refresh_token }))
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    /// Runs a complete login and returns the `AUTHORIZED` status body.
+    pub async fn login(&self) -> Value {
+        let (state, auth_url) = self.begin().await;
+        assert_eq!(self.authorize(&auth_url).await.status(), 200);
+        let (code, body) = self.status(&state).await;
+        assert_eq!(code, 200);
+        assert_eq!(body["status"], "AUTHORIZED", "unexpected status: {}", body);
+        body
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        if let Some(path) = &self.sqlite_path {
+            remove_sqlite_database(path);
+        }
+    }
+}
+
+/// Deletes a SQLite database, with the write-ahead log and shared memory files kept next to it.
+pub fn remove_sqlite_database(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+/// Value of the hidden input `name` of an HTML form.
+pub fn form_value(page: &str, name: &str) -> Option<String> {
+    let start = page.find(&format!("name=\"{}\" value=\"", name))? + name.len() + 15;
+    let len = page[start..].find('"')?;
+    Some(page[start..start + len].to_string())
+}
@@ -0,0 +1,27 @@
+use crate::handlers::redirect::{redirect_to_https, HttpsPort};
+use actix_web::http::header::{HOST, LOCATION};
+use actix_web::{test, web, App};
+
+#[actix_web::test]
+async fn plain_http_is_redirected_to_the_requested_host() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(HttpsPort(8443)))
+            .default_service(web::to(redirect_to_https)),
+    )
+    .await;
+
+    // Forwarding headers cannot point the redirect at another host
+    let req = test::TestRequest::post()
+        .uri("/auth/cli/start?x=1")
+        .insert_header((HOST, "auth.example.com:8080"))
+        .insert_header(("X-Forwarded-Host", "evil.example.com"))
+        .insert_header(("Forwarded", "host=evil.example.com"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 308);
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "https://auth.example.com:8443/auth/cli/start?x=1");
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
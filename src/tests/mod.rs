@@ -0,0 +1,21 @@
+//! End-to-end tests of the authentication flow.
+//!
+//! Each test starts the service on a random local port together with fake Cognito, STS and
+//! Redis servers, so the whole flow runs offline.
+
+mod admin;
+mod auth_flow;
+mod callback_pages;
+mod cli_version;
+mod client;
+mod container_credentials;
+mod credential_formats;
+mod device_confirmation;
+mod fake_oidc;
+mod fake_redis;
+mod fake_sts;
+mod harness;
+mod https_redirect;
+mod readiness;
+mod shutdown;
+mod sts_cache;
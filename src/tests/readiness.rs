@@ -0,0 +1,66 @@
+use crate::tests::fake_oidc::OidcFaults;
+use crate::tests::harness::{Backend, TestEnv};
+use serde_json::Value;
+
+/// Status code and body of the readiness probe.
+async fn readyz(env: &TestEnv) -> (u16, Value) {
+    let res = env.http.get(format!("{}/readyz", env.url)).send().await.unwrap();
+    (res.status().as_u16(), res.json().await.unwrap())
+}
+
+#[actix_web::test]
+async fn ready_when_every_dependency_is_up() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let (code, body) = readyz(&env).await;
+
+    assert_eq!(code, 200, "{}", body);
+    assert_eq!(body["status"], "up");
+    for check in ["redis", "jwks", "sts"] {
+        assert_eq!(body["checks"][check]["status"], "up", "{}", check);
+    }
+}
+
+#[actix_web::test]
+async fn not_ready_when_the_store_is_down() {
+    let env = TestEnv::start(Backend::Redis, &["--redis-connect-timeout", "100"]).await;
+    // Load the keys first, so that only the store check fails
+    assert_eq!(readyz(&env).await.0, 200);
+    env.redis.set_down(true);
+
+    let (code, body) = readyz(&env).await;
+    assert_eq!(code, 503, "{}", body);
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["redis"]["status"], "down");
+    assert!(body["checks"]["redis"]["error"].is_string());
+    assert_eq!(body["checks"]["jwks"]["status"], "up");
+}
+
+#[actix_web::test]
+async fn not_ready_without_keys() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    env.oidc.set_faults(OidcFaults {
+        jwks_status: Some(503),
+        ..Default::default()
+    });
+
+    let (code, body) = readyz(&env).await;
+    assert_eq!(code, 503, "{}", body);
+    assert_eq!(body["checks"]["jwks"]["status"], "down");
+    assert_eq!(body["checks"]["memory"]["status"], "up");
+
+    // Ready again as soon as the keys can be downloaded
+    env.oidc.set_faults(OidcFaults::default());
+    assert_eq!(readyz(&env).await.0, 200);
+}
+
+#[actix_web::test]
+async fn not_ready_once_draining() {
+    let env = TestEnv::start(Backend::Memory, &[]).await;
+    assert_eq!(readyz(&env).await.0, 200);
+    env.shutdown.begin();
+
+    let (code, body) = readyz(&env).await;
+    assert_eq!(code, 503, "{}", body);
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["shutdown"]["error"], "Server is shutting down");
+}
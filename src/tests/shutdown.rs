@@ -0,0 +1,42 @@
+use crate::shutdown;
+use crate::tests::fake_oidc::OidcFaults;
+use crate::tests::harness::{Backend, TestEnv};
+use actix_web::rt::time::sleep;
+use std::time::Duration;
+
+#[actix_web::test]
+async fn shutdown_drains_in_flight_requests() {
+    let env = TestEnv::start(Backend::Memory, &["--shutdown-readiness-delay", "1"]).await;
+    let login = env.login().await;
+    env.oidc.set_faults(OidcFaults {
+        token_delay: Some(Duration::from_millis(1500)),
+        ..Default::default()
+    });
+
+    let renewal = env.renew(login["refresh_token"].as_str().unwrap());
+    let shutting_down = async {
+        // Let the renewal reach the token endpoint before the shutdown starts
+        sleep(Duration::from_millis(200)).await;
+        let drain = shutdown::drain(vec![env.server.clone()], &env.shutdown, &env.args.shutdown, "test");
+        let while_draining = async {
+            sleep(Duration::from_millis(100)).await;
+            let readyz = env.http.get(format!("{}/readyz", env.url)).send().await.unwrap();
+            let start = env.start_login().await;
+            let start_body: serde_json::Value = start.json().await.unwrap_or_default();
+            (readyz.status().as_u16(), start_body["code"].clone())
+        };
+        tokio::join!(drain, while_draining).1
+    };
+    let ((code, renewed), (readyz, start)) = tokio::join!(renewal, shutting_down);
+
+    // The renewal in flight when the server stopped was answered
+    assert_eq!(code, 200, "{}", renewed);
+    assert!(renewed["access_key_id"].is_string(), "{}", renewed);
+
+    // Readiness failed and logins were refused while draining
+    assert_eq!(readyz, 503);
+    assert_eq!(start, "shutting_down");
+
+    // The listener is closed once drained
+    assert!(env.http.get(format!("{}/healthz", env.url)).send().await.is_err());
+}
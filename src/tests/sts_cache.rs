@@ -0,0 +1,94 @@
+use crate::tests::harness::{Backend, TestEnv};
+use std::sync::Arc;
+
+/// Keys of the credentials cached for the test user.
+fn cached_keys(env: &TestEnv) -> Vec<String> {
+    env.redis
+        .keys()
+        .into_iter()
+        .filter(|k| k.starts_with("auth:sts:user-1:"))
+        .collect()
+}
+
+#[actix_web::test]
+async fn renew_reuses_cached_credentials() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let login = env.login().await;
+    let (_, renewed) = env.renew(login["refresh_token"].as_str().unwrap()).await;
+
+    assert_eq!(renewed["access_key_id"], login["access_key_id"]);
+    assert_eq!(renewed["session_token"], login["session_token"]);
+    assert_eq!(env.sts.calls().len(), 1);
+
+    assert_eq!(
+        env.metric("mega_auth_sts_credential_cache_lookups_total{result=\"hit\"}").await,
+        Some(1.0)
+    );
+    assert_eq!(env.metric("mega_auth_sts_credential_cache_hit_ratio").await, Some(0.5));
+}
+
+#[actix_web::test]
+async fn cached_credentials_are_encrypted() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let login = env.login().await;
+
+    let keys = cached_keys(&env);
+    assert_eq!(keys.len(), 1);
+    let sealed = env.redis.get(&keys[0]).expect("cached credentials");
+    for field in ["access_key_id", "secret_access_key", "session_token"] {
+        assert!(!sealed.contains(login[field].as_str().unwrap()), "{} stored in clear", field);
+    }
+}
+
+#[actix_web::test]
+async fn credentials_without_enough_lifetime_are_not_reused() {
+    // The fake STS issues credentials expiring in 2099, short of the requested lifetime
+    let env = TestEnv::start(Backend::Memory, &["--sts-cache-min-lifetime", "9000000000"]).await;
+    let login = env.login().await;
+    let (_, renewed) = env.renew(login["refresh_token"].as_str().unwrap()).await;
+
+    assert_ne!(renewed["access_key_id"], login["access_key_id"]);
+    assert_eq!(env.sts.calls().len(), 2);
+}
+
+#[actix_web::test]
+async fn cache_can_be_disabled() {
+    let env = TestEnv::start(Backend::Redis, &["--sts-cache-min-lifetime", "0"]).await;
+    let login = env.login().await;
+    env.renew(login["refresh_token"].as_str().unwrap()).await;
+
+    assert_eq!(env.sts.calls().len(), 2);
+    assert!(cached_keys(&env).is_empty());
+}
+
+#[actix_web::test]
+async fn new_logins_do_not_reuse_cached_credentials() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let first = env.login().await;
+    let second = env.login().await;
+
+    assert_ne!(second["access_key_id"], first["access_key_id"]);
+    assert_eq!(env.sts.calls().len(), 2);
+}
+
+#[actix_web::test]
+async fn reloaded_roles_do_not_reuse_cached_credentials() {
+    let env = TestEnv::start(Backend::Redis, &[]).await;
+    let login = env.login().await;
+
+    let mut args = env.args.clone();
+    args.sts.role_arn = "arn:aws:iam::123456789012:role/other".to_string();
+    env.config.store(Arc::new(args.clone()));
+    let (_, renewed) = env.renew(login["refresh_token"].as_str().unwrap()).await;
+    assert_ne!(renewed["access_key_id"], login["access_key_id"]);
+
+    args.sts.external_id = Some("tenant-1".to_string());
+    env.config.store(Arc::new(args));
+    let (_, renewed_again) = env.renew(login["refresh_token"].as_str().unwrap()).await;
+    assert_ne!(renewed_again["access_key_id"], renewed["access_key_id"]);
+
+    let calls = env.sts.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[2].role_arn, "arn:aws:iam::123456789012:role/other");
+    assert_eq!(calls[2].external_id.as_deref(), Some("tenant-1"));
+}
@@ -0,0 +1,131 @@
+use crate::config::{ServerConfig, TlsVersion};
+use crate::utils::watch::watch_files;
+use arc_swap::ArcSwap;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::SupportedProtocolVersion;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Time to wait for a burst of file events to settle before reloading the certificate.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Certificate resolver serving the last certificate that was loaded successfully.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: ArcSwap<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load_full())
+    }
+}
+
+impl ReloadingCertResolver {
+    /// Loads the certificate and key, keeping the files' paths for later reloads.
+    fn new(cert_path: &Path, key_path: &Path, provider: Arc<CryptoProvider>) -> Result<Self, String> {
+        let key = load_certified_key(cert_path, key_path, &provider)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            provider,
+            current: ArcSwap::from_pointee(key),
+        })
+    }
+
+    /// Re-reads the certificate and key; the current ones are kept if they cannot be loaded.
+    fn reload(&self) -> Result<(), String> {
+        let key = load_certified_key(&self.cert_path, &self.key_path, &self.provider)?;
+        self.current.store(Arc::new(key));
+        Ok(())
+    }
+}
+
+/// Builds the rustls configuration of the HTTPS listener.
+///
+/// The returned resolver serves the certificate and can reload it; see [`spawn_cert_reloader`].
+pub fn server_config(
+    config: &ServerConfig,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(rustls::ServerConfig, Arc<ReloadingCertResolver>), String> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let resolver = Arc::new(ReloadingCertResolver::new(cert_path, key_path, provider.clone())?);
+
+    let versions: &[&'static SupportedProtocolVersion] = match config.tls_min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+
+    let server_config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)
+        .map_err(|e| format!("Unsupported TLS configuration: {}", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+
+    Ok((server_config, resolver))
+}
+
+/// Reads a PEM certificate chain and private key and checks that they belong together.
+pub fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, String> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Could not read certificates from {}: {}", cert_path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path.display()));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Could not read private key from {}: {}", key_path.display(), e))?;
+
+    CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))
+}
+
+/// Checks that the configured certificate and key can be loaded, without starting a listener.
+pub fn check_certified_key(cert_path: &Path, key_path: &Path) -> Result<(), String> {
+    load_certified_key(cert_path, key_path, &rustls::crypto::aws_lc_rs::default_provider()).map(|_| ())
+}
+
+/// Reloads the certificate whenever its certificate or key file changes.
+///
+/// Renewed certificates are picked up by new connections without a restart; a certificate
+/// that fails to load is logged and the previous one keeps being served.
+pub fn spawn_cert_reloader(resolver: Arc<ReloadingCertResolver>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+    let watcher = match watch_files(&[&resolver.cert_path, &resolver.key_path], move || {
+        let _ = tx.send(());
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            log::warn!("Could not watch the TLS certificate, reload disabled: {}", e);
+            return;
+        }
+    };
+
+    actix_web::rt::spawn(async move {
+        // Dropping the watcher stops it, so it lives as long as this task
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            actix_web::rt::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            match resolver.reload() {
+                Ok(()) => log::info!("Reloaded TLS certificate from {}", resolver.cert_path.display()),
+                Err(e) => log::error!("TLS certificate reload failed, keeping the current one: {}", e),
+            }
+        }
+    });
+}
@@ -1,3 +1,4 @@
+use crate::openapi::{routes, DOCS_PATH, OPENAPI_PATH};
 use figlet_rs::FIGfont;
 use std::io::Write;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
@@ -28,10 +29,17 @@ pub fn print_banner() {
     writeln!(&mut stdout, "\nAvailable Endpoints:").unwrap();
 
     let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::White)));
-    writeln!(&mut stdout, "  - GET /                 (API Info)").unwrap();
-    writeln!(&mut stdout, "  - POST /auth/cli/start  (CLI Auth Start)").unwrap();
-    writeln!(&mut stdout, "  - GET /auth/cli/status  (CLI Auth Status)").unwrap();
-    writeln!(&mut stdout, "  - POST /auth/cli/renew  (CLI Auth Renew)").unwrap();
+    let routes = routes();
+    let width = routes
+        .iter()
+        .map(|route| route.method.len() + route.path.len() + 1)
+        .max()
+        .unwrap_or(0);
+    for route in &routes {
+        let endpoint = format!("{} {}", route.method, route.path);
+        writeln!(&mut stdout, "  - {:<width$} ({})", endpoint, route.summary).unwrap();
+    }
+    writeln!(&mut stdout, "\nAPI docs: {} (spec at {})", DOCS_PATH, OPENAPI_PATH).unwrap();
 
     let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Magenta)));
     writeln!(&mut stdout, "\nTechnologies: Redis + BB8, AWS STS, Actix Web 4").unwrap();
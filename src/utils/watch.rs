@@ -0,0 +1,48 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Calls `on_change` whenever one of the given files is created, modified or removed.
+///
+/// The directories of the files are watched rather than the files themselves, so that
+/// replaced files (e.g. Kubernetes ConfigMap and Secret updates, which swap a symlink) are
+/// noticed as well as edits in place. Events usually come in bursts; callers should debounce.
+/// The watch stops when the returned watcher is dropped.
+pub fn watch_files<F>(paths: &[&Path], on_change: F) -> notify::Result<RecommendedWatcher>
+where
+    F: Fn() + Send + 'static,
+{
+    let names: BTreeSet<OsString> = paths
+        .iter()
+        .filter_map(|path| path.file_name().map(|name| name.to_os_string()))
+        .collect();
+    let dirs: BTreeSet<PathBuf> = paths
+        .iter()
+        .map(|path| match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+
+    let handler = move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        let relevant_kind = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        );
+        let relevant_path = event.paths.iter().any(|p| {
+            p.file_name()
+                .is_some_and(|name| names.contains(name) || name.to_string_lossy().starts_with(".."))
+        });
+        if relevant_kind && relevant_path {
+            on_change();
+        }
+    };
+
+    let mut watcher = notify::recommended_watcher(handler)?;
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}